@@ -0,0 +1,7 @@
+-- Add a full-text search vector over marking names and definitions
+ALTER TABLE markings ADD COLUMN search tsvector
+    GENERATED ALWAYS AS (
+        setweight(to_tsvector('english', name), 'A') ||
+        setweight(to_tsvector('english', definition), 'B')
+    ) STORED;
+CREATE INDEX markings_search_idx ON markings USING GIN (search);
@@ -0,0 +1,2 @@
+-- Track a version per marking for optimistic concurrency control
+ALTER TABLE markings ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
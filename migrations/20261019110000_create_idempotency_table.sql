@@ -0,0 +1,13 @@
+-- Create Idempotency Table
+CREATE TABLE idempotency(
+    scope TEXT NOT NULL,
+    client TEXT NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    request_fingerprint TEXT NOT NULL,
+    response_status_code SMALLINT,
+    response_headers TEXT,
+    response_body BYTEA,
+    created_at timestamptz NOT NULL,
+    locked_until timestamptz NOT NULL,
+    PRIMARY KEY (scope, client, idempotency_key)
+);
@@ -0,0 +1,14 @@
+-- Record where markings mirrored from a TAXII collection come from
+ALTER TABLE markings ADD COLUMN source_server TEXT;
+ALTER TABLE markings ADD COLUMN source_collection TEXT;
+ALTER TABLE markings ADD COLUMN fetched_at TIMESTAMPTZ;
+
+-- The date the last marking definition pulled from each collection was
+-- added, where the next pull resumes.
+CREATE TABLE taxii_sync_cursors(
+    api_root TEXT NOT NULL,
+    collection TEXT NOT NULL,
+    PRIMARY KEY (api_root, collection),
+    added_last TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL
+);
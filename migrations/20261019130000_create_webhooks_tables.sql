@@ -0,0 +1,36 @@
+-- Create Webhook Tables
+CREATE TABLE webhooks(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    url TEXT NOT NULL,
+    events TEXT[] NOT NULL,
+    secret TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
+
+CREATE TABLE webhook_deliveries(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    webhook_id uuid NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    event_id uuid NOT NULL,
+    event_type TEXT NOT NULL,
+    attempt INTEGER NOT NULL,
+    status_code INTEGER,
+    error TEXT,
+    delivered BOOLEAN NOT NULL,
+    attempted_at timestamptz NOT NULL
+);
+CREATE INDEX webhook_deliveries_webhook_idx ON webhook_deliveries (webhook_id, attempted_at);
+
+CREATE TABLE webhook_dead_letters(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    webhook_id uuid NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    event_id uuid NOT NULL,
+    event_type TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    last_error TEXT,
+    created_at timestamptz NOT NULL
+);
+CREATE INDEX webhook_dead_letters_webhook_idx ON webhook_dead_letters (webhook_id, created_at);
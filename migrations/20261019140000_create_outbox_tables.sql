@@ -0,0 +1,30 @@
+-- Create Outbox Tables
+-- Events are given their sequence once committed, in the order of their ids,
+-- so that no reader ever skips one committed late. Each batch sequenced at
+-- once is added at a later millisecond than the one before.
+CREATE TABLE outbox(
+    id BIGSERIAL NOT NULL,
+    PRIMARY KEY (id),
+    sequence BIGINT,
+    added_at timestamptz,
+    event_id uuid NOT NULL,
+    event_type TEXT NOT NULL,
+    marking_id uuid NOT NULL,
+    payload TEXT NOT NULL,
+    occurred_at timestamptz NOT NULL
+);
+CREATE UNIQUE INDEX outbox_sequence_idx ON outbox (sequence);
+CREATE INDEX outbox_unsequenced_idx ON outbox (id) WHERE sequence IS NULL;
+
+-- The last event each sink published.
+CREATE TABLE outbox_cursors(
+    sink TEXT NOT NULL,
+    PRIMARY KEY (sink),
+    sequence BIGINT NOT NULL,
+    updated_at timestamptz NOT NULL
+);
+
+-- When the current version of each marking was added to the outbox, which
+-- TAXII collections page by.
+ALTER TABLE markings ADD COLUMN date_added timestamptz;
+UPDATE markings SET date_added = date_trunc('milliseconds', COALESCE(updated_at, created_at));
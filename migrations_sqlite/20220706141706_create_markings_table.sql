@@ -0,0 +1,11 @@
+-- Create Markings Table
+CREATE TABLE markings(
+    id BLOB NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    definition_type TEXT NOT NULL,
+    definition TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT,
+    created_by BLOB NOT NULL,
+    updated_by BLOB
+);
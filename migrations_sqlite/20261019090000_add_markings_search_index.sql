@@ -0,0 +1,16 @@
+-- Add a full-text search index over marking names and definitions
+CREATE VIRTUAL TABLE markings_search USING fts5(id UNINDEXED, name, definition);
+
+CREATE TRIGGER markings_search_insert AFTER INSERT ON markings BEGIN
+    INSERT INTO markings_search (id, name, definition)
+    VALUES (new.id, new.name, new.definition);
+END;
+
+CREATE TRIGGER markings_search_update AFTER UPDATE ON markings BEGIN
+    UPDATE markings_search SET name = new.name, definition = new.definition
+    WHERE id = old.id;
+END;
+
+CREATE TRIGGER markings_search_delete AFTER DELETE ON markings BEGIN
+    DELETE FROM markings_search WHERE id = old.id;
+END;
@@ -0,0 +1,13 @@
+-- Create Idempotency Table
+CREATE TABLE idempotency(
+    scope TEXT NOT NULL,
+    client TEXT NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    request_fingerprint TEXT NOT NULL,
+    response_status_code INTEGER,
+    response_headers TEXT,
+    response_body BLOB,
+    created_at TEXT NOT NULL,
+    locked_until TEXT NOT NULL,
+    PRIMARY KEY (scope, client, idempotency_key)
+);
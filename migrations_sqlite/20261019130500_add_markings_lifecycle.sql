@@ -0,0 +1,9 @@
+-- Track where markings are in their lifecycle, and the objects they are assigned to
+ALTER TABLE markings ADD COLUMN status TEXT NOT NULL DEFAULT 'active';
+
+CREATE TABLE marking_assignments(
+    marking_id BLOB NOT NULL REFERENCES markings (id) ON DELETE CASCADE,
+    object_ref TEXT NOT NULL,
+    assigned_at TEXT NOT NULL,
+    PRIMARY KEY (marking_id, object_ref)
+);
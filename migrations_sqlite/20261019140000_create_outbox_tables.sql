@@ -0,0 +1,23 @@
+-- Create Outbox Tables
+CREATE TABLE outbox(
+    sequence INTEGER PRIMARY KEY AUTOINCREMENT,
+    event_id BLOB NOT NULL,
+    event_type TEXT NOT NULL,
+    marking_id BLOB NOT NULL,
+    payload TEXT NOT NULL,
+    occurred_at TEXT NOT NULL,
+    -- Each event is added at a later millisecond than the one before.
+    added_at TEXT NOT NULL
+);
+
+-- The last event each sink published.
+CREATE TABLE outbox_cursors(
+    sink TEXT NOT NULL PRIMARY KEY,
+    sequence INTEGER NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+-- When the current version of each marking was added to the outbox, which
+-- TAXII collections page by.
+ALTER TABLE markings ADD COLUMN date_added TEXT;
+UPDATE markings SET date_added = COALESCE(updated_at, created_at);
@@ -0,0 +1,506 @@
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+
+use uuid::Uuid;
+
+use crate::configuration::Settings;
+use crate::domain::NewMarking;
+use crate::misp::{export_taxonomies, import_taxonomy, Taxonomy};
+use crate::repository::{MarkingRepository, RepositoryError};
+use crate::routes::JsonData;
+use crate::startup::{get_storage, run};
+use crate::taxii_sync::{spawn_sync, SyncError, TaxiiSource};
+
+#[derive(clap::Parser)]
+#[clap(name = "metaman", version, about = "Manages data markings.")]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Starts the HTTP API. This is the default when no command is given.
+    Serve,
+    /// Applies the pending database migrations.
+    Migrate,
+    /// Manages individual markings.
+    #[clap(subcommand)]
+    Marking(MarkingCommand),
+    /// Creates every marking of a bundle, or none of them if one fails.
+    Import {
+        /// Path to a bundle written by `metaman export`.
+        bundle: PathBuf,
+    },
+    /// Writes every marking to standard output as a bundle.
+    Export,
+    /// Exchanges markings with MISP as taxonomies.
+    #[clap(subcommand)]
+    Taxonomy(TaxonomyCommand),
+    /// Pulls the marking definitions of every configured TAXII collection
+    /// once. `serve` pulls them on their own intervals.
+    Sync,
+}
+
+#[derive(clap::Subcommand)]
+pub enum MarkingCommand {
+    /// Creates a marking and prints it.
+    Create {
+        #[clap(long)]
+        name: String,
+        #[clap(long)]
+        definition_type: String,
+        #[clap(long)]
+        definition: String,
+    },
+    /// Prints the id, name and definition type of every marking.
+    List,
+    /// Prints a marking.
+    Show { id: Uuid },
+    /// Deletes a marking.
+    Delete { id: Uuid },
+}
+
+#[derive(clap::Subcommand)]
+pub enum TaxonomyCommand {
+    /// Writes every marking to MISP taxonomies, as
+    /// `<directory>/<namespace>/machinetag.json` like in MISP's own
+    /// repository of taxonomies.
+    Export {
+        /// Namespace of the taxonomy of the markings that are neither TLP
+        /// nor PAP levels, the prefix of their tags.
+        #[clap(long, default_value = "metaman")]
+        namespace: String,
+        /// Directory the taxonomies are written to.
+        directory: PathBuf,
+    },
+    /// Creates a marking for every tag of a MISP taxonomy. Tags whose
+    /// marking already exists are left as they are.
+    Import {
+        /// Path to a MISP `machinetag.json` file.
+        taxonomy: PathBuf,
+    },
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CliError {
+    #[error("{0}")]
+    Invalid(String),
+    #[error(transparent)]
+    Repository(#[from] RepositoryError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Invalid bundle: {0}")]
+    Bundle(#[from] serde_json::Error),
+    #[error(transparent)]
+    Sync(#[from] SyncError),
+}
+
+/// The format shared by `metaman export` and `metaman import`.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Bundle {
+    pub markings: Vec<JsonData>,
+}
+
+impl Command {
+    pub async fn execute(self, configuration: Settings) -> Result<(), CliError> {
+        match self {
+            Command::Serve => serve(configuration).await,
+            Command::Migrate => {
+                let storage = get_storage(&configuration.database).await?;
+                Ok(storage.schema.migrate().await?)
+            }
+            Command::Marking(command) => {
+                let storage = get_storage(&configuration.database).await?;
+                command
+                    .execute(&*storage.markings, &mut std::io::stdout())
+                    .await
+            }
+            Command::Import { bundle } => {
+                let bundle = serde_json::from_slice(&std::fs::read(bundle)?)?;
+                let storage = get_storage(&configuration.database).await?;
+                import(&*storage.markings, bundle, &mut std::io::stdout()).await
+            }
+            Command::Export => {
+                let storage = get_storage(&configuration.database).await?;
+                export(&*storage.markings, &mut std::io::stdout()).await
+            }
+            Command::Taxonomy(TaxonomyCommand::Export {
+                namespace,
+                directory,
+            }) => {
+                let storage = get_storage(&configuration.database).await?;
+                export_misp_taxonomies(
+                    &*storage.markings,
+                    &namespace,
+                    &directory,
+                    &mut std::io::stdout(),
+                )
+                .await
+            }
+            Command::Taxonomy(TaxonomyCommand::Import { taxonomy }) => {
+                let taxonomy = serde_json::from_slice(&std::fs::read(taxonomy)?)
+                    .map_err(|e| CliError::Invalid(format!("Invalid taxonomy: {}", e)))?;
+                let storage = get_storage(&configuration.database).await?;
+                import_misp_taxonomy(&*storage.markings, &taxonomy, &mut std::io::stdout()).await
+            }
+            Command::Sync => {
+                let storage = get_storage(&configuration.database).await?;
+                sync(&configuration, &*storage.markings, &mut std::io::stdout()).await
+            }
+        }
+    }
+}
+
+impl MarkingCommand {
+    pub async fn execute(
+        self,
+        repository: &dyn MarkingRepository,
+        out: &mut impl Write,
+    ) -> Result<(), CliError> {
+        match self {
+            MarkingCommand::Create {
+                name,
+                definition_type,
+                definition,
+            } => {
+                let new_marking = NewMarking::parse(name, definition_type, definition)
+                    .map_err(|e| CliError::Invalid(e.to_string()))?;
+                let marking = repository.insert_marking(&new_marking).await?;
+                serde_json::to_writer_pretty(&mut *out, &marking)?;
+                writeln!(out)?;
+            }
+            MarkingCommand::List => {
+                for marking in repository.list_markings().await? {
+                    writeln!(
+                        out,
+                        "{}\t{}\t{}",
+                        marking.id, marking.name, marking.definition_type
+                    )?;
+                }
+            }
+            MarkingCommand::Show { id } => {
+                let marking = repository.get_marking(id).await?;
+                serde_json::to_writer_pretty(&mut *out, &marking)?;
+                writeln!(out)?;
+            }
+            MarkingCommand::Delete { id } => repository.delete_marking(id, None).await?,
+        }
+        Ok(())
+    }
+}
+
+async fn serve(configuration: Settings) -> Result<(), CliError> {
+    let storage = get_storage(&configuration.database).await?;
+    if configuration.database.migrate_on_startup {
+        storage.schema.migrate().await?;
+    }
+    let address = format!(
+        "{}:{}",
+        configuration.application.host, configuration.application.port
+    );
+    let listener = TcpListener::bind(address)?;
+    spawn_sync(storage.markings.clone(), taxii_sources(&configuration)?);
+    let schema = storage.schema.clone();
+    run(listener, storage, &configuration.application)?.await?;
+    // Requests in flight have been drained, the connections can go.
+    schema.close().await;
+    Ok(())
+}
+
+fn taxii_sources(configuration: &Settings) -> Result<Vec<TaxiiSource>, SyncError> {
+    configuration
+        .taxii_sources
+        .iter()
+        .cloned()
+        .map(TaxiiSource::new)
+        .collect()
+}
+
+async fn sync(
+    configuration: &Settings,
+    repository: &dyn MarkingRepository,
+    out: &mut impl Write,
+) -> Result<(), CliError> {
+    for (settings, source) in configuration
+        .taxii_sources
+        .iter()
+        .zip(taxii_sources(configuration)?)
+    {
+        let report = source.sync(repository).await?;
+        writeln!(
+            out,
+            "{}\t{}\tmirrored {}, skipped {}",
+            settings.api_root, settings.collection, report.mirrored, report.skipped
+        )?;
+    }
+    Ok(())
+}
+
+/// Creates every marking of `bundle` in a single transaction.
+///
+/// The whole bundle is validated before anything is written, so that a typo
+/// in the last marking is reported without a round trip to the database.
+pub async fn import(
+    repository: &dyn MarkingRepository,
+    bundle: Bundle,
+    out: &mut impl Write,
+) -> Result<(), CliError> {
+    let markings = bundle
+        .markings
+        .into_iter()
+        .enumerate()
+        .map(|(index, marking)| {
+            NewMarking::try_from(marking)
+                .map_err(|e| CliError::Invalid(format!("Marking #{}: {}", index + 1, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut transaction = repository.begin().await?;
+    for marking in &markings {
+        if let Err(e) = transaction.insert_marking(marking).await {
+            transaction.rollback().await?;
+            return Err(e.into());
+        }
+    }
+    transaction.commit().await?;
+    writeln!(out, "Imported {} markings.", markings.len())?;
+    Ok(())
+}
+
+/// Writes the taxonomies of every marking under `directory`, one directory
+/// per namespace, and prints their paths.
+pub async fn export_misp_taxonomies(
+    repository: &dyn MarkingRepository,
+    namespace: &str,
+    directory: &Path,
+    out: &mut impl Write,
+) -> Result<(), CliError> {
+    let markings = repository.list_markings().await?;
+    for taxonomy in export_taxonomies(namespace, &markings) {
+        let path = directory.join(&taxonomy.namespace).join("machinetag.json");
+        std::fs::create_dir_all(directory.join(&taxonomy.namespace))?;
+        let mut file = std::fs::File::create(&path)?;
+        serde_json::to_writer_pretty(&mut file, &taxonomy)?;
+        writeln!(file)?;
+        writeln!(out, "{}", path.display())?;
+    }
+    Ok(())
+}
+
+/// Creates the markings of every tag of `taxonomy` that has none yet, in a
+/// single transaction, after listing the expanded forms that could not be
+/// used as definitions.
+pub async fn import_misp_taxonomy(
+    repository: &dyn MarkingRepository,
+    taxonomy: &Taxonomy,
+    out: &mut impl Write,
+) -> Result<(), CliError> {
+    let imported = import_taxonomy(taxonomy).map_err(CliError::Invalid)?;
+    for warning in &imported.warnings {
+        writeln!(out, "{}", warning)?;
+    }
+    let markings = imported.markings;
+    let existing: Vec<_> = repository
+        .list_markings()
+        .await?
+        .into_iter()
+        .map(|marking| marking.name)
+        .collect();
+    let (present, new): (Vec<_>, Vec<_>) = markings
+        .iter()
+        .partition(|marking| existing.iter().any(|name| name == marking.name.as_ref()));
+
+    let mut transaction = repository.begin().await?;
+    for marking in &new {
+        if let Err(e) = transaction.insert_marking(marking).await {
+            transaction.rollback().await?;
+            return Err(e.into());
+        }
+    }
+    transaction.commit().await?;
+    writeln!(
+        out,
+        "Imported {} markings, {} already existed.",
+        new.len(),
+        present.len()
+    )?;
+    Ok(())
+}
+
+pub async fn export(
+    repository: &dyn MarkingRepository,
+    out: &mut impl Write,
+) -> Result<(), CliError> {
+    let bundle = Bundle {
+        markings: repository
+            .list_markings()
+            .await?
+            .into_iter()
+            .map(JsonData::from)
+            .collect(),
+    };
+    serde_json::to_writer_pretty(&mut *out, &bundle)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::{
+        export, export_misp_taxonomies, import, import_misp_taxonomy, Bundle, CliError,
+        MarkingCommand,
+    };
+    use crate::misp::{export_taxonomies, Taxonomy};
+    use crate::repository::{InMemoryMarkingRepository, MarkingRepository};
+    use claim::{assert_err, assert_ok};
+
+    fn bundle(names: &[&str]) -> Bundle {
+        let markings = names
+            .iter()
+            .map(|name| {
+                serde_json::json!({
+                    "name": name,
+                    "definition_type": "statement",
+                    "definition": format!("Copyright {}", name)
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::from_value(serde_json::json!({ "markings": markings })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn an_exported_bundle_can_be_imported_elsewhere() {
+        let source = InMemoryMarkingRepository::new();
+        assert_ok!(import(&source, bundle(&["acme", "globex"]), &mut Vec::new()).await);
+        let mut exported = Vec::new();
+        assert_ok!(export(&source, &mut exported).await);
+
+        let target = InMemoryMarkingRepository::new();
+        let bundle: Bundle = serde_json::from_slice(&exported).unwrap();
+        assert_ok!(import(&target, bundle, &mut Vec::new()).await);
+
+        let names = |markings: Vec<crate::domain::Marking>| {
+            let mut names: Vec<_> = markings.into_iter().map(|m| m.name).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            names(source.list_markings().await.unwrap()),
+            names(target.list_markings().await.unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn a_bundle_with_an_invalid_marking_imports_nothing() {
+        let repository = InMemoryMarkingRepository::new();
+
+        let result = import(&repository, bundle(&["acme", "Not Valid"]), &mut Vec::new()).await;
+
+        assert!(matches!(result, Err(CliError::Invalid(_))));
+        assert!(repository.list_markings().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_bundle_with_a_duplicate_name_imports_nothing() {
+        let repository = InMemoryMarkingRepository::new();
+
+        assert_err!(import(&repository, bundle(&["acme", "acme"]), &mut Vec::new()).await);
+        assert!(repository.list_markings().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn marking_create_rejects_invalid_names() {
+        let repository = InMemoryMarkingRepository::new();
+        let command = MarkingCommand::Create {
+            name: "".into(),
+            definition_type: "statement".into(),
+            definition: "Copyright Acme".into(),
+        };
+
+        assert_err!(command.execute(&repository, &mut Vec::new()).await);
+    }
+
+    #[tokio::test]
+    async fn a_misp_taxonomy_is_imported_once() {
+        let source = InMemoryMarkingRepository::new();
+        assert_ok!(import(&source, bundle(&["acme", "globex"]), &mut Vec::new()).await);
+        let taxonomy =
+            export_taxonomies("metaman", &source.list_markings().await.unwrap()).remove(0);
+        let target = InMemoryMarkingRepository::new();
+        assert_ok!(import_misp_taxonomy(&target, &taxonomy, &mut Vec::new()).await);
+
+        let mut out = Vec::new();
+        assert_ok!(import_misp_taxonomy(&target, &taxonomy, &mut out).await);
+
+        assert_eq!(
+            "Imported 0 markings, 2 already existed.\n",
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!(2, target.list_markings().await.unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn exported_taxonomies_match_the_markings_they_came_from() {
+        let repository = InMemoryMarkingRepository::new();
+        let bundle = serde_json::from_value(serde_json::json!({
+            "markings": [
+                { "name": "tlp_amber", "definition_type": "tlp", "definition": "TLP Amber" },
+                { "name": "pap_green", "definition_type": "statement", "definition": "PAP Green" },
+                { "name": "acme", "definition_type": "statement", "definition": "Acme only" }
+            ]
+        }))
+        .unwrap();
+        assert_ok!(import(&repository, bundle, &mut Vec::new()).await);
+        let taxonomies = export_taxonomies("metaman", &repository.list_markings().await.unwrap());
+        assert_eq!(3, taxonomies.len());
+
+        for taxonomy in &taxonomies {
+            let mut out = Vec::new();
+            assert_ok!(import_misp_taxonomy(&repository, taxonomy, &mut out).await);
+            assert_eq!(
+                "Imported 0 markings, 1 already existed.\n",
+                String::from_utf8(out).unwrap()
+            );
+        }
+        assert_eq!(3, repository.list_markings().await.unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn taxonomies_are_exported_one_directory_per_namespace() {
+        let repository = InMemoryMarkingRepository::new();
+        assert_ok!(import(&repository, bundle(&["acme"]), &mut Vec::new()).await);
+        let directory = std::env::temp_dir().join(format!("metaman-{}", uuid::Uuid::new_v4()));
+        let mut out = Vec::new();
+
+        assert_ok!(export_misp_taxonomies(&repository, "metaman", &directory, &mut out).await);
+
+        let path = directory.join("metaman").join("machinetag.json");
+        assert_eq!(
+            format!("{}\n", path.display()),
+            String::from_utf8(out).unwrap()
+        );
+        let taxonomy: Taxonomy = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!("acme", taxonomy.values[0].entry[0].value);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn expanded_forms_that_cannot_be_definitions_are_reported() {
+        let taxonomy: Taxonomy = serde_json::from_value(serde_json::json!({
+            "namespace": "tlp",
+            "predicates": [{ "value": "amber", "expanded": "(TLP:AMBER) Limited disclosure." }]
+        }))
+        .unwrap();
+        let mut out = Vec::new();
+
+        assert_ok!(
+            import_misp_taxonomy(&InMemoryMarkingRepository::new(), &taxonomy, &mut out).await
+        );
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("tlp:amber: dropped its expanded form"));
+        assert!(out.ends_with("Imported 1 markings, 0 already existed.\n"));
+    }
+}
@@ -1,12 +1,117 @@
 use secrecy::{ExposeSecret, Secret};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
+use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::ConnectOptions;
+use std::collections::HashMap;
 
 #[derive(serde::Deserialize)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
+    #[serde(default)]
+    pub telemetry: TelemetrySettings,
+    /// TAXII collections whose marking definitions are mirrored.
+    #[serde(default)]
+    pub taxii_sources: Vec<TaxiiSourceSettings>,
+}
+
+/// A partner TAXII 2.1 collection, polled by `metaman serve`.
+#[derive(serde::Deserialize, Clone)]
+pub struct TaxiiSourceSettings {
+    /// API root of the partner server, such as
+    /// `https://taxii.partner.example.com/api/`.
+    pub api_root: String,
+    /// Id of the collection within the API root.
+    pub collection: String,
+    /// HTTP basic credentials, when the server asks for them.
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<Secret<String>>,
+    /// How often, in seconds, the collection is polled.
+    #[serde(default = "default_taxii_poll_interval")]
+    pub interval: u64,
+}
+
+fn default_taxii_poll_interval() -> u64 {
+    3600
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct TelemetrySettings {
+    /// Default filter directives, overridden by `RUST_LOG` when set.
+    pub level: String,
+    pub format: LogFormat,
+    pub output: LogOutput,
+    pub file: LogFileSettings,
+    /// Where to export traces. Traces are only logged when unset.
+    pub otlp: Option<OtlpSettings>,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            level: "info".into(),
+            format: LogFormat::default(),
+            output: LogOutput::default(),
+            file: LogFileSettings::default(),
+            otlp: None,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Bunyan,
+    Pretty,
+    Compact,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogOutput {
+    #[default]
+    Stdout,
+    File,
+}
+
+/// Where logs go when `output` is `file`.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct LogFileSettings {
+    pub directory: String,
+    /// Name of the log files, suffixed with their date when rotated.
+    pub prefix: String,
+    pub rotation: LogRotation,
+}
+
+impl Default for LogFileSettings {
+    fn default() -> Self {
+        Self {
+            directory: "logs".into(),
+            prefix: "metaman.log".into(),
+            rotation: LogRotation::Daily,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct OtlpSettings {
+    /// Base URL of the collector, `/v1/traces` is appended to it.
+    pub endpoint: String,
 }
 
 #[derive(serde::Deserialize)]
@@ -14,10 +119,231 @@ pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
+    /// Number of worker threads, one per physical core when unset.
+    #[serde(default)]
+    pub workers: Option<usize>,
+    /// How long, in seconds, idle connections are kept open.
+    #[serde(default = "default_keep_alive")]
+    pub keep_alive: u64,
+    /// Largest JSON body accepted, in bytes.
+    #[serde(default = "default_payload_limit")]
+    pub payload_limit: usize,
+    /// How long, in seconds, requests in flight are given to complete once
+    /// the server is asked to stop.
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    /// Serve HTTPS rather than plain HTTP.
+    #[serde(default)]
+    pub tls: Option<TlsSettings>,
+    /// Serve `/admin` and `/webhooks` to clients that did not authenticate
+    /// with a certificate, for deployments where a proxy in front of metaman
+    /// authenticates them instead.
+    #[serde(default)]
+    pub open_operator_routes: bool,
+    /// Cross-origin access for browser clients, denied when unset.
+    #[serde(default)]
+    pub cors: CorsSettings,
+    /// Requests allowed per client, unlimited when unset.
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    /// How marking events are posted to webhooks.
+    #[serde(default)]
+    pub webhooks: WebhookSettings,
+    /// Where marking events are published from the outbox.
+    #[serde(default)]
+    pub outbox: OutboxSettings,
+}
+
+/// Every sink is polled for the events saved after the last one it
+/// published, every `poll_interval_ms` while it is up to date.
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct OutboxSettings {
+    /// Whether this process publishes events. Off by default: with several
+    /// replicas, turn it on for a single one, or sinks get every event once
+    /// per replica.
+    pub enabled: bool,
+    pub sinks: Vec<SinkSettings>,
+    pub poll_interval_ms: u64,
+    /// How long a sink that failed waits before it is offered the event
+    /// again.
+    pub retry_delay_ms: u64,
+    /// Most events read from the outbox at once.
+    pub batch_size: i64,
+    /// How long events are kept once every sink has published them. This is
+    /// also how far back a `GET /events` stream can resume from its
+    /// `Last-Event-ID`. The cursor of a sink no longer configured holds
+    /// events back until it is deleted from `outbox_cursors`.
+    pub retention_hours: u64,
+}
+
+impl Default for OutboxSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sinks: vec![SinkSettings::Webhooks],
+            poll_interval_ms: 500,
+            retry_delay_ms: 5000,
+            batch_size: 100,
+            retention_hours: 168,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum SinkSettings {
+    /// The registered webhooks, see `webhooks`.
+    Webhooks,
+    /// Appends events to `path`, one JSON document per line.
+    File { path: String },
+    /// Prints events, one JSON document per line.
+    Stdout,
+}
+
+/// Failed deliveries are retried after `retry_delay_ms`, then twice as long
+/// after each further failure, until `max_attempts` have been made. The
+/// event is then dead-lettered.
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct WebhookSettings {
+    pub max_attempts: u32,
+    pub retry_delay_ms: u64,
+    /// How long, in seconds, receivers are given to answer.
+    pub timeout: u64,
+    /// Whether webhooks may target loopback, private and link-local
+    /// addresses, such as receivers on this host in tests. Off, so that
+    /// subscribers cannot have this server post to the internal network.
+    pub allow_private_targets: bool,
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        Self {
+            max_attempts: 8,
+            retry_delay_ms: 1000,
+            timeout: 10,
+            allow_private_targets: false,
+        }
+    }
+}
+
+/// Limits for each group of routes. Clients are told by their certificate,
+/// their `X-Api-Key` header when it is one of `api_keys`, or their IP
+/// address, in that order.
+#[derive(serde::Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct RateLimitSettings {
+    /// Requests that only read, such as `GET /markings`.
+    pub reads: Option<RateLimit>,
+    /// Requests that write a single marking.
+    pub writes: Option<RateLimit>,
+    /// Bulk writes to `/markings/bulk`.
+    pub imports: Option<RateLimit>,
+    /// Keys of the clients given buckets of their own. Requests with any
+    /// other key share the bucket of their IP address.
+    pub api_keys: Vec<String>,
+}
+
+/// A token bucket: clients may send `burst` requests at once, then
+/// `per_second` requests each second.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+/// Which browser pages may call the API. CORS is off, and cross-origin
+/// browser requests fail, until `allowed_origins` is set.
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct CorsSettings {
+    /// Origins such as `https://stewards.example.com`, or `*` for any.
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    /// Request headers pages may set, besides the CORS-safelisted ones.
+    pub allowed_headers: Vec<String>,
+    /// Response headers pages may read, besides the CORS-safelisted ones.
+    pub exposed_headers: Vec<String>,
+    /// Let pages send cookies and client certificates. Only allowed with
+    /// explicit origins.
+    pub allow_credentials: bool,
+    /// How long, in seconds, browsers may cache a preflight response.
+    pub max_age: usize,
+}
+
+impl Default for CorsSettings {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "POST", "PUT", "PATCH", "DELETE"]
+                .map(String::from)
+                .to_vec(),
+            allowed_headers: [
+                "Content-Type",
+                "If-Match",
+                "If-None-Match",
+                "Idempotency-Key",
+                "X-Api-Key",
+                "X-Request-Id",
+            ]
+            .map(String::from)
+            .to_vec(),
+            exposed_headers: ["ETag", "Retry-After", "X-Request-Id"]
+                .map(String::from)
+                .to_vec(),
+            allow_credentials: false,
+            max_age: 3600,
+        }
+    }
+}
+
+fn default_keep_alive() -> u64 {
+    5
+}
+
+fn default_payload_limit() -> usize {
+    2 * 1024 * 1024
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct TlsSettings {
+    /// Path to the PEM certificate chain, leaf first.
+    pub certificate: String,
+    /// Path to the PEM private key of the certificate.
+    pub private_key: String,
+    /// How often, in seconds, the certificate and key files are checked for
+    /// changes. A renewed certificate is served without a restart.
+    #[serde(default = "default_tls_reload_interval")]
+    pub reload_interval: u64,
+    /// Require clients to present a certificate.
+    #[serde(default)]
+    pub client_auth: Option<ClientAuthSettings>,
+}
+
+fn default_tls_reload_interval() -> u64 {
+    60
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct ClientAuthSettings {
+    /// Path to the PEM certificates of the authorities issuing client
+    /// certificates.
+    pub ca_certificate: String,
+    /// Names of known clients, by SHA-256 fingerprint of their certificate.
+    /// Other clients are identified by their fingerprint.
+    #[serde(default)]
+    pub identities: HashMap<String, String>,
 }
 
 #[derive(serde::Deserialize)]
 pub struct DatabaseSettings {
+    #[serde(default)]
+    pub kind: DatabaseKind,
     pub username: String,
     pub password: Secret<String>,
     #[serde(deserialize_with = "deserialize_number_from_string")]
@@ -25,6 +351,24 @@ pub struct DatabaseSettings {
     pub host: String,
     pub database_name: String,
     pub require_ssl: bool,
+    #[serde(default = "default_sqlite_path")]
+    pub sqlite_path: String,
+    /// Apply pending migrations before serving requests.
+    #[serde(default)]
+    pub migrate_on_startup: bool,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseKind {
+    #[default]
+    Postgres,
+    Memory,
+    Sqlite,
+}
+
+fn default_sqlite_path() -> String {
+    "metaman.db".into()
 }
 
 impl DatabaseSettings {
@@ -47,6 +391,14 @@ impl DatabaseSettings {
             .port(self.port)
             .ssl_mode(ssl_mode)
     }
+
+    pub fn sqlite(&self) -> SqliteConnectOptions {
+        let mut options = SqliteConnectOptions::new()
+            .filename(&self.sqlite_path)
+            .create_if_missing(true);
+        options.log_statements(tracing::log::LevelFilter::Trace);
+        options
+    }
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
@@ -104,3 +456,58 @@ impl TryFrom<String> for Environment {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{
+        LogFormat, LogOutput, LogRotation, OutboxSettings, TelemetrySettings,
+    };
+
+    fn telemetry(yaml: &str) -> Result<TelemetrySettings, config::ConfigError> {
+        config::Config::builder()
+            .add_source(config::File::from_str(yaml, config::FileFormat::Yaml))
+            .build()?
+            .try_deserialize()
+    }
+
+    #[test]
+    fn logs_default_to_bunyan_on_stdout() {
+        let settings = telemetry("level: debug").unwrap();
+
+        assert_eq!("debug", settings.level);
+        assert_eq!(LogFormat::Bunyan, settings.format);
+        assert_eq!(LogOutput::Stdout, settings.output);
+        assert!(settings.otlp.is_none());
+    }
+
+    #[test]
+    fn log_files_keep_the_defaults_they_are_not_given() {
+        let settings = telemetry(
+            r#"
+            format: compact
+            output: file
+            file:
+              directory: /var/log/metaman
+              rotation: hourly
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(LogFormat::Compact, settings.format);
+        assert_eq!(LogOutput::File, settings.output);
+        assert_eq!("/var/log/metaman", settings.file.directory);
+        assert_eq!("metaman.log", settings.file.prefix);
+        assert_eq!(LogRotation::Hourly, settings.file.rotation);
+    }
+
+    #[test]
+    fn unknown_log_formats_are_rejected() {
+        assert!(telemetry("format: xml").is_err());
+        assert!(telemetry("output: syslog").is_err());
+    }
+
+    #[test]
+    fn replicas_only_publish_events_when_told_to() {
+        assert!(!OutboxSettings::default().enabled);
+    }
+}
@@ -0,0 +1,67 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct Marking {
+    #[schema(value_type = String, format = "uuid")]
+    pub id: Uuid,
+    pub name: String,
+    pub definition_type: String,
+    pub definition: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+    #[schema(value_type = String, format = "uuid")]
+    pub created_by: Uuid,
+    #[schema(value_type = Option<String>, format = "uuid")]
+    pub updated_by: Option<Uuid>,
+    /// Bumped by every write, and served as the ETag of the marking.
+    pub version: i64,
+    /// Where the marking is in its lifecycle, see `MarkingStatus`.
+    pub status: String,
+    /// The TAXII server a mirrored marking was pulled from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_server: Option<String>,
+    /// The collection of `source_server` it was pulled from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_collection: Option<String>,
+    /// When it was last pulled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fetched_at: Option<DateTime<Utc>>,
+    /// When its current version was added to the outbox, to the
+    /// millisecond, as TAXII collections serve it. `None` until then.
+    #[serde(skip)]
+    pub date_added: Option<DateTime<Utc>>,
+}
+
+/// Where a marking is in its lifecycle. Deprecated markings should no longer
+/// be applied, revoked ones no longer hold at all; neither goes back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkingStatus {
+    Active,
+    Deprecated,
+    Revoked,
+}
+
+impl MarkingStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Deprecated => "deprecated",
+            Self::Revoked => "revoked",
+        }
+    }
+
+    /// Whether a marking at status `current` can move to this one: active
+    /// markings can be deprecated, and both can be revoked.
+    pub fn can_follow(&self, current: &str) -> bool {
+        current != self.as_str() && current != Self::Revoked.as_str()
+    }
+}
+
+/// Where a marking mirrored from a partner comes from.
+#[derive(Debug, Clone)]
+pub struct MarkingSource {
+    pub server: String,
+    pub collection: String,
+    pub fetched_at: DateTime<Utc>,
+}
@@ -0,0 +1,74 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// A marking applied to an object kept elsewhere, such as an indicator of
+/// the TIP.
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct MarkingAssignment {
+    #[schema(value_type = String, format = "uuid")]
+    pub marking_id: Uuid,
+    /// The STIX id of the object, such as
+    /// `indicator--8e2e2d2b-17d4-4cbf-938f-98ee46b3cd3f`.
+    pub object_ref: String,
+    pub assigned_at: DateTime<Utc>,
+}
+
+/// The STIX id of an object markings are assigned to: its type, two dashes
+/// and a UUID.
+#[derive(Debug)]
+pub struct ObjectRef(String);
+
+impl ObjectRef {
+    pub fn parse(s: String) -> Result<ObjectRef, String> {
+        let is_valid = match s.split_once("--") {
+            Some((object_type, id)) => {
+                (3..=250).contains(&object_type.len())
+                    && object_type
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+                    && Uuid::parse_str(id).is_ok()
+            }
+            None => false,
+        };
+        if is_valid {
+            Ok(Self(s))
+        } else {
+            Err(format!("{} is not the STIX id of an object.", s))
+        }
+    }
+}
+
+impl AsRef<str> for ObjectRef {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::ObjectRef;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn stix_ids_are_valid() {
+        assert_ok!(ObjectRef::parse(
+            "indicator--8e2e2d2b-17d4-4cbf-938f-98ee46b3cd3f".into()
+        ));
+        assert_ok!(ObjectRef::parse(
+            "x-acme-asset--8e2e2d2b-17d4-4cbf-938f-98ee46b3cd3f".into()
+        ));
+    }
+
+    #[test]
+    fn anything_else_is_rejected() {
+        for object_ref in [
+            "",
+            "indicator",
+            "indicator--42",
+            "Indicator--8e2e2d2b-17d4-4cbf-938f-98ee46b3cd3f",
+            "ip--8e2e2d2b-17d4-4cbf-938f-98ee46b3cd3f",
+        ] {
+            assert_err!(ObjectRef::parse(object_ref.into()), "{}", object_ref);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::domain::{Marking, MarkingAssignment, MarkingStatus};
+
+/// What happened to a marking.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+pub enum MarkingEventType {
+    #[serde(rename = "marking.created")]
+    Created,
+    #[serde(rename = "marking.updated")]
+    Updated,
+    #[serde(rename = "marking.deleted")]
+    Deleted,
+    #[serde(rename = "marking.deprecated")]
+    Deprecated,
+    #[serde(rename = "marking.revoked")]
+    Revoked,
+    #[serde(rename = "marking.assigned")]
+    Assigned,
+}
+
+impl MarkingEventType {
+    pub const ALL: [MarkingEventType; 6] = [
+        Self::Created,
+        Self::Updated,
+        Self::Deleted,
+        Self::Deprecated,
+        Self::Revoked,
+        Self::Assigned,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Created => "marking.created",
+            Self::Updated => "marking.updated",
+            Self::Deleted => "marking.deleted",
+            Self::Deprecated => "marking.deprecated",
+            Self::Revoked => "marking.revoked",
+            Self::Assigned => "marking.assigned",
+        }
+    }
+
+    /// The event of a marking moving to `status`.
+    pub fn of_status(status: MarkingStatus) -> Self {
+        match status {
+            MarkingStatus::Active => Self::Updated,
+            MarkingStatus::Deprecated => Self::Deprecated,
+            MarkingStatus::Revoked => Self::Revoked,
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|event_type| event_type.as_str() == s)
+            .ok_or_else(|| format!("{} is not a marking event.", s))
+    }
+}
+
+/// A change to a marking, as told to subscribers.
+#[derive(Debug, Clone, serde::Serialize, utoipa::ToSchema)]
+pub struct MarkingEvent {
+    /// Unique per event: receivers can use it to drop duplicates.
+    #[schema(value_type = String, format = "uuid")]
+    pub id: Uuid,
+    #[serde(rename = "type")]
+    pub event_type: MarkingEventType,
+    pub occurred_at: DateTime<Utc>,
+    /// The marking after the change, or as it was when it was deleted.
+    pub marking: Marking,
+    /// The object the marking was assigned to, for `marking.assigned`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub object_ref: Option<String>,
+}
+
+impl MarkingEvent {
+    pub fn new(event_type: MarkingEventType, marking: Marking) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            event_type,
+            occurred_at: Utc::now(),
+            marking,
+            object_ref: None,
+        }
+    }
+
+    pub fn assigned(marking: Marking, assignment: &MarkingAssignment) -> Self {
+        Self {
+            object_ref: Some(assignment.object_ref.clone()),
+            ..Self::new(MarkingEventType::Assigned, marking)
+        }
+    }
+}
+
+/// A marking event as saved in the outbox, in the transaction of the write
+/// that caused it.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct OutboxEvent {
+    /// Grows with every event, in the order their writes were committed.
+    pub sequence: i64,
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub marking_id: Uuid,
+    /// The `MarkingEvent`, as JSON.
+    pub payload: String,
+    pub occurred_at: DateTime<Utc>,
+}
@@ -20,6 +20,19 @@ impl MarkingName {
             Ok(Self(s))
         }
     }
+
+    /// Names a marking after a label from another tool, such as `TLP:AMBER`
+    /// for `tlp_amber`: letters are lowercased and every run of other
+    /// characters becomes an underscore.
+    pub fn from_label(label: &str) -> Result<MarkingName, String> {
+        let name = label
+            .to_lowercase()
+            .split(|c: char| !c.is_ascii_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join("_");
+        Self::parse(name).map_err(|_| format!("{} cannot be the name of a marking.", label))
+    }
 }
 
 impl AsRef<str> for MarkingName {
@@ -73,6 +86,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn labels_become_names() {
+        for (label, name) in [
+            ("TLP:AMBER", "tlp_amber"),
+            ("Copyright 2022, Acme", "copyright_acme"),
+        ] {
+            assert_eq!(name, MarkingName::from_label(label).unwrap().as_ref());
+        }
+        assert_err!(MarkingName::from_label("2022"));
+    }
+
     #[test]
     fn a_valid_name_is_parsed_successfully() {
         let name = "this_would_be_a_valid_name".to_string();
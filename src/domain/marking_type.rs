@@ -3,7 +3,12 @@ pub struct MarkingDefinitionType(MarkingType);
 
 impl MarkingDefinitionType {
     pub fn parse(s: String) -> Result<MarkingDefinitionType, String> {
-        Ok(Self(s.try_into()?))
+        s.try_into().map(Self)
+    }
+
+    /// Whether `s` names a supported type.
+    pub fn is_supported(s: &str) -> bool {
+        MarkingType::try_from(s.to_owned()).is_ok()
     }
 }
 
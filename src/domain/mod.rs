@@ -1,9 +1,17 @@
+mod marking;
+mod marking_assignment;
 mod marking_definition;
+mod marking_event;
 mod marking_name;
 mod marking_type;
 mod new_marking;
+mod webhook;
 
+pub use marking::{Marking, MarkingSource, MarkingStatus};
+pub use marking_assignment::{MarkingAssignment, ObjectRef};
 pub use marking_definition::MarkingDefinition;
+pub use marking_event::{MarkingEvent, MarkingEventType, OutboxEvent};
 pub use marking_name::MarkingName;
 pub use marking_type::MarkingDefinitionType;
-pub use new_marking::NewMarking;
+pub use new_marking::{InvalidMarking, NewMarking};
+pub use webhook::{webhook_sink_name, DeadLetter, NewWebhook, Webhook, WebhookDelivery};
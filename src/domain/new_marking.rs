@@ -1,7 +1,57 @@
 use crate::domain::{MarkingDefinition, MarkingDefinitionType, MarkingName};
 
+#[derive(Debug)]
 pub struct NewMarking {
     pub name: MarkingName,
     pub definition_type: MarkingDefinitionType,
     pub definition: MarkingDefinition,
 }
+
+/// Every invalid field of a new marking, with why it is invalid.
+#[derive(Debug)]
+pub struct InvalidMarking(Vec<(&'static str, String)>);
+
+impl InvalidMarking {
+    pub fn fields(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.0.iter().map(|(field, _)| *field)
+    }
+}
+
+impl std::fmt::Display for InvalidMarking {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reasons: Vec<_> = self.0.iter().map(|(_, reason)| reason.as_str()).collect();
+        write!(f, "{}", reasons.join(" "))
+    }
+}
+
+impl NewMarking {
+    /// Parses every field before giving up, so that each invalid one is
+    /// reported rather than only the first.
+    pub fn parse(
+        name: String,
+        definition_type: String,
+        definition: String,
+    ) -> Result<NewMarking, InvalidMarking> {
+        match (
+            MarkingName::parse(name),
+            MarkingDefinitionType::parse(definition_type),
+            MarkingDefinition::parse(definition),
+        ) {
+            (Ok(name), Ok(definition_type), Ok(definition)) => Ok(Self {
+                name,
+                definition_type,
+                definition,
+            }),
+            (name, definition_type, definition) => Err(InvalidMarking(
+                [
+                    ("name", name.err()),
+                    ("definition_type", definition_type.err()),
+                    ("definition", definition.err()),
+                ]
+                .into_iter()
+                .filter_map(|(field, reason)| Some((field, reason?)))
+                .collect(),
+            )),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+use uuid::Uuid;
+
+use crate::domain::MarkingEventType;
+
+#[derive(Debug)]
+pub struct NewWebhook {
+    pub url: String,
+    pub events: Vec<MarkingEventType>,
+    pub secret: Secret<String>,
+}
+
+/// A URL marking events are posted to.
+#[derive(Debug, Clone, serde::Serialize, utoipa::ToSchema)]
+pub struct Webhook {
+    #[schema(value_type = String, format = "uuid")]
+    pub id: Uuid,
+    pub url: String,
+    /// The events posted to `url`.
+    pub events: Vec<MarkingEventType>,
+    /// Key of the signature of every payload, never served back.
+    #[serde(skip)]
+    pub secret: Secret<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Webhook {
+    pub fn subscribes_to(&self, event_type: MarkingEventType) -> bool {
+        self.events.contains(&event_type)
+    }
+}
+
+/// The name of the cursor of the sink of webhook `id`.
+pub fn webhook_sink_name(id: Uuid) -> String {
+    format!("webhook:{}", id)
+}
+
+/// One attempt at posting an event to a webhook.
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct WebhookDelivery {
+    #[schema(value_type = String, format = "uuid")]
+    pub id: Uuid,
+    #[schema(value_type = String, format = "uuid")]
+    pub webhook_id: Uuid,
+    #[schema(value_type = String, format = "uuid")]
+    pub event_id: Uuid,
+    pub event_type: String,
+    /// 1 for the first attempt at an event, 2 for its first retry, and so on.
+    pub attempt: i32,
+    /// The status the receiver answered with, if it answered at all.
+    pub status_code: Option<i32>,
+    /// Why the attempt failed, if it did.
+    pub error: Option<String>,
+    pub delivered: bool,
+    pub attempted_at: DateTime<Utc>,
+}
+
+/// An event a webhook did not accept after every retry.
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct DeadLetter {
+    #[schema(value_type = String, format = "uuid")]
+    pub id: Uuid,
+    #[schema(value_type = String, format = "uuid")]
+    pub webhook_id: Uuid,
+    #[schema(value_type = String, format = "uuid")]
+    pub event_id: Uuid,
+    pub event_type: String,
+    /// The body every attempt posted.
+    #[serde(serialize_with = "serialize_json")]
+    #[schema(value_type = Object)]
+    pub payload: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+fn serialize_json<S: serde::Serializer>(payload: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    use serde::Serialize;
+
+    match serde_json::from_str::<serde_json::Value>(payload) {
+        Ok(value) => value.serialize(serializer),
+        Err(_) => serializer.serialize_str(payload),
+    }
+}
@@ -0,0 +1,161 @@
+use actix_web::body::to_bytes;
+use actix_web::http::StatusCode;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use chrono::{Duration, Utc};
+use sha2::{Digest, Sha256};
+
+use crate::rate_limit::Client;
+use crate::repository::{IdempotencyRepository, SavedResponse};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// How long a key keeps replaying its original response.
+const KEY_LIFETIME_HOURS: i64 = 24;
+/// How long a request holds its key before saving a response. A request
+/// still running after that may see a retry processed alongside it, so this
+/// is well above how long the slowest bulk request takes.
+const LEASE_SECONDS: i64 = 60;
+
+/// A key sent by a client, only ever matched against the keys of that same
+/// client.
+#[derive(Debug)]
+pub struct IdempotencyKey {
+    client: String,
+    key: String,
+}
+
+impl IdempotencyKey {
+    /// Reads the optional `Idempotency-Key` header of a request, sent by the
+    /// client the rate limiter told it apart as.
+    pub fn from_request(request: &HttpRequest) -> Result<Option<Self>, String> {
+        let key = match request.headers().get(IDEMPOTENCY_KEY_HEADER) {
+            None => return Ok(None),
+            Some(value) => value
+                .to_str()
+                .map_err(|_| "The idempotency key must be printable ASCII.".to_string())?,
+        };
+        let client = match request.extensions().get::<Client>() {
+            Some(client) => client.as_ref().to_owned(),
+            None => "unknown".into(),
+        };
+        Self::new(client, key.to_owned()).map(Some)
+    }
+
+    pub fn new(client: String, key: String) -> Result<Self, String> {
+        if key.trim().is_empty() {
+            return Err("The idempotency key cannot be empty.".into());
+        }
+        if key.len() > 255 {
+            return Err("The idempotency key must be shorter than 256 characters.".into());
+        }
+        Ok(Self { client, key })
+    }
+
+    pub fn client(&self) -> &str {
+        &self.client
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.key
+    }
+}
+
+/// Fingerprints a request payload, so that a key reused for a different
+/// request is refused instead of replaying an unrelated response.
+pub fn fingerprint(payload: &impl serde::Serialize) -> String {
+    let payload = serde_json::to_vec(payload).expect("Request payloads are serializable");
+    format!("{:x}", Sha256::digest(payload))
+}
+
+pub enum NextAction {
+    StartProcessing,
+    ReturnSavedResponse(HttpResponse),
+}
+
+pub async fn try_processing(
+    repository: &dyn IdempotencyRepository,
+    scope: &str,
+    key: &IdempotencyKey,
+    fingerprint: &str,
+) -> Result<NextAction, HttpResponse> {
+    let now = Utc::now();
+    let record = repository
+        .claim_idempotency_key(
+            scope,
+            key.client(),
+            key.as_ref(),
+            fingerprint,
+            now - Duration::hours(KEY_LIFETIME_HOURS),
+            now + Duration::seconds(LEASE_SECONDS),
+        )
+        .await
+        .map_err(|_| HttpResponse::InternalServerError().finish())?;
+
+    match record {
+        None => Ok(NextAction::StartProcessing),
+        Some(record) if record.request_fingerprint != fingerprint => {
+            Err(HttpResponse::UnprocessableEntity().finish())
+        }
+        Some(record) => match record.response {
+            Some(response) => Ok(NextAction::ReturnSavedResponse(replay(response))),
+            None => Err(HttpResponse::Conflict().finish()),
+        },
+    }
+}
+
+/// Records the response to the request holding `key` and hands it back.
+///
+/// Server errors are not recorded: the key is released instead so that a
+/// retry gets another chance at succeeding.
+pub async fn save_response(
+    repository: &dyn IdempotencyRepository,
+    scope: &str,
+    key: &IdempotencyKey,
+    response: HttpResponse,
+) -> HttpResponse {
+    if response.status().is_server_error() {
+        let _ = repository
+            .release_idempotency_key(scope, key.client(), key.as_ref())
+            .await;
+        return response;
+    }
+
+    let (response, body) = response.into_parts();
+    let body = match to_bytes(body).await {
+        Ok(body) => body,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let saved = SavedResponse {
+        status_code: response.status().as_u16(),
+        headers: response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((name.as_str().to_owned(), value.to_str().ok()?.to_owned()))
+            })
+            .collect(),
+        body: body.to_vec(),
+    };
+    if repository
+        .save_idempotent_response(scope, key.client(), key.as_ref(), &saved)
+        .await
+        .is_err()
+    {
+        let _ = repository
+            .release_idempotency_key(scope, key.client(), key.as_ref())
+            .await;
+    }
+
+    response.set_body(body).map_into_boxed_body()
+}
+
+fn replay(saved: SavedResponse) -> HttpResponse {
+    let status = StatusCode::from_u16(saved.status_code).unwrap_or(StatusCode::OK);
+    let mut response = HttpResponse::build(status);
+    for (name, value) in saved.headers {
+        response.append_header((name, value));
+    }
+    response.body(saved.body)
+}
@@ -1,5 +1,18 @@
+pub mod cli;
 pub mod configuration;
 pub mod domain;
+pub mod idempotency;
+pub mod metrics;
+pub mod misp;
+pub mod openapi;
+pub mod outbox;
+pub mod rate_limit;
+pub mod repository;
+pub mod request_id;
 pub mod routes;
 pub mod startup;
+pub mod taxii;
+pub mod taxii_sync;
 pub mod telemetry;
+pub mod tls;
+pub mod webhooks;
@@ -1,24 +1,44 @@
+use clap::Parser;
+use metaman::cli::{Cli, Command};
 use metaman::configuration::get_configuration;
-use metaman::startup::run;
-use metaman::telemetry::{get_subscriber, init_subscriber};
-use sqlx::postgres::PgPoolOptions;
-use std::net::TcpListener;
+use metaman::telemetry::{
+    get_configured_subscriber, get_otlp_layer, get_subscriber, init_subscriber,
+};
+use tracing_subscriber::layer::SubscriberExt;
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
-    let subscriber = get_subscriber("metaman".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
-
+async fn main() {
+    let command = Cli::parse().command.unwrap_or(Command::Serve);
     let configuration = get_configuration().expect("Failed to read configuration.");
-    let connection_pool = PgPoolOptions::new()
-        .connect_timeout(std::time::Duration::from_secs(2))
-        .connect_lazy_with(configuration.database.with_db());
 
-    let address = format!(
-        "{}:{}",
-        configuration.application.host, configuration.application.port
-    );
-    let listener = TcpListener::bind(address)?;
-    run(listener, connection_pool)?.await?;
-    Ok(())
+    // Administrative commands print their results on stdout, so only the
+    // server logs as configured.
+    let mut log_guard = None;
+    if let Command::Serve = command {
+        let (subscriber, guard) =
+            get_configured_subscriber("metaman".into(), &configuration.telemetry);
+        log_guard = Some(guard);
+        let otlp_layer = configuration
+            .telemetry
+            .otlp
+            .as_ref()
+            .map(|otlp| get_otlp_layer("metaman".into(), otlp))
+            .transpose()
+            .expect("Failed to set up trace export.");
+        init_subscriber(subscriber.with(otlp_layer));
+    } else {
+        init_subscriber(get_subscriber(
+            "metaman".into(),
+            "warn".into(),
+            std::io::stderr,
+        ));
+    }
+
+    let result = command.execute(configuration).await;
+    opentelemetry::global::shutdown_tracer_provider();
+    drop(log_guard);
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
 }
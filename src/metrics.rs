@@ -0,0 +1,145 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::StatusCode;
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::time::Instant;
+
+use crate::repository::ConnectionUsage;
+
+/// Every metric of the process.
+static REGISTRY: Lazy<Registry> = Lazy::new(|| {
+    Registry::new_custom(Some("metaman".into()), None).expect("The metrics prefix is valid")
+});
+
+static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("http_requests_total", "HTTP requests, by route and status."),
+        &["method", "route", "status"],
+    ))
+});
+
+static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new(
+            "http_request_duration_seconds",
+            "Time spent answering HTTP requests, by route.",
+        ),
+        &["method", "route"],
+    ))
+});
+
+pub(crate) static VALIDATION_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "validation_failures_total",
+            "Marking payloads rejected, by invalid field.",
+        ),
+        &["field"],
+    ))
+});
+
+static RATE_LIMITED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "rate_limited_requests_total",
+            "Requests rejected by the rate limiter, by route group.",
+        ),
+        &["group"],
+    ))
+});
+
+static MARKINGS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new("markings", "Stored markings, by definition type."),
+        &["definition_type"],
+    ))
+});
+
+static DB_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new("db_connections", "Database connections, by state."),
+        &["state"],
+    ))
+});
+
+fn register<M>(metric: prometheus::Result<M>) -> M
+where
+    M: prometheus::core::Collector + Clone + 'static,
+{
+    let metric = metric.expect("Metric definitions are valid");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Metrics are only registered once");
+    metric
+}
+
+/// Counts a marking payload rejected for its `field`.
+pub fn record_validation_failure(field: &str) {
+    VALIDATION_FAILURES.with_label_values(&[field]).inc();
+}
+
+pub fn record_rate_limited(group: &str) {
+    RATE_LIMITED.with_label_values(&[group]).inc();
+}
+
+/// Routes whose responses stream for as long as clients stay connected.
+/// How long they take says nothing of how fast requests are answered, so
+/// they are counted but left out of the durations.
+const STREAMING_ROUTES: [&str; 1] = ["/events"];
+
+/// Measures one HTTP request, labelled with the route pattern it matched
+/// rather than its path, so that ids do not blow up the number of series.
+pub struct RequestTimer {
+    method: String,
+    route: String,
+    start: Instant,
+}
+
+impl RequestTimer {
+    pub fn start(request: &ServiceRequest) -> Self {
+        Self {
+            method: request.method().to_string(),
+            route: request
+                .match_pattern()
+                .unwrap_or_else(|| "unmatched".into()),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn observe(self, status: StatusCode) {
+        HTTP_REQUESTS
+            .with_label_values(&[&self.method, &self.route, status.as_str()])
+            .inc();
+        if STREAMING_ROUTES.contains(&self.route.as_str()) {
+            return;
+        }
+        HTTP_REQUEST_DURATION
+            .with_label_values(&[&self.method, &self.route])
+            .observe(self.start.elapsed().as_secs_f64());
+    }
+}
+
+/// Samples the markings stored, by definition type.
+pub fn record_markings(counts: Vec<(String, i64)>) {
+    MARKINGS.reset();
+    for (definition_type, count) in counts {
+        MARKINGS.with_label_values(&[&definition_type]).set(count);
+    }
+}
+
+pub fn record_connections(connections: ConnectionUsage) {
+    let idle = i64::from(connections.idle);
+    DB_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+    DB_CONNECTIONS
+        .with_label_values(&["in_use"])
+        .set(i64::from(connections.open) - idle);
+}
+
+/// Renders every metric in the Prometheus text format.
+pub fn encode() -> prometheus::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    TextEncoder::new().encode(&REGISTRY.gather(), &mut body)?;
+    Ok(body)
+}
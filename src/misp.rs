@@ -0,0 +1,541 @@
+//! MISP taxonomies, the `machinetag.json` files MISP labels events with.
+//!
+//! TLP and PAP markings are exported to the standard taxonomies, such as
+//! `tlp:amber` for `tlp_amber` and `PAP:GREEN` for `pap_green`. Every other
+//! marking goes to a taxonomy of its own whose predicates are definition
+//! types, such as `metaman:statement="copyright_acme"`.
+//!
+//! Imported tags become markings named after the tag, such as `tlp_amber`
+//! for `tlp:amber`: names start with the namespace, so that taxonomies
+//! cannot clash. Values of predicates named after a definition type are
+//! what metaman exports, so they are marking names and kept as they are:
+//! importing an export gives back the same markings.
+use crate::domain::{Marking, MarkingDefinition, MarkingDefinitionType, MarkingName, NewMarking};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Taxonomy {
+    pub namespace: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub version: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expanded: Option<String>,
+    #[serde(default)]
+    pub predicates: Vec<Predicate>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<PredicateValues>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Predicate {
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expanded: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub colour: Option<String>,
+}
+
+/// The values a predicate takes, which are tagged as
+/// `namespace:predicate="value"`.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct PredicateValues {
+    pub predicate: String,
+    #[serde(default)]
+    pub entry: Vec<Entry>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expanded: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub colour: Option<String>,
+}
+
+/// The levels of TLP and PAP, which name their predicates in the standard
+/// taxonomies.
+const LEVELS: [&str; 5] = ["red", "amber", "green", "white", "clear"];
+
+/// The colours MISP gives the TLP levels.
+const TLP_COLOURS: [(&str, &str); 5] = [
+    ("red", "#CC0033"),
+    ("amber", "#FFC000"),
+    ("green", "#339900"),
+    ("white", "#FFFFFF"),
+    ("clear", "#FFFFFF"),
+];
+
+/// Lays `markings` out as taxonomies: TLP markings named after a level go
+/// to the standard `tlp` taxonomy, PAP statements named after a level to
+/// `PAP`, and every other marking to a taxonomy in `namespace`, with a
+/// predicate for each definition type and an entry for each marking.
+///
+/// The version of each is the time its markings were last written, so that
+/// MISP picks up a newer export.
+pub fn export_taxonomies(namespace: &str, markings: &[Marking]) -> Vec<Taxonomy> {
+    let tlp: Vec<_> = markings
+        .iter()
+        .filter_map(|marking| Some((marking, standard_level(marking, "tlp", "tlp_")?)))
+        .collect();
+    let pap: Vec<_> = markings
+        .iter()
+        .filter_map(|marking| Some((marking, standard_level(marking, "statement", "pap_")?)))
+        .collect();
+    let others: Vec<_> = markings
+        .iter()
+        .filter(|marking| {
+            standard_level(marking, "tlp", "tlp_").is_none()
+                && standard_level(marking, "statement", "pap_").is_none()
+        })
+        .collect();
+
+    let mut taxonomies = Vec::new();
+    if !tlp.is_empty() {
+        taxonomies.push(standard_taxonomy(
+            "tlp",
+            "The Traffic Light Protocol.",
+            &tlp,
+            str::to_owned,
+        ));
+    }
+    if !pap.is_empty() {
+        taxonomies.push(standard_taxonomy(
+            "PAP",
+            "The Permissible Actions Protocol.",
+            &pap,
+            str::to_uppercase,
+        ));
+    }
+    if !others.is_empty() {
+        taxonomies.push(own_taxonomy(namespace, &others));
+    }
+    taxonomies
+}
+
+/// The level `marking` is named after in a standard taxonomy, such as
+/// `amber` for the TLP marking `tlp_amber`.
+fn standard_level<'a>(
+    marking: &'a Marking,
+    definition_type: &str,
+    prefix: &str,
+) -> Option<&'a str> {
+    if marking.definition_type != definition_type {
+        return None;
+    }
+    marking
+        .name
+        .strip_prefix(prefix)
+        .filter(|level| LEVELS.contains(level))
+}
+
+/// A standard taxonomy with a predicate for each level, named by `predicate`.
+fn standard_taxonomy(
+    namespace: &str,
+    description: &str,
+    levels: &[(&Marking, &str)],
+    predicate: fn(&str) -> String,
+) -> Taxonomy {
+    Taxonomy {
+        namespace: namespace.to_owned(),
+        description: description.to_owned(),
+        version: version(levels.iter().map(|(marking, _)| *marking)),
+        expanded: None,
+        predicates: levels
+            .iter()
+            .map(|(marking, level)| Predicate {
+                value: predicate(level),
+                expanded: Some(marking.definition.clone()),
+                description: None,
+                colour: colour(marking),
+            })
+            .collect(),
+        values: Vec::new(),
+    }
+}
+
+fn own_taxonomy(namespace: &str, markings: &[&Marking]) -> Taxonomy {
+    let mut definition_types: Vec<_> = markings
+        .iter()
+        .map(|marking| marking.definition_type.clone())
+        .collect();
+    definition_types.sort();
+    definition_types.dedup();
+
+    let values = definition_types
+        .iter()
+        .map(|definition_type| PredicateValues {
+            predicate: definition_type.clone(),
+            entry: markings
+                .iter()
+                .filter(|marking| &marking.definition_type == definition_type)
+                .map(|marking| Entry {
+                    value: marking.name.clone(),
+                    expanded: Some(marking.definition.clone()),
+                    description: None,
+                    colour: colour(marking),
+                })
+                .collect(),
+        })
+        .collect();
+    Taxonomy {
+        namespace: namespace.to_owned(),
+        description: "Data markings managed by metaman.".into(),
+        version: version(markings.iter().copied()),
+        expanded: None,
+        predicates: definition_types
+            .into_iter()
+            .map(|definition_type| Predicate {
+                value: definition_type,
+                expanded: None,
+                description: None,
+                colour: None,
+            })
+            .collect(),
+        values,
+    }
+}
+
+fn version<'a>(markings: impl Iterator<Item = &'a Marking>) -> i64 {
+    markings
+        .map(|marking| marking.updated_at.unwrap_or(marking.created_at).timestamp())
+        .max()
+        .unwrap_or_default()
+}
+
+/// The colour of a TLP marking whose name ends with its level, such as
+/// `tlp_amber`.
+fn colour(marking: &Marking) -> Option<String> {
+    if marking.definition_type != "tlp" {
+        return None;
+    }
+    let level = marking.name.rsplit('_').next()?;
+    TLP_COLOURS
+        .iter()
+        .find(|(known, _)| *known == level)
+        .map(|(_, colour)| colour.to_string())
+}
+
+/// The markings of a taxonomy, with what was lost on the way.
+#[derive(Debug)]
+pub struct ImportedTaxonomy {
+    pub markings: Vec<NewMarking>,
+    /// Why the expanded form of some tags could not be their definition,
+    /// which is the tag itself instead.
+    pub warnings: Vec<String>,
+}
+
+/// The markings for every tag of `taxonomy`: one per predicate without
+/// values, one per value otherwise.
+///
+/// Tags in the `tlp` namespace, or under a `tlp` predicate, are TLP
+/// markings, every other tag is a statement. Values under a predicate named
+/// after a definition type keep their name. Their definition is the
+/// expanded form of the tag when it is a valid definition, the tag itself
+/// otherwise, with a warning, less the quotes definitions cannot hold.
+pub fn import_taxonomy(taxonomy: &Taxonomy) -> Result<ImportedTaxonomy, String> {
+    let namespace = &taxonomy.namespace;
+    let mut imported = ImportedTaxonomy {
+        markings: Vec::new(),
+        warnings: Vec::new(),
+    };
+    for predicate in &taxonomy.predicates {
+        let entries = taxonomy
+            .values
+            .iter()
+            .filter(|values| values.predicate == predicate.value)
+            .flat_map(|values| &values.entry)
+            .collect::<Vec<_>>();
+        let is_definition_type = MarkingDefinitionType::is_supported(&predicate.value);
+        let definition_type = if is_definition_type {
+            predicate.value.as_str()
+        } else if namespace.eq_ignore_ascii_case("tlp") {
+            "tlp"
+        } else {
+            "statement"
+        };
+
+        if entries.is_empty() {
+            let tag = format!("{}:{}", namespace, predicate.value);
+            let name = MarkingName::from_label(&format!("{} {}", namespace, predicate.value));
+            imported.push(&tag, name, definition_type, predicate.expanded.as_deref())?;
+        }
+        for entry in entries {
+            let tag = format!("{}:{}=\"{}\"", namespace, predicate.value, entry.value);
+            // Predicates named after a definition type are what metaman
+            // exports, their values are marking names.
+            let name = if is_definition_type {
+                MarkingName::parse(entry.value.clone())
+            } else {
+                MarkingName::from_label(&format!(
+                    "{} {} {}",
+                    namespace, predicate.value, entry.value
+                ))
+            };
+            imported.push(&tag, name, definition_type, entry.expanded.as_deref())?;
+        }
+    }
+    Ok(imported)
+}
+
+impl ImportedTaxonomy {
+    fn push(
+        &mut self,
+        tag: &str,
+        name: Result<MarkingName, String>,
+        definition_type: &str,
+        expanded: Option<&str>,
+    ) -> Result<(), String> {
+        let expanded = expanded.map(|expanded| MarkingDefinition::parse(expanded.to_owned()));
+        let definition = match expanded {
+            Some(Ok(definition)) => Ok(definition),
+            Some(Err(e)) => {
+                self.warnings
+                    .push(format!("{}: dropped its expanded form, {}", tag, e));
+                MarkingDefinition::parse(tag.replace('"', ""))
+            }
+            None => MarkingDefinition::parse(tag.replace('"', "")),
+        }
+        .map_err(|e| format!("{}: {}", tag, e))?;
+        self.markings.push(NewMarking {
+            name: name.map_err(|e| format!("{}: {}", tag, e))?,
+            definition_type: MarkingDefinitionType::parse(definition_type.to_owned())?,
+            definition,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::Marking;
+    use crate::misp::{export_taxonomies, import_taxonomy, Taxonomy};
+    use claim::assert_err;
+    use uuid::Uuid;
+
+    fn marking(name: &str, definition_type: &str, definition: &str) -> Marking {
+        Marking {
+            id: Uuid::new_v4(),
+            name: name.into(),
+            definition_type: definition_type.into(),
+            definition: definition.into(),
+            created_at: "2022-03-01T10:00:00Z".parse().unwrap(),
+            updated_at: None,
+            created_by: Uuid::new_v4(),
+            updated_by: None,
+            version: 1,
+            status: "active".into(),
+            source_server: None,
+            source_collection: None,
+            fetched_at: None,
+            date_added: None,
+        }
+    }
+
+    fn names(taxonomy: &Taxonomy) -> Vec<(String, String, String)> {
+        import_taxonomy(taxonomy)
+            .unwrap()
+            .markings
+            .into_iter()
+            .map(|marking| {
+                (
+                    marking.name.as_ref().to_owned(),
+                    marking.definition_type.as_ref().to_owned(),
+                    marking.definition.as_ref().to_owned(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tlp_and_pap_markings_are_exported_to_the_standard_taxonomies() {
+        let taxonomies = export_taxonomies(
+            "metaman",
+            &[
+                marking("tlp_amber", "tlp", "TLP Amber"),
+                marking("pap_green", "statement", "PAP Green"),
+                marking("copyright_acme", "statement", "Copyright Acme"),
+                marking("acme_amber", "tlp", "Acme Amber"),
+            ],
+        );
+
+        assert_eq!(
+            serde_json::json!([
+                {
+                    "namespace": "tlp",
+                    "description": "The Traffic Light Protocol.",
+                    "version": 1646128800,
+                    "predicates": [{
+                        "value": "amber",
+                        "expanded": "TLP Amber",
+                        "colour": "#FFC000"
+                    }]
+                },
+                {
+                    "namespace": "PAP",
+                    "description": "The Permissible Actions Protocol.",
+                    "version": 1646128800,
+                    "predicates": [{ "value": "GREEN", "expanded": "PAP Green" }]
+                },
+                {
+                    "namespace": "metaman",
+                    "description": "Data markings managed by metaman.",
+                    "version": 1646128800,
+                    "predicates": [{ "value": "statement" }, { "value": "tlp" }],
+                    "values": [
+                        {
+                            "predicate": "statement",
+                            "entry": [{ "value": "copyright_acme", "expanded": "Copyright Acme" }]
+                        },
+                        {
+                            "predicate": "tlp",
+                            "entry": [{
+                                "value": "acme_amber",
+                                "expanded": "Acme Amber",
+                                "colour": "#FFC000"
+                            }]
+                        }
+                    ]
+                }
+            ]),
+            serde_json::to_value(taxonomies).unwrap()
+        );
+    }
+
+    #[test]
+    fn tlp_colours_follow_the_level_the_name_ends_with() {
+        let taxonomies = export_taxonomies(
+            "metaman",
+            &[
+                marking("tlp_amber", "tlp", "Not for red teams"),
+                marking("tlp_clearance", "tlp", "TLP Green"),
+            ],
+        );
+
+        assert_eq!(
+            Some("#FFC000"),
+            taxonomies[0].predicates[0].colour.as_deref()
+        );
+        assert_eq!(None, taxonomies[1].values[0].entry[0].colour);
+    }
+
+    #[test]
+    fn exported_taxonomies_import_the_same_markings() {
+        let taxonomies = export_taxonomies(
+            "metaman",
+            &[
+                marking("tlp_amber", "tlp", "TLP Amber"),
+                marking("pap_green", "statement", "PAP Green"),
+                marking("copyright_acme", "statement", "Copyright Acme"),
+            ],
+        );
+
+        let imported: Vec<_> = taxonomies.iter().flat_map(names).collect();
+        assert_eq!(
+            vec![
+                ("tlp_amber".into(), "tlp".into(), "TLP Amber".into()),
+                ("pap_green".into(), "statement".into(), "PAP Green".into()),
+                (
+                    "copyright_acme".into(),
+                    "statement".into(),
+                    "Copyright Acme".into()
+                ),
+            ],
+            imported
+        );
+    }
+
+    #[test]
+    fn misp_taxonomies_are_imported_tag_by_tag() {
+        let taxonomy: Taxonomy = serde_json::from_value(serde_json::json!({
+            "namespace": "tlp",
+            "description": "The Traffic Light Protocol.",
+            "version": 6,
+            "predicates": [
+                {
+                    "value": "amber",
+                    "expanded": "(TLP:AMBER) Limited disclosure.",
+                    "colour": "#FFC000"
+                },
+                { "value": "clear", "expanded": "TLP Clear" }
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                ("tlp_amber".into(), "tlp".into(), "tlp:amber".into()),
+                ("tlp_clear".into(), "tlp".into(), "TLP Clear".into()),
+            ],
+            names(&taxonomy)
+        );
+        let warnings = import_taxonomy(&taxonomy).unwrap().warnings;
+        assert_eq!(1, warnings.len());
+        assert!(warnings[0].starts_with("tlp:amber: dropped its expanded form"));
+    }
+
+    #[test]
+    fn predicate_values_become_statements() {
+        let taxonomy: Taxonomy = serde_json::from_value(serde_json::json!({
+            "namespace": "PAP",
+            "description": "Permissible Actions Protocol.",
+            "version": 3,
+            "predicates": [{ "value": "GREEN" }, { "value": "level" }],
+            "values": [{
+                "predicate": "level",
+                "entry": [{ "value": "high", "expanded": "High sensitivity" }]
+            }]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                ("pap_green".into(), "statement".into(), "PAP:GREEN".into()),
+                (
+                    "pap_level_high".into(),
+                    "statement".into(),
+                    "High sensitivity".into()
+                ),
+            ],
+            names(&taxonomy)
+        );
+    }
+
+    #[test]
+    fn values_are_tagged_with_quotes() {
+        let taxonomy: Taxonomy = serde_json::from_value(serde_json::json!({
+            "namespace": "source",
+            "predicates": [{ "value": "origin" }],
+            "values": [{ "predicate": "origin", "entry": [{ "value": "internal", "expanded": "" }] }]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            vec![(
+                "source_origin_internal".into(),
+                "statement".into(),
+                "source:origin=internal".into()
+            )],
+            names(&taxonomy)
+        );
+        let warnings = import_taxonomy(&taxonomy).unwrap().warnings;
+        assert!(warnings[0].starts_with(r#"source:origin="internal": dropped its expanded form"#));
+    }
+
+    #[test]
+    fn tags_that_cannot_be_markings_are_rejected() {
+        let taxonomy: Taxonomy = serde_json::from_value(serde_json::json!({
+            "namespace": "admiralty",
+            "predicates": [{ "value": "rating" }],
+            "values": [{ "predicate": "rating", "entry": [{ "value": "a/b" }] }]
+        }))
+        .unwrap();
+
+        assert_err!(import_taxonomy(&taxonomy));
+    }
+}
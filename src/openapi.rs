@@ -0,0 +1,100 @@
+use utoipa::OpenApi;
+
+use crate::domain::{
+    DeadLetter, Marking, MarkingAssignment, MarkingEvent, MarkingEventType, Webhook,
+    WebhookDelivery,
+};
+use crate::repository::{MigrationState, MigrationStatus, SearchResult};
+use crate::request_id::ErrorBody;
+use crate::routes::{
+    self, BulkItemResult, BulkMode, BulkUpdateData, Check, JsonData, PatchData, Readiness,
+    WebhookData,
+};
+use crate::taxii::{
+    ApiRoot, Collection, Collections, Discovery, Envelope, Manifest, ManifestRecord,
+    StixMarkingDefinition, TaxiiError, Versions,
+};
+
+/// The OpenAPI document of every route registered by `startup::run`, served
+/// at `/openapi.json`.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "metaman",
+        description = "Manages data markings.",
+        license(name = "MIT")
+    ),
+    paths(
+        routes::create_marking,
+        routes::list_markings,
+        routes::search_markings,
+        routes::bulk_create_markings,
+        routes::bulk_update_markings,
+        routes::get_marking,
+        routes::update_marking,
+        routes::patch_marking,
+        routes::delete_marking,
+        routes::deprecate_marking,
+        routes::revoke_marking,
+        routes::list_marking_assignments,
+        routes::assign_marking,
+        routes::stream_events,
+        routes::health_check,
+        routes::health_live,
+        routes::health_ready,
+        routes::metrics,
+        routes::migration_status,
+        routes::taxii_discovery,
+        routes::taxii_api_root,
+        routes::taxii_collections,
+        routes::taxii_collection,
+        routes::taxii_objects,
+        routes::taxii_object,
+        routes::taxii_versions,
+        routes::taxii_manifest,
+        routes::create_webhook,
+        routes::list_webhooks,
+        routes::get_webhook,
+        routes::delete_webhook,
+        routes::list_webhook_deliveries,
+        routes::list_webhook_dead_letters,
+    ),
+    components(schemas(
+        JsonData,
+        PatchData,
+        Marking,
+        MarkingAssignment,
+        SearchResult,
+        BulkMode,
+        BulkUpdateData,
+        BulkItemResult,
+        ErrorBody,
+        Readiness,
+        Check,
+        MigrationStatus,
+        MigrationState,
+        Discovery,
+        ApiRoot,
+        Collections,
+        Collection,
+        Envelope,
+        Versions,
+        Manifest,
+        ManifestRecord,
+        TaxiiError,
+        StixMarkingDefinition,
+        WebhookData,
+        Webhook,
+        WebhookDelivery,
+        DeadLetter,
+        MarkingEvent,
+        MarkingEventType,
+    )),
+    tags(
+        (name = "markings", description = "Data markings."),
+        (name = "operations", description = "Probes, metrics and schema status."),
+        (name = "taxii", description = "Markings as a TAXII 2.1 collection of STIX marking definitions."),
+        (name = "webhooks", description = "Subscriptions to marking events, posted as signed `MarkingEvent` payloads."),
+    )
+)]
+pub struct ApiDoc;
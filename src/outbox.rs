@@ -0,0 +1,420 @@
+//! Publishes the marking events saved in the outbox to sinks, and tells
+//! `GET /events` streams about new ones.
+//!
+//! Every marking write saves its event in the same transaction, so an event
+//! exists if and only if its write was committed. Each sink then reads the
+//! outbox from the last event it published, as recorded in its cursor, and
+//! only moves its cursor once an event is published: every sink gets every
+//! event at least once, in the order they were committed, even across
+//! restarts. Every webhook is a sink of its own.
+use chrono::Utc;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::configuration::{OutboxSettings, SinkSettings, WebhookSettings};
+use crate::domain::OutboxEvent;
+use crate::repository::{OutboxRepository, RepositoryError, Storage};
+use crate::webhooks::{webhook_client, WebhookSink};
+
+/// How often events past their retention are deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Why a sink could not publish an event.
+pub type SinkError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Somewhere marking events are published to, such as a message broker.
+///
+/// An event is offered again until `publish` succeeds, and may still be
+/// offered twice when the process stops in between: receivers should drop
+/// the event ids they have already seen.
+#[async_trait::async_trait]
+pub trait EventSink: Send + Sync {
+    /// Identifies the cursor of the sink. A sink whose name is new starts
+    /// from the first event in the outbox.
+    fn name(&self) -> String;
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), SinkError>;
+}
+
+/// Prints every event on stdout, one JSON document per line.
+pub struct StdoutSink;
+
+#[async_trait::async_trait]
+impl EventSink for StdoutSink {
+    fn name(&self) -> String {
+        "stdout".into()
+    }
+
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), SinkError> {
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", event.payload)?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// Appends every event to a file, one JSON document per line.
+pub struct FileSink {
+    path: PathBuf,
+    file: Mutex<tokio::fs::File>,
+}
+
+impl FileSink {
+    /// Opens `path` for appending, creating it if need be.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+        let path = path.into();
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        Ok(Self {
+            path,
+            file: Mutex::new(tokio::fs::File::from_std(file)),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSink for FileSink {
+    fn name(&self) -> String {
+        format!("file:{}", self.path.display())
+    }
+
+    /// Only returns once the event is on disk.
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), SinkError> {
+        let mut line = event.payload.clone();
+        line.push('\n');
+        let mut file = self.file.lock().await;
+        file.write_all(line.as_bytes()).await?;
+        file.sync_data().await?;
+        Ok(())
+    }
+}
+
+/// Builds the sinks listed in `settings.sinks`, but for webhooks, which
+/// `spawn_webhook_outbox` publishes to.
+pub fn configured_sinks(
+    settings: &OutboxSettings,
+) -> Result<Vec<Arc<dyn EventSink>>, std::io::Error> {
+    let mut sinks: Vec<Arc<dyn EventSink>> = Vec::new();
+    for sink in &settings.sinks {
+        match sink {
+            SinkSettings::Webhooks => {}
+            SinkSettings::File { path } => sinks.push(Arc::new(FileSink::open(path)?)),
+            SinkSettings::Stdout => sinks.push(Arc::new(StdoutSink)),
+        }
+    }
+    Ok(sinks)
+}
+
+/// Publishes the outbox to each of `sinks` from its own task, so that a
+/// slow or failing sink holds up no other, for as long as the runtime runs.
+pub fn spawn_outbox(
+    repository: Arc<dyn OutboxRepository>,
+    sinks: Vec<Arc<dyn EventSink>>,
+    settings: &OutboxSettings,
+) {
+    for sink in sinks {
+        tokio::spawn(run_sink(repository.clone(), sink, settings.clone()));
+    }
+}
+
+/// Publishes the outbox to each registered webhook from a task of its own,
+/// so that a receiver that is down only holds up its own events while they
+/// are retried. Webhooks are listed every `poll_interval_ms`: tasks start
+/// for new ones and stop for deleted ones.
+pub fn spawn_webhook_outbox(
+    storage: &Storage,
+    settings: &OutboxSettings,
+    webhook_settings: &WebhookSettings,
+) -> Result<(), std::io::Error> {
+    let client = webhook_client(webhook_settings).map_err(std::io::Error::other)?;
+    let outbox = storage.outbox.clone();
+    let webhooks = storage.webhooks.clone();
+    let settings = settings.clone();
+    let webhook_settings = webhook_settings.clone();
+    tokio::spawn(async move {
+        let mut sinks: HashMap<Uuid, JoinHandle<()>> = HashMap::new();
+        loop {
+            match webhooks.list_webhooks().await {
+                Ok(registered) => {
+                    sinks.retain(|id, sink| {
+                        let kept = registered.iter().any(|webhook| webhook.id == *id);
+                        if !kept {
+                            sink.abort();
+                        }
+                        kept
+                    });
+                    for webhook in registered {
+                        sinks.entry(webhook.id).or_insert_with(|| {
+                            let sink = WebhookSink::new(
+                                webhook.id,
+                                webhooks.clone(),
+                                client.clone(),
+                                webhook_settings.clone(),
+                            );
+                            tokio::spawn(run_sink(outbox.clone(), Arc::new(sink), settings.clone()))
+                        });
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!(error.message = %e, "Failed to list webhooks, retrying.");
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(settings.poll_interval_ms)).await;
+        }
+    });
+    Ok(())
+}
+
+async fn run_sink(
+    repository: Arc<dyn OutboxRepository>,
+    sink: Arc<dyn EventSink>,
+    settings: OutboxSettings,
+) {
+    let name = sink.name();
+    loop {
+        let delay = match publish_pending(&*repository, &*sink, &name, settings.batch_size).await {
+            // More events may be waiting.
+            Ok(published) if published as i64 >= settings.batch_size => continue,
+            Ok(_) => settings.poll_interval_ms,
+            Err(e) => {
+                tracing::warn!(
+                    sink = %name,
+                    error.message = %e,
+                    "Failed to publish marking events, retrying."
+                );
+                settings.retry_delay_ms
+            }
+        };
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+    }
+}
+
+/// Publishes the next events after the cursor of `sink` and moves its cursor
+/// past each of them, returning how many were published.
+async fn publish_pending(
+    repository: &dyn OutboxRepository,
+    sink: &dyn EventSink,
+    name: &str,
+    batch_size: i64,
+) -> Result<usize, SinkError> {
+    let cursor = repository.get_outbox_cursor(name).await?;
+    let events = repository.list_outbox_events(cursor, batch_size).await?;
+    for event in &events {
+        sink.publish(event).await?;
+        repository.save_outbox_cursor(name, event.sequence).await?;
+    }
+    Ok(events.len())
+}
+
+/// Deletes the events published by every sink once they are older than
+/// `settings.retention_hours`, every `PRUNE_INTERVAL`, for as long as the
+/// runtime runs.
+pub fn spawn_outbox_pruning(repository: Arc<dyn OutboxRepository>, settings: &OutboxSettings) {
+    let settings = settings.clone();
+    tokio::spawn(async move {
+        loop {
+            match prune_outbox(&*repository, &settings).await {
+                Ok(0) => {}
+                Ok(pruned) => tracing::info!(pruned, "Pruned published marking events."),
+                Err(e) => tracing::warn!(error.message = %e, "Failed to prune the outbox."),
+            }
+            tokio::time::sleep(PRUNE_INTERVAL).await;
+        }
+    });
+}
+
+async fn prune_outbox(
+    repository: &dyn OutboxRepository,
+    settings: &OutboxSettings,
+) -> Result<u64, RepositoryError> {
+    let retention = chrono::Duration::hours(settings.retention_hours.min(i32::MAX as u64) as i64);
+    repository.prune_outbox_events(Utc::now() - retention).await
+}
+
+/// Tells `GET /events` streams the sequence of the last event saved to the
+/// outbox, so that they only read the outbox once there is something new in
+/// it, rather than each polling it. The outbox is only polled while a stream
+/// is subscribed.
+pub struct EventFeed {
+    repository: Arc<dyn OutboxRepository>,
+    settings: OutboxSettings,
+    sender: Arc<watch::Sender<i64>>,
+    /// Whether a task is polling the outbox.
+    polling: Arc<std::sync::Mutex<bool>>,
+}
+
+impl EventFeed {
+    pub fn new(repository: Arc<dyn OutboxRepository>, settings: &OutboxSettings) -> Self {
+        Self {
+            repository,
+            settings: settings.clone(),
+            sender: Arc::new(watch::channel(0).0),
+            polling: Arc::new(std::sync::Mutex::new(false)),
+        }
+    }
+
+    /// Starts polling the outbox unless a stream already subscribed did.
+    pub fn subscribe(&self) -> watch::Receiver<i64> {
+        let mut polling = self.polling.lock().unwrap();
+        let receiver = self.sender.subscribe();
+        if !*polling {
+            *polling = true;
+            tokio::spawn(poll_outbox(
+                self.repository.clone(),
+                self.settings.clone(),
+                self.sender.clone(),
+                self.polling.clone(),
+            ));
+        }
+        receiver
+    }
+}
+
+/// Polls the sequence of the last event saved to the outbox until the last
+/// stream subscribed to `sender` has ended.
+async fn poll_outbox(
+    repository: Arc<dyn OutboxRepository>,
+    settings: OutboxSettings,
+    sender: Arc<watch::Sender<i64>>,
+    polling: Arc<std::sync::Mutex<bool>>,
+) {
+    loop {
+        let delay = match repository.last_outbox_sequence().await {
+            Ok(sequence) => {
+                sender.send_if_modified(|last| std::mem::replace(last, sequence) != sequence);
+                settings.poll_interval_ms
+            }
+            Err(e) => {
+                tracing::warn!(error.message = %e, "Failed to poll the outbox, retrying.");
+                settings.retry_delay_ms
+            }
+        };
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+        // Checked under the lock `subscribe` takes, so that a stream
+        // subscribing meanwhile either keeps this task or starts another.
+        let mut polling = polling.lock().unwrap();
+        if sender.receiver_count() == 0 {
+            *polling = false;
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::OutboxSettings;
+    use crate::domain::{
+        MarkingDefinition, MarkingDefinitionType, MarkingName, NewMarking, OutboxEvent,
+    };
+    use crate::outbox::{publish_pending, EventFeed, EventSink, SinkError};
+    use crate::repository::{InMemoryMarkingRepository, MarkingRepository, OutboxRepository};
+    use claim::{assert_err, assert_ok};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// Records what it is given, failing on the events listed in `failing`.
+    #[derive(Default)]
+    struct RecordingSink {
+        published: Mutex<Vec<i64>>,
+        failing: Vec<i64>,
+    }
+
+    #[async_trait::async_trait]
+    impl EventSink for RecordingSink {
+        fn name(&self) -> String {
+            "recording".into()
+        }
+
+        async fn publish(&self, event: &OutboxEvent) -> Result<(), SinkError> {
+            if self.failing.contains(&event.sequence) {
+                return Err("The broker is down.".into());
+            }
+            self.published.lock().unwrap().push(event.sequence);
+            Ok(())
+        }
+    }
+
+    async fn repository_with_markings(names: &[&str]) -> InMemoryMarkingRepository {
+        let repository = InMemoryMarkingRepository::new();
+        for name in names {
+            let marking = NewMarking {
+                name: MarkingName::parse(name.to_string()).unwrap(),
+                definition_type: MarkingDefinitionType::parse("statement".into()).unwrap(),
+                definition: MarkingDefinition::parse("A statement".into()).unwrap(),
+            };
+            repository.insert_marking(&marking).await.unwrap();
+        }
+        repository
+    }
+
+    #[tokio::test]
+    async fn sinks_resume_after_the_last_event_they_published() {
+        let repository = repository_with_markings(&["acme", "globex", "initech"]).await;
+        let sink = RecordingSink::default();
+
+        assert_eq!(
+            2,
+            publish_pending(&repository, &sink, "recording", 2)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            1,
+            publish_pending(&repository, &sink, "recording", 2)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            0,
+            publish_pending(&repository, &sink, "recording", 2)
+                .await
+                .unwrap()
+        );
+
+        assert_eq!(vec![1, 2, 3], *sink.published.lock().unwrap());
+        assert_eq!(3, repository.get_outbox_cursor("recording").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn events_a_sink_failed_to_publish_are_offered_again() {
+        let repository = repository_with_markings(&["acme", "globex", "initech"]).await;
+        let sink = RecordingSink {
+            failing: vec![2],
+            ..RecordingSink::default()
+        };
+
+        assert_err!(publish_pending(&repository, &sink, "recording", 10).await);
+
+        assert_eq!(vec![1], *sink.published.lock().unwrap());
+        assert_eq!(1, repository.get_outbox_cursor("recording").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn the_feed_only_polls_the_outbox_while_streams_are_subscribed() {
+        let repository = Arc::new(repository_with_markings(&["acme"]).await);
+        let settings = OutboxSettings {
+            poll_interval_ms: 10,
+            ..OutboxSettings::default()
+        };
+        let feed = EventFeed::new(repository.clone(), &settings);
+        assert!(!*feed.polling.lock().unwrap());
+
+        let mut stream = feed.subscribe();
+        let changed = tokio::time::timeout(Duration::from_secs(1), stream.changed()).await;
+        assert_ok!(assert_ok!(changed));
+        assert_eq!(1, *stream.borrow());
+        assert!(*feed.polling.lock().unwrap());
+
+        drop(stream);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!*feed.polling.lock().unwrap());
+    }
+}
@@ -0,0 +1,248 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::Method;
+use actix_web::{HttpMessage, HttpResponse};
+use hashlink::lru_cache::{Entry, LruCache};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::configuration::{RateLimit, RateLimitSettings};
+use crate::metrics::record_rate_limited;
+use crate::tls::ClientIdentity;
+
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Past this many buckets, the least recently used is dropped: its client
+/// has not sent anything for a while, and would mostly have a full bucket
+/// anyway.
+const MAX_BUCKETS: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RouteGroup {
+    Reads,
+    Writes,
+    Imports,
+}
+
+impl RouteGroup {
+    /// The group of a request, or `None` for the health and metrics
+    /// endpoints, which are never limited.
+    pub fn of(method: &Method, path: &str) -> Option<Self> {
+        if path == "/health_check" || path.starts_with("/health/") || path == "/metrics" {
+            return None;
+        }
+        if method.is_safe() {
+            Some(RouteGroup::Reads)
+        } else if path == "/markings/bulk" {
+            Some(RouteGroup::Imports)
+        } else {
+            Some(RouteGroup::Writes)
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            RouteGroup::Reads => "reads",
+            RouteGroup::Writes => "writes",
+            RouteGroup::Imports => "imports",
+        }
+    }
+}
+
+/// The client a request was told apart as, left in its extensions for the
+/// handlers.
+#[derive(Clone, Debug)]
+pub struct Client(String);
+
+impl AsRef<str> for Client {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Token buckets for every client and route group, shared by every worker.
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    buckets: Mutex<LruCache<(RouteGroup, String), Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings) -> Self {
+        Self {
+            settings,
+            buckets: Mutex::new(LruCache::new(MAX_BUCKETS)),
+        }
+    }
+
+    /// Returns the 429 to answer `request` with when its client is over the
+    /// limit, `None` when it may go through. Either way, the client is left
+    /// in the extensions of `request`.
+    pub fn reject(&self, request: &ServiceRequest) -> Option<HttpResponse> {
+        let client = client(request, &self.settings.api_keys);
+        request.extensions_mut().insert(Client(client.clone()));
+        let group = RouteGroup::of(request.method(), request.path())?;
+        let retry_after = self.take(group, client, Instant::now()).err()?;
+        record_rate_limited(group.as_str());
+        // Clients wait at least the time it takes to refill one token.
+        let seconds = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+        Some(
+            HttpResponse::TooManyRequests()
+                .insert_header((RETRY_AFTER, seconds))
+                .json(serde_json::json!({
+                    "error": format!("Too many requests, retry in {} seconds.", seconds),
+                })),
+        )
+    }
+
+    /// Takes a token from the bucket of `client` for `group`, or returns how
+    /// long until one is available.
+    pub fn take(&self, group: RouteGroup, client: String, now: Instant) -> Result<(), Duration> {
+        let limit = match self.limit(group) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = match buckets.entry((group, client)) {
+            Entry::Occupied(mut entry) => {
+                entry.to_back();
+                entry.into_mut()
+            }
+            Entry::Vacant(entry) => entry.insert(Bucket {
+                tokens: f64::from(limit.burst),
+                updated: now,
+            }),
+        };
+        let tokens = bucket.refill(limit, now);
+        if tokens >= 1.0 {
+            bucket.tokens = tokens - 1.0;
+            bucket.updated = now;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - tokens) / limit.per_second))
+        }
+    }
+
+    fn limit(&self, group: RouteGroup) -> Option<RateLimit> {
+        match group {
+            RouteGroup::Reads => self.settings.reads,
+            RouteGroup::Writes => self.settings.writes,
+            RouteGroup::Imports => self.settings.imports,
+        }
+    }
+}
+
+impl Bucket {
+    /// The tokens of the bucket at `now`, without updating it.
+    fn refill(&self, limit: RateLimit, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * limit.per_second).min(f64::from(limit.burst))
+    }
+}
+
+/// Tells clients apart by certificate, API key or IP address. Only the keys
+/// in `api_keys` count, or clients could get a fresh bucket by sending a new
+/// key with every request.
+fn client(request: &ServiceRequest, api_keys: &[String]) -> String {
+    if let Some(identity) = request.conn_data::<ClientIdentity>() {
+        return format!("identity:{}", identity.as_ref());
+    }
+    let api_key = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|api_key| api_keys.iter().any(|known| known == api_key));
+    if let Some(api_key) = api_key {
+        return format!("key:{}", api_key);
+    }
+    match request.peer_addr() {
+        Some(address) => format!("ip:{}", address.ip()),
+        None => "unknown".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{RateLimit, RateLimitSettings};
+    use crate::rate_limit::{RateLimiter, RouteGroup, MAX_BUCKETS};
+    use actix_web::http::Method;
+    use claim::{assert_err, assert_ok};
+    use std::time::{Duration, Instant};
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitSettings {
+            reads: None,
+            writes: Some(RateLimit {
+                burst: 2,
+                per_second: 0.5,
+            }),
+            imports: None,
+            api_keys: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn a_burst_is_allowed_then_tokens_refill_over_time() {
+        let limiter = limiter();
+        let start = Instant::now();
+        let take = |at: Duration| limiter.take(RouteGroup::Writes, "client".into(), start + at);
+
+        assert_ok!(take(Duration::ZERO));
+        assert_ok!(take(Duration::ZERO));
+        assert_eq!(Err(Duration::from_secs(2)), take(Duration::ZERO));
+        assert_eq!(Err(Duration::from_secs(1)), take(Duration::from_secs(1)));
+        assert_ok!(take(Duration::from_secs(2)));
+        assert_err!(take(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn clients_and_groups_have_their_own_buckets() {
+        let limiter = limiter();
+        let now = Instant::now();
+        for _ in 0..2 {
+            assert_ok!(limiter.take(RouteGroup::Writes, "a".into(), now));
+        }
+
+        assert_err!(limiter.take(RouteGroup::Writes, "a".into(), now));
+        assert_ok!(limiter.take(RouteGroup::Writes, "b".into(), now));
+        assert_ok!(limiter.take(RouteGroup::Reads, "a".into(), now));
+    }
+
+    #[test]
+    fn requests_are_grouped_by_method_and_path() {
+        let cases = [
+            (Method::GET, "/markings", Some(RouteGroup::Reads)),
+            (Method::POST, "/markings", Some(RouteGroup::Writes)),
+            (Method::DELETE, "/markings/1", Some(RouteGroup::Writes)),
+            (Method::GET, "/markings/bulk", Some(RouteGroup::Reads)),
+            (Method::PUT, "/markings/bulk", Some(RouteGroup::Imports)),
+            (Method::GET, "/health/ready", None),
+            (Method::GET, "/metrics", None),
+        ];
+        for (method, path, group) in cases {
+            assert_eq!(group, RouteGroup::of(&method, path), "{} {}", method, path);
+        }
+    }
+
+    #[test]
+    fn the_least_recently_used_bucket_is_dropped_past_the_cap() {
+        let limiter = limiter();
+        let now = Instant::now();
+        let take = |client: &str| limiter.take(RouteGroup::Writes, client.into(), now);
+        for client in ["idle", "busy", "idle", "busy"] {
+            assert_ok!(take(client));
+        }
+
+        for client in 0..MAX_BUCKETS {
+            assert_ok!(take(&client.to_string()));
+            assert_err!(take("busy"));
+        }
+
+        assert!(limiter.buckets.lock().unwrap().len() <= MAX_BUCKETS + 1);
+        assert_ok!(take("idle"));
+    }
+}
@@ -0,0 +1,994 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use uuid::Uuid;
+
+use crate::domain::{
+    webhook_sink_name, DeadLetter, Marking, MarkingAssignment, MarkingEvent, MarkingEventType,
+    MarkingSource, MarkingStatus, NewMarking, NewWebhook, ObjectRef, OutboxEvent, Webhook,
+    WebhookDelivery,
+};
+use crate::repository::{
+    mirror_event_type, next_date_added, ConnectionUsage, IdempotencyRecord, IdempotencyRepository,
+    MarkingRepository, MarkingTransaction, MigrationStatus, OutboxRepository, RepositoryError,
+    SavedResponse, SchemaRepository, SearchResult, WebhookRepository,
+};
+
+type Markings = HashMap<Uuid, Marking>;
+
+/// Markings, their assignments and their outbox, locked together so that a
+/// write and its event are seen at once.
+#[derive(Default)]
+struct MarkingStore {
+    markings: Markings,
+    assignments: Vec<MarkingAssignment>,
+    outbox: Vec<OutboxEvent>,
+    /// The `date_added` of the last event saved.
+    last_added: Option<DateTime<Utc>>,
+}
+
+impl MarkingStore {
+    fn save_event(&mut self, event_type: MarkingEventType, marking: &Marking) {
+        self.save_marking_event(&MarkingEvent::new(event_type, marking.clone()));
+    }
+
+    /// The sequence of the last event saved, which pruning always keeps.
+    fn last_sequence(&self) -> i64 {
+        self.outbox.last().map_or(0, |event| event.sequence)
+    }
+
+    fn save_marking_event(&mut self, event: &MarkingEvent) {
+        let added = next_date_added(self.last_added);
+        self.last_added = Some(added);
+        if let Some(marking) = self.markings.get_mut(&event.marking.id) {
+            marking.date_added = Some(added);
+        }
+        self.outbox.push(OutboxEvent {
+            sequence: self.last_sequence() + 1,
+            event_id: event.id,
+            event_type: event.event_type.as_str().to_owned(),
+            marking_id: event.marking.id,
+            payload: serde_json::to_string(event).expect("Marking events are serializable"),
+            occurred_at: event.occurred_at,
+        });
+    }
+
+    /// Drops the assignments of markings that were deleted, as the foreign
+    /// key of `marking_assignments` does.
+    fn forget_deleted_assignments(&mut self) {
+        let markings = &self.markings;
+        self.assignments
+            .retain(|assignment| markings.contains_key(&assignment.marking_id));
+    }
+}
+
+struct ClaimedKey {
+    created_at: DateTime<Utc>,
+    locked_until: DateTime<Utc>,
+    record: IdempotencyRecord,
+}
+
+type IdempotencyRecords = HashMap<(String, String, String), ClaimedKey>;
+
+#[derive(Default)]
+struct Webhooks {
+    webhooks: Vec<Webhook>,
+    deliveries: Vec<WebhookDelivery>,
+    dead_letters: Vec<DeadLetter>,
+}
+
+/// Keeps markings in process memory, enforcing the same unique names as the
+/// `markings` table.
+///
+/// Transactions hold an exclusive lock on the whole store until they are
+/// committed or dropped, so a task holding a transaction must not call back
+/// into the repository itself.
+#[derive(Default)]
+pub struct InMemoryMarkingRepository {
+    store: Arc<Mutex<MarkingStore>>,
+    idempotency: Mutex<IdempotencyRecords>,
+    webhooks: Mutex<Webhooks>,
+    outbox_cursors: Mutex<HashMap<String, i64>>,
+    sync_cursors: Mutex<HashMap<(String, String), String>>,
+}
+
+impl InMemoryMarkingRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl MarkingRepository for InMemoryMarkingRepository {
+    #[tracing::instrument(name = "Saving new marking in memory", skip(self, new_marking))]
+    async fn insert_marking(&self, new_marking: &NewMarking) -> Result<Marking, RepositoryError> {
+        let mut store = self.store.lock().await;
+        let marking = insert_marking(&mut store.markings, new_marking)?;
+        store.save_event(MarkingEventType::Created, &marking);
+        Ok(marking)
+    }
+
+    async fn get_marking(&self, id: Uuid) -> Result<Marking, RepositoryError> {
+        self.store
+            .lock()
+            .await
+            .markings
+            .get(&id)
+            .cloned()
+            .ok_or(RepositoryError::NotFound(id))
+    }
+
+    async fn list_markings(&self) -> Result<Vec<Marking>, RepositoryError> {
+        let mut markings: Vec<_> = self.store.lock().await.markings.values().cloned().collect();
+        markings.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(markings)
+    }
+
+    async fn list_added_markings(&self) -> Result<Vec<Marking>, RepositoryError> {
+        let mut markings: Vec<_> = self
+            .store
+            .lock()
+            .await
+            .markings
+            .values()
+            .filter(|marking| marking.date_added.is_some())
+            .cloned()
+            .collect();
+        markings.sort_by_key(|marking| (marking.date_added, marking.id));
+        Ok(markings)
+    }
+
+    async fn count_markings_by_type(&self) -> Result<Vec<(String, i64)>, RepositoryError> {
+        let mut counts = std::collections::BTreeMap::new();
+        for marking in self.store.lock().await.markings.values() {
+            *counts.entry(marking.definition_type.clone()).or_insert(0) += 1;
+        }
+        Ok(counts.into_iter().collect())
+    }
+
+    #[tracing::instrument(name = "Updating marking in memory", skip(self, marking))]
+    async fn update_marking(
+        &self,
+        id: Uuid,
+        marking: &NewMarking,
+        expected_version: Option<i64>,
+    ) -> Result<Marking, RepositoryError> {
+        let mut store = self.store.lock().await;
+        let marking = update_marking(&mut store.markings, id, marking, expected_version)?;
+        store.save_event(MarkingEventType::Updated, &marking);
+        Ok(marking)
+    }
+
+    #[tracing::instrument(name = "Deleting marking from memory", skip(self))]
+    async fn delete_marking(
+        &self,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<(), RepositoryError> {
+        let mut store = self.store.lock().await;
+        let marking = delete_marking(&mut store.markings, id, expected_version)?;
+        store.forget_deleted_assignments();
+        store.save_event(MarkingEventType::Deleted, &marking);
+        Ok(())
+    }
+
+    async fn search_markings(
+        &self,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<SearchResult>, RepositoryError> {
+        let terms = search_terms(query);
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut results: Vec<_> = self
+            .store
+            .lock()
+            .await
+            .markings
+            .values()
+            .filter_map(|marking| search_marking(marking, &terms))
+            .collect();
+        results.sort_by(|a, b| b.rank.total_cmp(&a.rank).then(a.name.cmp(&b.name)));
+        results.truncate(limit.max(0) as usize);
+        Ok(results)
+    }
+
+    #[tracing::instrument(name = "Changing marking status in memory", skip(self))]
+    async fn set_marking_status(
+        &self,
+        id: Uuid,
+        status: MarkingStatus,
+        expected_version: Option<i64>,
+    ) -> Result<Marking, RepositoryError> {
+        let mut store = self.store.lock().await;
+        check_version(&store.markings, id, expected_version)?;
+        let marking = store
+            .markings
+            .get_mut(&id)
+            .ok_or(RepositoryError::NotFound(id))?;
+        if !status.can_follow(&marking.status) {
+            return Err(RepositoryError::WrongStatus(id, marking.status.clone()));
+        }
+        marking.status = status.as_str().to_owned();
+        marking.updated_at = Some(Utc::now());
+        marking.updated_by = Some(Uuid::new_v4());
+        marking.version += 1;
+        let marking = marking.clone();
+        store.save_event(MarkingEventType::of_status(status), &marking);
+        Ok(marking)
+    }
+
+    #[tracing::instrument(name = "Assigning marking in memory", skip(self))]
+    async fn assign_marking(
+        &self,
+        id: Uuid,
+        object_ref: &ObjectRef,
+    ) -> Result<MarkingAssignment, RepositoryError> {
+        let mut store = self.store.lock().await;
+        let marking = store
+            .markings
+            .get(&id)
+            .cloned()
+            .ok_or(RepositoryError::NotFound(id))?;
+        if marking.status == MarkingStatus::Revoked.as_str() {
+            return Err(RepositoryError::WrongStatus(id, marking.status));
+        }
+        let existing = store.assignments.iter().find(|assignment| {
+            assignment.marking_id == id && assignment.object_ref == object_ref.as_ref()
+        });
+        if let Some(assignment) = existing {
+            return Ok(assignment.clone());
+        }
+        let assignment = MarkingAssignment {
+            marking_id: id,
+            object_ref: object_ref.as_ref().to_owned(),
+            assigned_at: Utc::now(),
+        };
+        store.assignments.push(assignment.clone());
+        store.save_marking_event(&MarkingEvent::assigned(marking, &assignment));
+        Ok(assignment)
+    }
+
+    async fn list_assignments(&self, id: Uuid) -> Result<Vec<MarkingAssignment>, RepositoryError> {
+        Ok(self
+            .store
+            .lock()
+            .await
+            .assignments
+            .iter()
+            .filter(|assignment| assignment.marking_id == id)
+            .cloned()
+            .collect())
+    }
+
+    #[tracing::instrument(name = "Mirroring marking in memory", skip(self, marking))]
+    async fn mirror_marking(
+        &self,
+        id: Uuid,
+        marking: &NewMarking,
+        source: &MarkingSource,
+    ) -> Result<Marking, RepositoryError> {
+        let mut store = self.store.lock().await;
+        if let Some(current) = store.markings.get(&id) {
+            let from_source = current.source_server.as_ref() == Some(&source.server)
+                && current.source_collection.as_ref() == Some(&source.collection);
+            if !from_source {
+                return Err(RepositoryError::ForeignMarking(id));
+            }
+        }
+        ensure_name_is_free(&store.markings, marking, Some(id))?;
+        let previous_version = store.markings.get(&id).map(|marking| marking.version);
+        let mirrored = store.markings.entry(id).or_insert_with(|| Marking {
+            id,
+            name: marking.name.as_ref().to_owned(),
+            definition_type: marking.definition_type.as_ref().to_owned(),
+            definition: marking.definition.as_ref().to_owned(),
+            created_at: source.fetched_at,
+            updated_at: None,
+            created_by: Uuid::new_v4(),
+            updated_by: None,
+            version: 1,
+            status: MarkingStatus::Active.as_str().to_owned(),
+            source_server: None,
+            source_collection: None,
+            fetched_at: None,
+            date_added: None,
+        });
+        let changed = mirrored.name != marking.name.as_ref()
+            || mirrored.definition_type != marking.definition_type.as_ref()
+            || mirrored.definition != marking.definition.as_ref();
+        if changed {
+            mirrored.name = marking.name.as_ref().to_owned();
+            mirrored.definition_type = marking.definition_type.as_ref().to_owned();
+            mirrored.definition = marking.definition.as_ref().to_owned();
+            mirrored.updated_at = Some(source.fetched_at);
+            mirrored.updated_by = Some(Uuid::new_v4());
+            mirrored.version += 1;
+        }
+        mirrored.source_server = Some(source.server.clone());
+        mirrored.source_collection = Some(source.collection.clone());
+        mirrored.fetched_at = Some(source.fetched_at);
+        let mirrored = mirrored.clone();
+        if let Some(event_type) = mirror_event_type(previous_version, &mirrored) {
+            store.save_event(event_type, &mirrored);
+        }
+        Ok(mirrored)
+    }
+
+    async fn get_sync_cursor(
+        &self,
+        api_root: &str,
+        collection: &str,
+    ) -> Result<Option<String>, RepositoryError> {
+        Ok(self
+            .sync_cursors
+            .lock()
+            .await
+            .get(&(api_root.to_owned(), collection.to_owned()))
+            .cloned())
+    }
+
+    async fn save_sync_cursor(
+        &self,
+        api_root: &str,
+        collection: &str,
+        added_last: &str,
+    ) -> Result<(), RepositoryError> {
+        self.sync_cursors.lock().await.insert(
+            (api_root.to_owned(), collection.to_owned()),
+            added_last.to_owned(),
+        );
+        Ok(())
+    }
+
+    async fn begin(&self) -> Result<Box<dyn MarkingTransaction>, RepositoryError> {
+        let guard = self.store.clone().lock_owned().await;
+        let pending = guard.markings.clone();
+        Ok(Box::new(InMemoryMarkingTransaction {
+            guard,
+            pending,
+            events: Vec::new(),
+        }))
+    }
+}
+
+/// Memory has no schema: there is never anything to migrate.
+#[async_trait::async_trait]
+impl SchemaRepository for InMemoryMarkingRepository {
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        Ok(())
+    }
+
+    fn connections(&self) -> Option<ConnectionUsage> {
+        None
+    }
+
+    async fn migrate(&self) -> Result<(), RepositoryError> {
+        Ok(())
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, RepositoryError> {
+        Ok(Vec::new())
+    }
+
+    async fn close(&self) {}
+}
+
+#[async_trait::async_trait]
+impl IdempotencyRepository for InMemoryMarkingRepository {
+    async fn claim_idempotency_key(
+        &self,
+        scope: &str,
+        client: &str,
+        key: &str,
+        request_fingerprint: &str,
+        expired_before: DateTime<Utc>,
+        locked_until: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyRecord>, RepositoryError> {
+        let mut records = self.idempotency.lock().await;
+        let id = (scope.to_owned(), client.to_owned(), key.to_owned());
+        let now = Utc::now();
+        match records.get(&id) {
+            Some(claimed)
+                if claimed.created_at >= expired_before
+                    && (claimed.record.response.is_some() || claimed.locked_until >= now) =>
+            {
+                Ok(Some(claimed.record.clone()))
+            }
+            _ => {
+                let record = IdempotencyRecord {
+                    request_fingerprint: request_fingerprint.to_owned(),
+                    response: None,
+                };
+                records.insert(
+                    id,
+                    ClaimedKey {
+                        created_at: now,
+                        locked_until,
+                        record,
+                    },
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    async fn save_idempotent_response(
+        &self,
+        scope: &str,
+        client: &str,
+        key: &str,
+        response: &SavedResponse,
+    ) -> Result<(), RepositoryError> {
+        let mut records = self.idempotency.lock().await;
+        if let Some(claimed) =
+            records.get_mut(&(scope.to_owned(), client.to_owned(), key.to_owned()))
+        {
+            claimed.record.response = Some(response.clone());
+        }
+        Ok(())
+    }
+
+    async fn release_idempotency_key(
+        &self,
+        scope: &str,
+        client: &str,
+        key: &str,
+    ) -> Result<(), RepositoryError> {
+        let mut records = self.idempotency.lock().await;
+        records.remove(&(scope.to_owned(), client.to_owned(), key.to_owned()));
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl WebhookRepository for InMemoryMarkingRepository {
+    async fn insert_webhook(&self, new_webhook: &NewWebhook) -> Result<Webhook, RepositoryError> {
+        let webhook = Webhook {
+            id: Uuid::new_v4(),
+            url: new_webhook.url.clone(),
+            events: new_webhook.events.clone(),
+            secret: new_webhook.secret.clone(),
+            created_at: Utc::now(),
+        };
+        // Held so that no event is saved before the webhook starts from the
+        // last one.
+        let store = self.store.lock().await;
+        self.outbox_cursors
+            .lock()
+            .await
+            .insert(webhook_sink_name(webhook.id), store.last_sequence());
+        self.webhooks.lock().await.webhooks.push(webhook.clone());
+        Ok(webhook)
+    }
+
+    async fn get_webhook(&self, id: Uuid) -> Result<Webhook, RepositoryError> {
+        self.webhooks
+            .lock()
+            .await
+            .webhooks
+            .iter()
+            .find(|webhook| webhook.id == id)
+            .cloned()
+            .ok_or(RepositoryError::WebhookNotFound(id))
+    }
+
+    async fn list_webhooks(&self) -> Result<Vec<Webhook>, RepositoryError> {
+        Ok(self.webhooks.lock().await.webhooks.clone())
+    }
+
+    async fn delete_webhook(&self, id: Uuid) -> Result<(), RepositoryError> {
+        let mut webhooks = self.webhooks.lock().await;
+        if webhooks.webhooks.iter().all(|webhook| webhook.id != id) {
+            return Err(RepositoryError::WebhookNotFound(id));
+        }
+        webhooks.webhooks.retain(|webhook| webhook.id != id);
+        webhooks
+            .deliveries
+            .retain(|delivery| delivery.webhook_id != id);
+        webhooks
+            .dead_letters
+            .retain(|dead_letter| dead_letter.webhook_id != id);
+        Ok(())
+    }
+
+    async fn record_delivery(&self, delivery: &WebhookDelivery) -> Result<(), RepositoryError> {
+        let mut webhooks = self.webhooks.lock().await;
+        if webhooks
+            .webhooks
+            .iter()
+            .any(|webhook| webhook.id == delivery.webhook_id)
+        {
+            webhooks.deliveries.push(delivery.clone());
+        }
+        Ok(())
+    }
+
+    async fn list_deliveries(
+        &self,
+        webhook_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, RepositoryError> {
+        Ok(self
+            .webhooks
+            .lock()
+            .await
+            .deliveries
+            .iter()
+            .rev()
+            .filter(|delivery| delivery.webhook_id == webhook_id)
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_dead_letter(&self, dead_letter: &DeadLetter) -> Result<(), RepositoryError> {
+        let mut webhooks = self.webhooks.lock().await;
+        if webhooks
+            .webhooks
+            .iter()
+            .any(|webhook| webhook.id == dead_letter.webhook_id)
+        {
+            webhooks.dead_letters.push(dead_letter.clone());
+        }
+        Ok(())
+    }
+
+    async fn list_dead_letters(
+        &self,
+        webhook_id: Uuid,
+    ) -> Result<Vec<DeadLetter>, RepositoryError> {
+        Ok(self
+            .webhooks
+            .lock()
+            .await
+            .dead_letters
+            .iter()
+            .rev()
+            .filter(|dead_letter| dead_letter.webhook_id == webhook_id)
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl OutboxRepository for InMemoryMarkingRepository {
+    async fn list_outbox_events(
+        &self,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<OutboxEvent>, RepositoryError> {
+        Ok(self
+            .store
+            .lock()
+            .await
+            .outbox
+            .iter()
+            .filter(|event| event.sequence > after)
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn last_outbox_sequence(&self) -> Result<i64, RepositoryError> {
+        Ok(self.store.lock().await.last_sequence())
+    }
+
+    async fn get_outbox_cursor(&self, sink: &str) -> Result<i64, RepositoryError> {
+        Ok(self
+            .outbox_cursors
+            .lock()
+            .await
+            .get(sink)
+            .copied()
+            .unwrap_or(0))
+    }
+
+    async fn save_outbox_cursor(&self, sink: &str, sequence: i64) -> Result<(), RepositoryError> {
+        let mut cursors = self.outbox_cursors.lock().await;
+        let cursor = cursors.entry(sink.to_owned()).or_default();
+        *cursor = (*cursor).max(sequence);
+        Ok(())
+    }
+
+    async fn prune_outbox_events(&self, before: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        let mut store = self.store.lock().await;
+        let cursors = self.outbox_cursors.lock().await;
+        let webhooks = self.webhooks.lock().await;
+        let bound = cursors
+            .iter()
+            .filter(|(sink, _)| {
+                !sink.starts_with("webhook:")
+                    || webhooks
+                        .webhooks
+                        .iter()
+                        .any(|webhook| **sink == webhook_sink_name(webhook.id))
+            })
+            .map(|(_, sequence)| *sequence)
+            .fold(store.last_sequence(), i64::min);
+        let saved = store.outbox.len();
+        store
+            .outbox
+            .retain(|event| event.occurred_at >= before || event.sequence >= bound);
+        Ok((saved - store.outbox.len()) as u64)
+    }
+}
+
+pub struct InMemoryMarkingTransaction {
+    guard: OwnedMutexGuard<MarkingStore>,
+    pending: Markings,
+    /// Saved to the outbox on commit.
+    events: Vec<(MarkingEventType, Marking)>,
+}
+
+#[async_trait::async_trait]
+impl MarkingTransaction for InMemoryMarkingTransaction {
+    async fn insert_marking(
+        &mut self,
+        new_marking: &NewMarking,
+    ) -> Result<Marking, RepositoryError> {
+        let marking = insert_marking(&mut self.pending, new_marking)?;
+        self.events
+            .push((MarkingEventType::Created, marking.clone()));
+        Ok(marking)
+    }
+
+    async fn update_marking(
+        &mut self,
+        id: Uuid,
+        marking: &NewMarking,
+        expected_version: Option<i64>,
+    ) -> Result<Marking, RepositoryError> {
+        let marking = update_marking(&mut self.pending, id, marking, expected_version)?;
+        self.events
+            .push((MarkingEventType::Updated, marking.clone()));
+        Ok(marking)
+    }
+
+    async fn delete_marking(
+        &mut self,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<(), RepositoryError> {
+        let marking = delete_marking(&mut self.pending, id, expected_version)?;
+        self.events.push((MarkingEventType::Deleted, marking));
+        Ok(())
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), RepositoryError> {
+        let Self {
+            mut guard,
+            pending,
+            events,
+        } = *self;
+        guard.markings = pending;
+        guard.forget_deleted_assignments();
+        for (event_type, marking) in &events {
+            guard.save_event(*event_type, marking);
+        }
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), RepositoryError> {
+        Ok(())
+    }
+}
+
+fn insert_marking(
+    markings: &mut Markings,
+    new_marking: &NewMarking,
+) -> Result<Marking, RepositoryError> {
+    ensure_name_is_free(markings, new_marking, None)?;
+    let marking = Marking {
+        id: Uuid::new_v4(),
+        name: new_marking.name.as_ref().to_owned(),
+        definition_type: new_marking.definition_type.as_ref().to_owned(),
+        definition: new_marking.definition.as_ref().to_owned(),
+        created_at: Utc::now(),
+        updated_at: None,
+        created_by: Uuid::new_v4(),
+        updated_by: None,
+        version: 1,
+        status: MarkingStatus::Active.as_str().to_owned(),
+        source_server: None,
+        source_collection: None,
+        fetched_at: None,
+        date_added: None,
+    };
+    markings.insert(marking.id, marking.clone());
+    Ok(marking)
+}
+
+fn update_marking(
+    markings: &mut Markings,
+    id: Uuid,
+    new_marking: &NewMarking,
+    expected_version: Option<i64>,
+) -> Result<Marking, RepositoryError> {
+    check_version(markings, id, expected_version)?;
+    ensure_name_is_free(markings, new_marking, Some(id))?;
+    let marking = markings.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+    marking.name = new_marking.name.as_ref().to_owned();
+    marking.definition_type = new_marking.definition_type.as_ref().to_owned();
+    marking.definition = new_marking.definition.as_ref().to_owned();
+    marking.updated_at = Some(Utc::now());
+    marking.updated_by = Some(Uuid::new_v4());
+    marking.version += 1;
+    Ok(marking.clone())
+}
+
+fn delete_marking(
+    markings: &mut Markings,
+    id: Uuid,
+    expected_version: Option<i64>,
+) -> Result<Marking, RepositoryError> {
+    check_version(markings, id, expected_version)?;
+    markings.remove(&id).ok_or(RepositoryError::NotFound(id))
+}
+
+fn check_version(
+    markings: &Markings,
+    id: Uuid,
+    expected_version: Option<i64>,
+) -> Result<(), RepositoryError> {
+    let marking = markings.get(&id).ok_or(RepositoryError::NotFound(id))?;
+    match expected_version {
+        Some(version) if version != marking.version => {
+            Err(RepositoryError::VersionMismatch(id, marking.version))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn ensure_name_is_free(
+    markings: &Markings,
+    new_marking: &NewMarking,
+    id: Option<Uuid>,
+) -> Result<(), RepositoryError> {
+    let name = new_marking.name.as_ref();
+    let is_taken = markings
+        .values()
+        .any(|marking| marking.name == name && Some(marking.id) != id);
+    if is_taken {
+        return Err(RepositoryError::DuplicateName(name.to_owned()));
+    }
+    Ok(())
+}
+
+fn search_terms(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Matches markings whose name or definition contains every term, ranking
+/// name hits above definition hits like the weighted Postgres search vector.
+fn search_marking(marking: &Marking, terms: &[String]) -> Option<SearchResult> {
+    let name_words = search_terms(&marking.name);
+    let definition_words = search_terms(&marking.definition);
+
+    let mut rank = 0.0;
+    for term in terms {
+        let in_name = name_words.contains(term);
+        let in_definition = definition_words.contains(term);
+        if !in_name && !in_definition {
+            return None;
+        }
+        rank += if in_name { 1.0 } else { 0.4 };
+    }
+
+    Some(SearchResult {
+        id: marking.id,
+        name: marking.name.clone(),
+        definition_type: marking.definition_type.clone(),
+        definition: marking.definition.clone(),
+        rank: rank / terms.len() as f32,
+        snippet: highlight(&marking.definition, terms),
+    })
+}
+
+fn highlight(text: &str, terms: &[String]) -> String {
+    let mut snippet = String::with_capacity(text.len());
+    let mut word = String::new();
+    for c in text.chars().chain(std::iter::once('\0')) {
+        if c.is_alphanumeric() {
+            word.push(c);
+            continue;
+        }
+        if terms.contains(&word.to_lowercase()) {
+            snippet.push_str("<mark>");
+            snippet.push_str(&word);
+            snippet.push_str("</mark>");
+        } else {
+            snippet.push_str(&word);
+        }
+        word.clear();
+        if c != '\0' {
+            snippet.push(c);
+        }
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::{MarkingDefinition, MarkingDefinitionType, MarkingName, NewMarking};
+    use crate::repository::{
+        IdempotencyRepository, InMemoryMarkingRepository, MarkingRepository, RepositoryError,
+    };
+    use chrono::{Duration, Utc};
+    use claim::{assert_err, assert_none, assert_ok, assert_some};
+
+    fn new_marking(name: &str, definition: &str) -> NewMarking {
+        NewMarking {
+            name: MarkingName::parse(name.into()).unwrap(),
+            definition_type: MarkingDefinitionType::parse("statement".into()).unwrap(),
+            definition: MarkingDefinition::parse(definition.into()).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn duplicate_names_are_rejected() {
+        let repository = InMemoryMarkingRepository::new();
+        assert_ok!(repository.insert_marking(&new_marking("foo", "Foo")).await);
+
+        let result = repository.insert_marking(&new_marking("foo", "Bar")).await;
+        assert!(matches!(result, Err(RepositoryError::DuplicateName(_))));
+    }
+
+    #[tokio::test]
+    async fn renaming_onto_an_existing_name_is_rejected() {
+        let repository = InMemoryMarkingRepository::new();
+        repository
+            .insert_marking(&new_marking("foo", "Foo"))
+            .await
+            .unwrap();
+        let bar = repository
+            .insert_marking(&new_marking("bar", "Bar"))
+            .await
+            .unwrap();
+
+        assert_err!(
+            repository
+                .update_marking(bar.id, &new_marking("foo", "Bar"), None)
+                .await
+        );
+        assert_ok!(
+            repository
+                .update_marking(bar.id, &new_marking("bar", "Baz"), None)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_against_a_stale_version_are_rejected() {
+        let repository = InMemoryMarkingRepository::new();
+        let foo = repository
+            .insert_marking(&new_marking("foo", "Foo"))
+            .await
+            .unwrap();
+        let updated = repository
+            .update_marking(foo.id, &new_marking("foo", "Bar"), Some(1))
+            .await
+            .unwrap();
+        assert_eq!(2, updated.version);
+
+        let result = repository
+            .update_marking(foo.id, &new_marking("foo", "Baz"), Some(1))
+            .await;
+        assert!(matches!(
+            result,
+            Err(RepositoryError::VersionMismatch(_, 2))
+        ));
+        assert_err!(repository.delete_marking(foo.id, Some(1)).await);
+        assert_ok!(repository.delete_marking(foo.id, Some(2)).await);
+    }
+
+    #[tokio::test]
+    async fn a_rolled_back_transaction_leaves_no_trace() {
+        let repository = InMemoryMarkingRepository::new();
+
+        let mut transaction = repository.begin().await.unwrap();
+        transaction
+            .insert_marking(&new_marking("foo", "Foo"))
+            .await
+            .unwrap();
+        transaction.rollback().await.unwrap();
+
+        assert!(repository.list_markings().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_committed_transaction_is_visible() {
+        let repository = InMemoryMarkingRepository::new();
+
+        let mut transaction = repository.begin().await.unwrap();
+        transaction
+            .insert_marking(&new_marking("foo", "Foo"))
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+
+        assert_eq!(1, repository.list_markings().await.unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn search_highlights_matching_terms() {
+        let repository = InMemoryMarkingRepository::new();
+        repository
+            .insert_marking(&new_marking("copyright_vendor_x", "Copyright Vendor X."))
+            .await
+            .unwrap();
+        repository
+            .insert_marking(&new_marking("copyright_vendor_y", "Copyright Vendor Y."))
+            .await
+            .unwrap();
+
+        let results = repository.search_markings("vendor x", 10).await.unwrap();
+
+        assert_eq!(1, results.len());
+        assert_eq!(
+            "Copyright <mark>Vendor</mark> <mark>X</mark>.",
+            results[0].snippet
+        );
+    }
+
+    #[tokio::test]
+    async fn idempotency_keys_held_past_their_lease_can_be_claimed_again() {
+        let repository = InMemoryMarkingRepository::new();
+        let now = Utc::now();
+        let expired_before = now - Duration::hours(24);
+
+        assert_none!(repository
+            .claim_idempotency_key(
+                "markings",
+                "client",
+                "key",
+                "abc",
+                expired_before,
+                now + Duration::seconds(60)
+            )
+            .await
+            .unwrap());
+        let claimed = repository
+            .claim_idempotency_key(
+                "markings",
+                "client",
+                "key",
+                "abc",
+                expired_before,
+                now + Duration::seconds(60),
+            )
+            .await
+            .unwrap();
+        assert_none!(assert_some!(claimed).response);
+
+        repository
+            .claim_idempotency_key(
+                "markings",
+                "client",
+                "other",
+                "abc",
+                expired_before,
+                now - Duration::seconds(1),
+            )
+            .await
+            .unwrap();
+        assert_none!(repository
+            .claim_idempotency_key(
+                "markings",
+                "client",
+                "other",
+                "abc",
+                expired_before,
+                now + Duration::seconds(60)
+            )
+            .await
+            .unwrap());
+    }
+}
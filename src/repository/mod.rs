@@ -0,0 +1,422 @@
+mod memory;
+mod postgres;
+mod sqlite;
+
+pub use memory::InMemoryMarkingRepository;
+pub use postgres::PostgresMarkingRepository;
+pub use sqlite::SqliteMarkingRepository;
+
+use crate::domain::{
+    DeadLetter, Marking, MarkingAssignment, MarkingEventType, MarkingSource, MarkingStatus,
+    NewMarking, NewWebhook, ObjectRef, OutboxEvent, Webhook, WebhookDelivery,
+};
+use chrono::{DateTime, SubsecRound, Utc};
+use sqlx::migrate::{AppliedMigration, Migrator};
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[derive(thiserror::Error, Debug)]
+pub enum RepositoryError {
+    #[error("A marking named {0} already exists.")]
+    DuplicateName(String),
+    #[error("Marking {0} does not exist.")]
+    NotFound(Uuid),
+    #[error("Webhook {0} does not exist.")]
+    WebhookNotFound(Uuid),
+    #[error("Marking {0} has been modified, it is now at version {1}.")]
+    VersionMismatch(Uuid, i64),
+    #[error("Marking {0} was not pulled from this source.")]
+    ForeignMarking(Uuid),
+    #[error("Marking {0} is {1}.")]
+    WrongStatus(Uuid, String),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+#[derive(Debug, serde::Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct SearchResult {
+    #[schema(value_type = String, format = "uuid")]
+    pub id: Uuid,
+    pub name: String,
+    pub definition_type: String,
+    pub definition: String,
+    pub rank: f32,
+    pub snippet: String,
+}
+
+/// Storage for markings, independent of the database backing it.
+///
+/// Every write also saves its `MarkingEvent` to the outbox, atomically with
+/// the write itself: see `OutboxRepository`.
+#[async_trait::async_trait]
+pub trait MarkingRepository: Send + Sync {
+    async fn insert_marking(&self, new_marking: &NewMarking) -> Result<Marking, RepositoryError>;
+    async fn get_marking(&self, id: Uuid) -> Result<Marking, RepositoryError>;
+    async fn list_markings(&self) -> Result<Vec<Marking>, RepositoryError>;
+    /// The markings whose current version was added to the outbox, by
+    /// `date_added` then id. A version added later is never dated before one
+    /// already listed, so a client paging by `date_added` misses none.
+    async fn list_added_markings(&self) -> Result<Vec<Marking>, RepositoryError>;
+    /// Counts the markings of each definition type, by definition type.
+    async fn count_markings_by_type(&self) -> Result<Vec<(String, i64)>, RepositoryError>;
+    /// Replaces a marking. When `expected_version` is set, the write only
+    /// happens if the stored marking is still at that version.
+    async fn update_marking(
+        &self,
+        id: Uuid,
+        marking: &NewMarking,
+        expected_version: Option<i64>,
+    ) -> Result<Marking, RepositoryError>;
+    async fn delete_marking(
+        &self,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<(), RepositoryError>;
+    async fn search_markings(
+        &self,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<SearchResult>, RepositoryError>;
+    /// Moves marking `id` to `status`, if `MarkingStatus::can_follow` lets
+    /// it, and returns `WrongStatus` otherwise.
+    async fn set_marking_status(
+        &self,
+        id: Uuid,
+        status: MarkingStatus,
+        expected_version: Option<i64>,
+    ) -> Result<Marking, RepositoryError>;
+    /// Assigns marking `id` to `object_ref`, unless it already is. Revoked
+    /// markings cannot be assigned.
+    async fn assign_marking(
+        &self,
+        id: Uuid,
+        object_ref: &ObjectRef,
+    ) -> Result<MarkingAssignment, RepositoryError>;
+    /// The objects marking `id` is assigned to, first assigned first.
+    async fn list_assignments(&self, id: Uuid) -> Result<Vec<MarkingAssignment>, RepositoryError>;
+    /// Creates or replaces marking `id` with a copy pulled from `source`. Its
+    /// version is only bumped when its content changed.
+    ///
+    /// Markings created locally, or pulled from another source, are left
+    /// alone and `ForeignMarking` is returned.
+    async fn mirror_marking(
+        &self,
+        id: Uuid,
+        marking: &NewMarking,
+        source: &MarkingSource,
+    ) -> Result<Marking, RepositoryError>;
+    /// The date the last marking definition pulled from `collection` of
+    /// `api_root` was added, as its server gave it, where the next pull
+    /// resumes. `None` before the first pull.
+    async fn get_sync_cursor(
+        &self,
+        api_root: &str,
+        collection: &str,
+    ) -> Result<Option<String>, RepositoryError>;
+    async fn save_sync_cursor(
+        &self,
+        api_root: &str,
+        collection: &str,
+        added_last: &str,
+    ) -> Result<(), RepositoryError>;
+
+    /// Starts a transaction: nothing written through it is visible to other
+    /// callers until it is committed, and dropping it rolls everything back.
+    async fn begin(&self) -> Result<Box<dyn MarkingTransaction>, RepositoryError>;
+}
+
+#[async_trait::async_trait]
+pub trait MarkingTransaction: Send {
+    async fn insert_marking(
+        &mut self,
+        new_marking: &NewMarking,
+    ) -> Result<Marking, RepositoryError>;
+    async fn update_marking(
+        &mut self,
+        id: Uuid,
+        marking: &NewMarking,
+        expected_version: Option<i64>,
+    ) -> Result<Marking, RepositoryError>;
+    async fn delete_marking(
+        &mut self,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<(), RepositoryError>;
+    async fn commit(self: Box<Self>) -> Result<(), RepositoryError>;
+    async fn rollback(self: Box<Self>) -> Result<(), RepositoryError>;
+}
+
+/// A response recorded against an idempotency key, replayed on retries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IdempotencyRecord {
+    pub request_fingerprint: String,
+    /// `None` while the first request carrying the key is still running.
+    pub response: Option<SavedResponse>,
+}
+
+#[async_trait::async_trait]
+pub trait IdempotencyRepository: Send + Sync {
+    /// Claims the `key` of `client` within `scope` for a new request until
+    /// `locked_until`, forgetting any record created before `expired_before`.
+    /// Clients have keys of their own, so that one cannot replay or block
+    /// the requests of another by guessing its keys.
+    ///
+    /// Returns `None` when the key was free and now belongs to the caller,
+    /// or the existing record when another request already claimed it. A
+    /// claim still without a response once its lease ran out was abandoned,
+    /// say by a request dropped mid-way, and is handed to the caller.
+    async fn claim_idempotency_key(
+        &self,
+        scope: &str,
+        client: &str,
+        key: &str,
+        request_fingerprint: &str,
+        expired_before: DateTime<Utc>,
+        locked_until: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyRecord>, RepositoryError>;
+    async fn save_idempotent_response(
+        &self,
+        scope: &str,
+        client: &str,
+        key: &str,
+        response: &SavedResponse,
+    ) -> Result<(), RepositoryError>;
+    /// Frees a claimed key whose request failed, so that a retry runs again.
+    async fn release_idempotency_key(
+        &self,
+        scope: &str,
+        client: &str,
+        key: &str,
+    ) -> Result<(), RepositoryError>;
+}
+
+/// The marking events saved by every write, and how far each sink got in
+/// publishing them.
+#[async_trait::async_trait]
+pub trait OutboxRepository: Send + Sync {
+    /// At most `limit` events after event `after`, in the order they were
+    /// committed. Events still being written are left for a later call.
+    async fn list_outbox_events(
+        &self,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<OutboxEvent>, RepositoryError>;
+    /// The sequence of the last event saved, 0 when there is none.
+    async fn last_outbox_sequence(&self) -> Result<i64, RepositoryError>;
+    /// The sequence of the last event `sink` published, 0 when it has not
+    /// published any.
+    async fn get_outbox_cursor(&self, sink: &str) -> Result<i64, RepositoryError>;
+    /// Moves the cursor of `sink` to `sequence`, unless it is already past
+    /// it: a replica lagging behind never moves a cursor back.
+    async fn save_outbox_cursor(&self, sink: &str, sequence: i64) -> Result<(), RepositoryError>;
+    /// Deletes the events saved before `before` that every sink has
+    /// published, as told by the cursors of the configured sinks and of the
+    /// webhooks still registered, returning how many. The last event is
+    /// kept, so that sequences carry on after it.
+    async fn prune_outbox_events(&self, before: DateTime<Utc>) -> Result<u64, RepositoryError>;
+}
+
+/// The `date_added` of the next version added to the outbox: now, to the
+/// millisecond, unless that would not be after the `last` one.
+fn next_date_added(last: Option<DateTime<Utc>>) -> DateTime<Utc> {
+    let now = Utc::now().trunc_subsecs(3);
+    last.map_or(now, |last| {
+        now.max(last + chrono::Duration::milliseconds(1))
+    })
+}
+
+/// The event of a mirrored marking: `None` when the copy pulled left it as
+/// it was at `previous_version`.
+fn mirror_event_type(
+    previous_version: Option<i64>,
+    mirrored: &Marking,
+) -> Option<MarkingEventType> {
+    match previous_version {
+        None => Some(MarkingEventType::Created),
+        Some(version) if version != mirrored.version => Some(MarkingEventType::Updated),
+        Some(_) => None,
+    }
+}
+
+/// Webhooks, and what became of the events posted to them.
+#[async_trait::async_trait]
+pub trait WebhookRepository: Send + Sync {
+    async fn insert_webhook(&self, new_webhook: &NewWebhook) -> Result<Webhook, RepositoryError>;
+    async fn get_webhook(&self, id: Uuid) -> Result<Webhook, RepositoryError>;
+    async fn list_webhooks(&self) -> Result<Vec<Webhook>, RepositoryError>;
+    /// Deletes a webhook along with its deliveries and dead letters.
+    async fn delete_webhook(&self, id: Uuid) -> Result<(), RepositoryError>;
+    async fn record_delivery(&self, delivery: &WebhookDelivery) -> Result<(), RepositoryError>;
+    /// The last `limit` deliveries to webhook `webhook_id`, latest first.
+    async fn list_deliveries(
+        &self,
+        webhook_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, RepositoryError>;
+    async fn insert_dead_letter(&self, dead_letter: &DeadLetter) -> Result<(), RepositoryError>;
+    /// The dead letters of webhook `webhook_id`, latest first.
+    async fn list_dead_letters(&self, webhook_id: Uuid)
+        -> Result<Vec<DeadLetter>, RepositoryError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// Applied, but the migration shipped with this build has since changed.
+    Modified,
+    /// Applied by another build, most likely a newer one.
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, utoipa::ToSchema)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: Option<String>,
+    pub state: MigrationState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionUsage {
+    pub open: u32,
+    pub idle: u32,
+}
+
+#[async_trait::async_trait]
+pub trait SchemaRepository: Send + Sync {
+    /// Checks that the store can be reached.
+    async fn ping(&self) -> Result<(), RepositoryError>;
+    /// Reports the connections held by the store, if it uses any.
+    fn connections(&self) -> Option<ConnectionUsage>;
+    /// Applies the pending migrations. Concurrent callers, including other
+    /// replicas, wait on a database lock so that only one of them runs them.
+    async fn migrate(&self) -> Result<(), RepositoryError>;
+    /// Lists the migrations of this build and of the database, by version.
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, RepositoryError>;
+    /// Waits for the connections in use to be released, then closes them all.
+    async fn close(&self);
+}
+
+fn migration_status(migrator: &Migrator, applied: Vec<AppliedMigration>) -> Vec<MigrationStatus> {
+    let mut statuses: Vec<_> = migrator
+        .iter()
+        .map(|migration| {
+            let state = match applied.iter().find(|a| a.version == migration.version) {
+                Some(a) if a.checksum == migration.checksum => MigrationState::Applied,
+                Some(_) => MigrationState::Modified,
+                None => MigrationState::Pending,
+            };
+            MigrationStatus {
+                version: migration.version,
+                description: Some(migration.description.to_string()),
+                state,
+            }
+        })
+        .collect();
+    statuses.extend(
+        applied
+            .iter()
+            .filter(|a| migrator.iter().all(|m| m.version != a.version))
+            .map(|a| MigrationStatus {
+                version: a.version,
+                description: None,
+                state: MigrationState::Unknown,
+            }),
+    );
+    statuses.sort_by_key(|status| status.version);
+    statuses
+}
+
+/// Every repository the application uses, all backed by the same store.
+#[derive(Clone)]
+pub struct Storage {
+    pub markings: Arc<dyn MarkingRepository>,
+    pub idempotency: Arc<dyn IdempotencyRepository>,
+    pub webhooks: Arc<dyn WebhookRepository>,
+    pub outbox: Arc<dyn OutboxRepository>,
+    pub schema: Arc<dyn SchemaRepository>,
+}
+
+impl Storage {
+    pub fn new<R>(repository: R) -> Self
+    where
+        R: MarkingRepository
+            + IdempotencyRepository
+            + WebhookRepository
+            + OutboxRepository
+            + SchemaRepository
+            + 'static,
+    {
+        let repository = Arc::new(repository);
+        Self {
+            markings: repository.clone(),
+            idempotency: repository.clone(),
+            webhooks: repository.clone(),
+            outbox: repository.clone(),
+            schema: repository,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repository::{migration_status, MigrationState};
+    use sqlx::migrate::{AppliedMigration, Migration, MigrationType, Migrator};
+    use std::borrow::Cow;
+
+    fn migration(version: i64, sql: &'static str) -> Migration {
+        Migration::new(
+            version,
+            Cow::Borrowed("migration"),
+            MigrationType::Simple,
+            Cow::Borrowed(sql),
+        )
+    }
+
+    fn applied(migration: &Migration) -> AppliedMigration {
+        AppliedMigration {
+            version: migration.version,
+            checksum: migration.checksum.clone(),
+        }
+    }
+
+    #[test]
+    fn migrations_are_compared_by_version_and_checksum() {
+        let migrator = Migrator {
+            migrations: Cow::Owned(vec![
+                migration(1, "SELECT 1"),
+                migration(2, "SELECT 2"),
+                migration(3, "SELECT 3"),
+            ]),
+            ignore_missing: false,
+        };
+        let applied = vec![
+            applied(&migration(1, "SELECT 1")),
+            applied(&migration(2, "SELECT 'changed'")),
+            applied(&migration(4, "SELECT 4")),
+        ];
+
+        let states: Vec<_> = migration_status(&migrator, applied)
+            .into_iter()
+            .map(|status| (status.version, status.state))
+            .collect();
+
+        assert_eq!(
+            vec![
+                (1, MigrationState::Applied),
+                (2, MigrationState::Modified),
+                (3, MigrationState::Pending),
+                (4, MigrationState::Unknown),
+            ],
+            states
+        );
+    }
+}
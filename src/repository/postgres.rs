@@ -0,0 +1,1166 @@
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::{
+    webhook_sink_name, DeadLetter, Marking, MarkingAssignment, MarkingEvent, MarkingEventType,
+    MarkingSource, MarkingStatus, NewMarking, NewWebhook, ObjectRef, OutboxEvent, Webhook,
+    WebhookDelivery,
+};
+use crate::repository::{
+    migration_status, mirror_event_type, ConnectionUsage, IdempotencyRecord, IdempotencyRepository,
+    MarkingRepository, MarkingTransaction, MigrationStatus, OutboxRepository, RepositoryError,
+    SavedResponse, SchemaRepository, SearchResult, WebhookRepository,
+};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Key of the advisory lock readers of the outbox take turns on, see
+/// `sequence_committed_events`: `metaman` in ASCII.
+const OUTBOX_LOCK: i64 = 0x006d_6574_616d_616e;
+
+pub struct PostgresMarkingRepository {
+    pool: PgPool,
+}
+
+impl PostgresMarkingRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Gives the events committed since the last call their sequence, after
+    /// the sequences already given, in the order the events were saved.
+    ///
+    /// Marking writers do not take turns, so their events are saved with ids
+    /// in one order and committed in another: a reader going by ids could
+    /// read past an event whose transaction has yet to commit, and never
+    /// publish it. Sequences are only given to committed events, by one
+    /// reader at a time, so none is ever given below one already read. This
+    /// costs readers a short transaction per read and leaves writers alone,
+    /// and nothing but a look at `outbox_unsequenced_idx` while no event is
+    /// waiting for its sequence.
+    async fn sequence_committed_events(&self) -> Result<(), RepositoryError> {
+        let pending = sqlx::query!(
+            r#"SELECT EXISTS (SELECT 1 FROM outbox WHERE sequence IS NULL) AS "pending!""#
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(log_error)?;
+        if !pending.pending {
+            return Ok(());
+        }
+        let mut transaction = self.pool.begin().await.map_err(log_error)?;
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(OUTBOX_LOCK)
+            .execute(&mut transaction)
+            .await
+            .map_err(log_error)?;
+        // Taken once the lock is held, the snapshot of this statement sees
+        // the sequences given by the previous reader. The markings of the
+        // events are added along with them, after every marking added before.
+        sqlx::query!(
+            r#"
+            WITH sequenced AS (
+                UPDATE outbox
+                SET sequence = previous.last + pending.rank, added_at = previous.added_at
+                FROM (
+                    SELECT id, ROW_NUMBER() OVER (ORDER BY id) AS rank
+                    FROM outbox
+                    WHERE sequence IS NULL
+                ) AS pending, (
+                    SELECT COALESCE(MAX(sequence), 0) AS last,
+                        GREATEST(
+                            date_trunc('milliseconds', clock_timestamp()),
+                            MAX(added_at) + INTERVAL '1 millisecond'
+                        ) AS added_at
+                    FROM outbox
+                ) AS previous
+                WHERE outbox.id = pending.id
+                RETURNING outbox.marking_id, outbox.added_at
+            )
+            UPDATE markings
+            SET date_added = sequenced.added_at
+            FROM sequenced
+            WHERE markings.id = sequenced.marking_id
+            "#
+        )
+        .execute(&mut transaction)
+        .await
+        .map_err(log_error)?;
+        transaction.commit().await.map_err(log_error)?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl MarkingRepository for PostgresMarkingRepository {
+    #[tracing::instrument(name = "Saving new marking in the database", skip(self, new_marking))]
+    async fn insert_marking(&self, new_marking: &NewMarking) -> Result<Marking, RepositoryError> {
+        let mut transaction = self.pool.begin().await.map_err(log_error)?;
+        let marking = insert_marking(&mut transaction, new_marking).await?;
+        transaction.commit().await.map_err(log_error)?;
+        Ok(marking)
+    }
+
+    #[tracing::instrument(name = "Fetching marking from the database", skip(self))]
+    async fn get_marking(&self, id: Uuid) -> Result<Marking, RepositoryError> {
+        sqlx::query_as!(
+            Marking,
+            r#"
+            SELECT id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+                source_server, source_collection, fetched_at, status, date_added
+            FROM markings
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(log_error)?
+        .ok_or(RepositoryError::NotFound(id))
+    }
+
+    #[tracing::instrument(name = "Listing markings from the database", skip(self))]
+    async fn list_markings(&self) -> Result<Vec<Marking>, RepositoryError> {
+        let markings = sqlx::query_as!(
+            Marking,
+            r#"
+            SELECT id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+                source_server, source_collection, fetched_at, status, date_added
+            FROM markings
+            ORDER BY name
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(markings)
+    }
+
+    #[tracing::instrument(name = "Listing added markings from the database", skip(self))]
+    async fn list_added_markings(&self) -> Result<Vec<Marking>, RepositoryError> {
+        self.sequence_committed_events().await?;
+        let markings = sqlx::query_as!(
+            Marking,
+            r#"
+            SELECT id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+                source_server, source_collection, fetched_at, status, date_added
+            FROM markings
+            WHERE date_added IS NOT NULL
+            ORDER BY date_added, id
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(markings)
+    }
+
+    async fn count_markings_by_type(&self) -> Result<Vec<(String, i64)>, RepositoryError> {
+        let counts = sqlx::query!(
+            r#"
+            SELECT definition_type, COUNT(*) AS "count!"
+            FROM markings
+            GROUP BY definition_type
+            ORDER BY definition_type
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(counts
+            .into_iter()
+            .map(|row| (row.definition_type, row.count))
+            .collect())
+    }
+
+    #[tracing::instrument(name = "Updating marking in the database", skip(self, marking))]
+    async fn update_marking(
+        &self,
+        id: Uuid,
+        marking: &NewMarking,
+        expected_version: Option<i64>,
+    ) -> Result<Marking, RepositoryError> {
+        let mut transaction = self.pool.begin().await.map_err(log_error)?;
+        let marking = update_marking(&mut transaction, id, marking, expected_version).await?;
+        transaction.commit().await.map_err(log_error)?;
+        Ok(marking)
+    }
+
+    #[tracing::instrument(name = "Deleting marking from the database", skip(self))]
+    async fn delete_marking(
+        &self,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<(), RepositoryError> {
+        let mut transaction = self.pool.begin().await.map_err(log_error)?;
+        delete_marking(&mut transaction, id, expected_version).await?;
+        transaction.commit().await.map_err(log_error)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Searching markings in the database", skip(self))]
+    async fn search_markings(
+        &self,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<SearchResult>, RepositoryError> {
+        let results = sqlx::query_as!(
+            SearchResult,
+            r#"
+            SELECT id, name, definition_type, definition,
+                ts_rank(search, query) AS "rank!",
+                ts_headline(
+                    'english', definition, query,
+                    'StartSel=<mark>, StopSel=</mark>, MaxFragments=2'
+                ) AS "snippet!"
+            FROM markings, websearch_to_tsquery('english', $1) query
+            WHERE search @@ query
+            ORDER BY 5 DESC, name
+            LIMIT $2
+            "#,
+            query,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(results)
+    }
+
+    #[tracing::instrument(name = "Changing marking status in the database", skip(self))]
+    async fn set_marking_status(
+        &self,
+        id: Uuid,
+        status: MarkingStatus,
+        expected_version: Option<i64>,
+    ) -> Result<Marking, RepositoryError> {
+        let mut transaction = self.pool.begin().await.map_err(log_error)?;
+        // The statuses left out are those `MarkingStatus::can_follow` refuses.
+        let updated = sqlx::query_as!(
+            Marking,
+            r#"
+            UPDATE markings
+            SET status = $2, updated_at = $3, updated_by = $4, version = version + 1
+            WHERE id = $1 AND ($5::BIGINT IS NULL OR version = $5)
+                AND status NOT IN ($2, 'revoked')
+            RETURNING id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+                source_server, source_collection, fetched_at, status, date_added
+            "#,
+            id,
+            status.as_str(),
+            Utc::now(),
+            Uuid::new_v4(),
+            expected_version
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .map_err(log_error)?;
+        let marking = match updated {
+            Some(marking) => marking,
+            None => return Err(status_unchanged(&mut transaction, id, expected_version).await),
+        };
+        save_event(
+            &mut transaction,
+            MarkingEventType::of_status(status),
+            &marking,
+        )
+        .await?;
+        transaction.commit().await.map_err(log_error)?;
+        Ok(marking)
+    }
+
+    #[tracing::instrument(name = "Assigning marking in the database", skip(self))]
+    async fn assign_marking(
+        &self,
+        id: Uuid,
+        object_ref: &ObjectRef,
+    ) -> Result<MarkingAssignment, RepositoryError> {
+        let mut transaction = self.pool.begin().await.map_err(log_error)?;
+        // Locked so that it cannot be revoked before the assignment commits.
+        let marking = sqlx::query_as!(
+            Marking,
+            r#"
+            SELECT id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+                source_server, source_collection, fetched_at, status, date_added
+            FROM markings
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            id
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .map_err(log_error)?
+        .ok_or(RepositoryError::NotFound(id))?;
+        if marking.status == MarkingStatus::Revoked.as_str() {
+            return Err(RepositoryError::WrongStatus(id, marking.status));
+        }
+        let assigned = sqlx::query_as!(
+            MarkingAssignment,
+            r#"
+            INSERT INTO marking_assignments (marking_id, object_ref, assigned_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (marking_id, object_ref) DO NOTHING
+            RETURNING marking_id, object_ref, assigned_at
+            "#,
+            id,
+            object_ref.as_ref(),
+            Utc::now()
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .map_err(log_error)?;
+        let assignment = match assigned {
+            Some(assignment) => {
+                save_marking_event(
+                    &mut transaction,
+                    &MarkingEvent::assigned(marking, &assignment),
+                )
+                .await?;
+                assignment
+            }
+            None => sqlx::query_as!(
+                MarkingAssignment,
+                r#"
+                SELECT marking_id, object_ref, assigned_at
+                FROM marking_assignments
+                WHERE marking_id = $1 AND object_ref = $2
+                "#,
+                id,
+                object_ref.as_ref()
+            )
+            .fetch_one(&mut transaction)
+            .await
+            .map_err(log_error)?,
+        };
+        transaction.commit().await.map_err(log_error)?;
+        Ok(assignment)
+    }
+
+    #[tracing::instrument(name = "Listing marking assignments from the database", skip(self))]
+    async fn list_assignments(&self, id: Uuid) -> Result<Vec<MarkingAssignment>, RepositoryError> {
+        let assignments = sqlx::query_as!(
+            MarkingAssignment,
+            r#"
+            SELECT marking_id, object_ref, assigned_at
+            FROM marking_assignments
+            WHERE marking_id = $1
+            ORDER BY assigned_at, object_ref
+            "#,
+            id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(assignments)
+    }
+
+    #[tracing::instrument(name = "Mirroring marking in the database", skip(self, marking))]
+    async fn mirror_marking(
+        &self,
+        id: Uuid,
+        marking: &NewMarking,
+        source: &MarkingSource,
+    ) -> Result<Marking, RepositoryError> {
+        let mut transaction = self.pool.begin().await.map_err(log_error)?;
+        let previous_version = sqlx::query!("SELECT version FROM markings WHERE id = $1", id)
+            .fetch_optional(&mut transaction)
+            .await
+            .map_err(log_error)?
+            .map(|row| row.version);
+        let mirrored = sqlx::query_as!(
+            Marking,
+            r#"
+            INSERT INTO markings (
+                id, name, definition_type, definition, created_at, created_by,
+                source_server, source_collection, fetched_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $5)
+            ON CONFLICT (id) DO UPDATE
+            SET name = EXCLUDED.name, definition_type = EXCLUDED.definition_type,
+                definition = EXCLUDED.definition,
+                updated_at = CASE WHEN (markings.name, markings.definition_type, markings.definition)
+                    <> (EXCLUDED.name, EXCLUDED.definition_type, EXCLUDED.definition)
+                    THEN EXCLUDED.created_at ELSE markings.updated_at END,
+                updated_by = CASE WHEN (markings.name, markings.definition_type, markings.definition)
+                    <> (EXCLUDED.name, EXCLUDED.definition_type, EXCLUDED.definition)
+                    THEN EXCLUDED.created_by ELSE markings.updated_by END,
+                version = CASE WHEN (markings.name, markings.definition_type, markings.definition)
+                    <> (EXCLUDED.name, EXCLUDED.definition_type, EXCLUDED.definition)
+                    THEN markings.version + 1 ELSE markings.version END,
+                source_server = EXCLUDED.source_server,
+                source_collection = EXCLUDED.source_collection,
+                fetched_at = EXCLUDED.fetched_at
+            WHERE markings.source_server = EXCLUDED.source_server
+                AND markings.source_collection = EXCLUDED.source_collection
+            RETURNING id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+                source_server, source_collection, fetched_at, status, date_added
+            "#,
+            id,
+            marking.name.as_ref(),
+            marking.definition_type.as_ref(),
+            marking.definition.as_ref(),
+            source.fetched_at,
+            Uuid::new_v4(),
+            source.server,
+            source.collection
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .map_err(|e| map_write_error(e, marking))?
+        .ok_or(RepositoryError::ForeignMarking(id))?;
+        if let Some(event_type) = mirror_event_type(previous_version, &mirrored) {
+            save_event(&mut transaction, event_type, &mirrored).await?;
+        }
+        transaction.commit().await.map_err(log_error)?;
+        Ok(mirrored)
+    }
+
+    async fn get_sync_cursor(
+        &self,
+        api_root: &str,
+        collection: &str,
+    ) -> Result<Option<String>, RepositoryError> {
+        let cursor = sqlx::query!(
+            "SELECT added_last FROM taxii_sync_cursors WHERE api_root = $1 AND collection = $2",
+            api_root,
+            collection
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(cursor.map(|row| row.added_last))
+    }
+
+    async fn save_sync_cursor(
+        &self,
+        api_root: &str,
+        collection: &str,
+        added_last: &str,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO taxii_sync_cursors (api_root, collection, added_last, updated_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (api_root, collection) DO UPDATE
+            SET added_last = EXCLUDED.added_last, updated_at = EXCLUDED.updated_at
+            "#,
+            api_root,
+            collection,
+            added_last,
+            Utc::now()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(())
+    }
+
+    async fn begin(&self) -> Result<Box<dyn MarkingTransaction>, RepositoryError> {
+        let transaction = self.pool.begin().await.map_err(log_error)?;
+        Ok(Box::new(PostgresMarkingTransaction { transaction }))
+    }
+}
+
+#[async_trait::async_trait]
+impl SchemaRepository for PostgresMarkingRepository {
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map_err(log_error)?;
+        Ok(())
+    }
+
+    fn connections(&self) -> Option<ConnectionUsage> {
+        Some(ConnectionUsage {
+            open: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+        })
+    }
+
+    #[tracing::instrument(name = "Migrating the database", skip(self))]
+    async fn migrate(&self) -> Result<(), RepositoryError> {
+        MIGRATOR
+            .run(&self.pool)
+            .await
+            .map_err(|e| log_error(e.into()))
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, RepositoryError> {
+        let mut connection = self.pool.acquire().await.map_err(log_error)?;
+        // Until the first migration runs there is no table recording them.
+        let migrated = sqlx::query_scalar!(
+            r#"SELECT to_regclass('_sqlx_migrations') IS NOT NULL AS "migrated!""#
+        )
+        .fetch_one(&mut connection)
+        .await
+        .map_err(log_error)?;
+        let applied = if migrated {
+            connection
+                .list_applied_migrations()
+                .await
+                .map_err(|e| log_error(e.into()))?
+        } else {
+            Vec::new()
+        };
+        Ok(migration_status(&MIGRATOR, applied))
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+#[async_trait::async_trait]
+impl IdempotencyRepository for PostgresMarkingRepository {
+    #[tracing::instrument(name = "Claiming idempotency key in the database", skip(self))]
+    async fn claim_idempotency_key(
+        &self,
+        scope: &str,
+        client: &str,
+        key: &str,
+        request_fingerprint: &str,
+        expired_before: DateTime<Utc>,
+        locked_until: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyRecord>, RepositoryError> {
+        // A record read below may be released in between by the request
+        // holding it, in which case the key is free to claim again.
+        loop {
+            sqlx::query!(
+                r#"
+                DELETE FROM idempotency
+                WHERE scope = $1 AND client = $2 AND idempotency_key = $3 AND created_at < $4
+                "#,
+                scope,
+                client,
+                key,
+                expired_before
+            )
+            .execute(&self.pool)
+            .await
+            .map_err(log_error)?;
+
+            let now = Utc::now();
+            let claimed = sqlx::query!(
+                r#"
+                INSERT INTO idempotency
+                    (scope, client, idempotency_key, request_fingerprint, created_at, locked_until)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (scope, client, idempotency_key) DO UPDATE
+                SET request_fingerprint = EXCLUDED.request_fingerprint,
+                    created_at = EXCLUDED.created_at,
+                    locked_until = EXCLUDED.locked_until
+                WHERE idempotency.response_status_code IS NULL
+                    AND idempotency.locked_until < $5
+                "#,
+                scope,
+                client,
+                key,
+                request_fingerprint,
+                now,
+                locked_until
+            )
+            .execute(&self.pool)
+            .await
+            .map_err(log_error)?
+            .rows_affected()
+                == 1;
+            if claimed {
+                return Ok(None);
+            }
+
+            let record = sqlx::query!(
+                r#"
+                SELECT request_fingerprint, response_status_code, response_headers, response_body
+                FROM idempotency
+                WHERE scope = $1 AND client = $2 AND idempotency_key = $3
+                "#,
+                scope,
+                client,
+                key
+            )
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(log_error)?;
+            let record = match record {
+                Some(record) => record,
+                None => continue,
+            };
+
+            let response = match (
+                record.response_status_code,
+                record.response_headers,
+                record.response_body,
+            ) {
+                (Some(status_code), Some(headers), Some(body)) => Some(SavedResponse {
+                    status_code: status_code as u16,
+                    headers: serde_json::from_str(&headers).unwrap_or_default(),
+                    body,
+                }),
+                _ => None,
+            };
+            return Ok(Some(IdempotencyRecord {
+                request_fingerprint: record.request_fingerprint,
+                response,
+            }));
+        }
+    }
+
+    #[tracing::instrument(
+        name = "Saving idempotent response in the database",
+        skip(self, response)
+    )]
+    async fn save_idempotent_response(
+        &self,
+        scope: &str,
+        client: &str,
+        key: &str,
+        response: &SavedResponse,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            r#"
+            UPDATE idempotency
+            SET response_status_code = $4, response_headers = $5, response_body = $6
+            WHERE scope = $1 AND client = $2 AND idempotency_key = $3
+            "#,
+            scope,
+            client,
+            key,
+            response.status_code as i16,
+            serde_json::to_string(&response.headers).expect("Headers are serializable"),
+            response.body
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Releasing idempotency key in the database", skip(self))]
+    async fn release_idempotency_key(
+        &self,
+        scope: &str,
+        client: &str,
+        key: &str,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            "DELETE FROM idempotency WHERE scope = $1 AND client = $2 AND idempotency_key = $3",
+            scope,
+            client,
+            key
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl WebhookRepository for PostgresMarkingRepository {
+    #[tracing::instrument(name = "Saving new webhook in the database", skip(self, new_webhook))]
+    async fn insert_webhook(&self, new_webhook: &NewWebhook) -> Result<Webhook, RepositoryError> {
+        let events: Vec<_> = new_webhook
+            .events
+            .iter()
+            .map(|event_type| event_type.as_str().to_owned())
+            .collect();
+        self.sequence_committed_events().await?;
+        let mut transaction = self.pool.begin().await.map_err(log_error)?;
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO webhooks (id, url, events, secret, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, url, events, secret, created_at
+            "#,
+            Uuid::new_v4(),
+            new_webhook.url,
+            &events,
+            new_webhook.secret.expose_secret(),
+            Utc::now()
+        )
+        .fetch_one(&mut transaction)
+        .await
+        .map_err(log_error)?;
+        // The webhook gets the events committed from now on, not the backlog.
+        sqlx::query!(
+            r#"
+            INSERT INTO outbox_cursors (sink, sequence, updated_at)
+            SELECT $1, COALESCE(MAX(sequence), 0), $2 FROM outbox
+            "#,
+            webhook_sink_name(row.id),
+            Utc::now()
+        )
+        .execute(&mut transaction)
+        .await
+        .map_err(log_error)?;
+        transaction.commit().await.map_err(log_error)?;
+        Ok(webhook(
+            row.id,
+            row.url,
+            row.events,
+            row.secret,
+            row.created_at,
+        ))
+    }
+
+    #[tracing::instrument(name = "Fetching webhook from the database", skip(self))]
+    async fn get_webhook(&self, id: Uuid) -> Result<Webhook, RepositoryError> {
+        let row = sqlx::query!(
+            "SELECT id, url, events, secret, created_at FROM webhooks WHERE id = $1",
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(log_error)?
+        .ok_or(RepositoryError::WebhookNotFound(id))?;
+        Ok(webhook(
+            row.id,
+            row.url,
+            row.events,
+            row.secret,
+            row.created_at,
+        ))
+    }
+
+    #[tracing::instrument(name = "Listing webhooks from the database", skip(self))]
+    async fn list_webhooks(&self) -> Result<Vec<Webhook>, RepositoryError> {
+        let rows = sqlx::query!(
+            "SELECT id, url, events, secret, created_at FROM webhooks ORDER BY created_at, id"
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(rows
+            .into_iter()
+            .map(|row| webhook(row.id, row.url, row.events, row.secret, row.created_at))
+            .collect())
+    }
+
+    #[tracing::instrument(name = "Deleting webhook from the database", skip(self))]
+    async fn delete_webhook(&self, id: Uuid) -> Result<(), RepositoryError> {
+        let result = sqlx::query!("DELETE FROM webhooks WHERE id = $1", id)
+            .execute(&self.pool)
+            .await
+            .map_err(log_error)?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::WebhookNotFound(id));
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Saving webhook delivery in the database", skip(self, delivery))]
+    async fn record_delivery(&self, delivery: &WebhookDelivery) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_deliveries (
+                id, webhook_id, event_id, event_type, attempt, status_code, error, delivered,
+                attempted_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+            delivery.id,
+            delivery.webhook_id,
+            delivery.event_id,
+            delivery.event_type,
+            delivery.attempt,
+            delivery.status_code,
+            delivery.error,
+            delivery.delivered,
+            delivery.attempted_at
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Listing webhook deliveries from the database", skip(self))]
+    async fn list_deliveries(
+        &self,
+        webhook_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, RepositoryError> {
+        let deliveries = sqlx::query_as!(
+            WebhookDelivery,
+            r#"
+            SELECT id, webhook_id, event_id, event_type, attempt, status_code, error, delivered,
+                attempted_at
+            FROM webhook_deliveries
+            WHERE webhook_id = $1
+            ORDER BY attempted_at DESC, attempt DESC
+            LIMIT $2
+            "#,
+            webhook_id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(deliveries)
+    }
+
+    #[tracing::instrument(name = "Saving dead letter in the database", skip(self, dead_letter))]
+    async fn insert_dead_letter(&self, dead_letter: &DeadLetter) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_dead_letters (
+                id, webhook_id, event_id, event_type, payload, attempts, last_error, created_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+            dead_letter.id,
+            dead_letter.webhook_id,
+            dead_letter.event_id,
+            dead_letter.event_type,
+            dead_letter.payload,
+            dead_letter.attempts,
+            dead_letter.last_error,
+            dead_letter.created_at
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Listing dead letters from the database", skip(self))]
+    async fn list_dead_letters(
+        &self,
+        webhook_id: Uuid,
+    ) -> Result<Vec<DeadLetter>, RepositoryError> {
+        let dead_letters = sqlx::query_as!(
+            DeadLetter,
+            r#"
+            SELECT id, webhook_id, event_id, event_type, payload, attempts, last_error, created_at
+            FROM webhook_dead_letters
+            WHERE webhook_id = $1
+            ORDER BY created_at DESC
+            "#,
+            webhook_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(dead_letters)
+    }
+}
+
+#[async_trait::async_trait]
+impl OutboxRepository for PostgresMarkingRepository {
+    async fn list_outbox_events(
+        &self,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<OutboxEvent>, RepositoryError> {
+        self.sequence_committed_events().await?;
+        let events = sqlx::query_as!(
+            OutboxEvent,
+            r#"
+            SELECT sequence AS "sequence!", event_id, event_type, marking_id, payload, occurred_at
+            FROM outbox
+            WHERE sequence > $1
+            ORDER BY sequence
+            LIMIT $2
+            "#,
+            after,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(events)
+    }
+
+    async fn last_outbox_sequence(&self) -> Result<i64, RepositoryError> {
+        self.sequence_committed_events().await?;
+        let last = sqlx::query!(r#"SELECT COALESCE(MAX(sequence), 0) AS "sequence!" FROM outbox"#)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(log_error)?;
+        Ok(last.sequence)
+    }
+
+    async fn get_outbox_cursor(&self, sink: &str) -> Result<i64, RepositoryError> {
+        let cursor = sqlx::query!("SELECT sequence FROM outbox_cursors WHERE sink = $1", sink)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(log_error)?;
+        Ok(cursor.map_or(0, |row| row.sequence))
+    }
+
+    async fn save_outbox_cursor(&self, sink: &str, sequence: i64) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO outbox_cursors (sink, sequence, updated_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (sink) DO UPDATE
+            SET sequence = GREATEST(outbox_cursors.sequence, EXCLUDED.sequence),
+                updated_at = EXCLUDED.updated_at
+            "#,
+            sink,
+            sequence,
+            Utc::now()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Pruning the outbox", skip(self))]
+    async fn prune_outbox_events(&self, before: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM outbox
+            WHERE occurred_at < $1
+            AND sequence < (
+                SELECT MIN(bound) FROM (
+                    SELECT MAX(sequence) AS bound FROM outbox
+                    UNION ALL
+                    SELECT sequence FROM outbox_cursors
+                    WHERE sink NOT LIKE 'webhook:%'
+                    OR EXISTS (
+                        SELECT 1 FROM webhooks WHERE outbox_cursors.sink = 'webhook:' || webhooks.id
+                    )
+                ) AS bounds
+            )
+            "#,
+            before
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(result.rows_affected())
+    }
+}
+
+/// Event types this build does not know, written by a newer one, are left
+/// out.
+fn webhook(
+    id: Uuid,
+    url: String,
+    events: Vec<String>,
+    secret: String,
+    created_at: DateTime<Utc>,
+) -> Webhook {
+    Webhook {
+        id,
+        url,
+        events: events
+            .iter()
+            .filter_map(|event_type| MarkingEventType::parse(event_type).ok())
+            .collect(),
+        secret: Secret::new(secret),
+        created_at,
+    }
+}
+
+pub struct PostgresMarkingTransaction {
+    transaction: Transaction<'static, Postgres>,
+}
+
+#[async_trait::async_trait]
+impl MarkingTransaction for PostgresMarkingTransaction {
+    async fn insert_marking(
+        &mut self,
+        new_marking: &NewMarking,
+    ) -> Result<Marking, RepositoryError> {
+        insert_marking(&mut self.transaction, new_marking).await
+    }
+
+    async fn update_marking(
+        &mut self,
+        id: Uuid,
+        marking: &NewMarking,
+        expected_version: Option<i64>,
+    ) -> Result<Marking, RepositoryError> {
+        update_marking(&mut self.transaction, id, marking, expected_version).await
+    }
+
+    async fn delete_marking(
+        &mut self,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<(), RepositoryError> {
+        delete_marking(&mut self.transaction, id, expected_version).await
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), RepositoryError> {
+        self.transaction.commit().await.map_err(log_error)?;
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), RepositoryError> {
+        self.transaction.rollback().await.map_err(log_error)?;
+        Ok(())
+    }
+}
+
+async fn insert_marking(
+    connection: &mut PgConnection,
+    new_marking: &NewMarking,
+) -> Result<Marking, RepositoryError> {
+    let marking = sqlx::query_as!(
+        Marking,
+        r#"
+        INSERT INTO markings (id, name, definition_type, definition, created_at, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+            source_server, source_collection, fetched_at, status, date_added
+        "#,
+        Uuid::new_v4(),
+        new_marking.name.as_ref(),
+        new_marking.definition_type.as_ref(),
+        new_marking.definition.as_ref(),
+        Utc::now(),
+        Uuid::new_v4()
+    )
+    .fetch_one(&mut *connection)
+    .await
+    .map_err(|e| map_write_error(e, new_marking))?;
+    save_event(connection, MarkingEventType::Created, &marking).await?;
+    Ok(marking)
+}
+
+async fn update_marking(
+    connection: &mut PgConnection,
+    id: Uuid,
+    marking: &NewMarking,
+    expected_version: Option<i64>,
+) -> Result<Marking, RepositoryError> {
+    let updated = sqlx::query_as!(
+        Marking,
+        r#"
+        UPDATE markings
+        SET name = $2, definition_type = $3, definition = $4, updated_at = $5, updated_by = $6,
+            version = version + 1
+        WHERE id = $1 AND ($7::BIGINT IS NULL OR version = $7)
+        RETURNING id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+            source_server, source_collection, fetched_at, status, date_added
+        "#,
+        id,
+        marking.name.as_ref(),
+        marking.definition_type.as_ref(),
+        marking.definition.as_ref(),
+        Utc::now(),
+        Uuid::new_v4(),
+        expected_version
+    )
+    .fetch_optional(&mut *connection)
+    .await
+    .map_err(|e| map_write_error(e, marking))?;
+
+    match updated {
+        Some(marking) => {
+            save_event(connection, MarkingEventType::Updated, &marking).await?;
+            Ok(marking)
+        }
+        None => Err(missing_or_modified(connection, id).await),
+    }
+}
+
+async fn delete_marking(
+    connection: &mut PgConnection,
+    id: Uuid,
+    expected_version: Option<i64>,
+) -> Result<(), RepositoryError> {
+    let deleted = sqlx::query_as!(
+        Marking,
+        r#"
+        DELETE FROM markings
+        WHERE id = $1 AND ($2::BIGINT IS NULL OR version = $2)
+        RETURNING id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+            source_server, source_collection, fetched_at, status, date_added
+        "#,
+        id,
+        expected_version
+    )
+    .fetch_optional(&mut *connection)
+    .await
+    .map_err(log_error)?;
+    match deleted {
+        Some(marking) => save_event(connection, MarkingEventType::Deleted, &marking).await,
+        None => Err(missing_or_modified(connection, id).await),
+    }
+}
+
+/// Saves the event of a write to the outbox, in the transaction of the
+/// write.
+async fn save_event(
+    connection: &mut PgConnection,
+    event_type: MarkingEventType,
+    marking: &Marking,
+) -> Result<(), RepositoryError> {
+    save_marking_event(connection, &MarkingEvent::new(event_type, marking.clone())).await
+}
+
+async fn save_marking_event(
+    connection: &mut PgConnection,
+    event: &MarkingEvent,
+) -> Result<(), RepositoryError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO outbox (event_id, event_type, marking_id, payload, occurred_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        event.id,
+        event.event_type.as_str(),
+        event.marking.id,
+        serde_json::to_string(event).expect("Marking events are serializable"),
+        event.occurred_at
+    )
+    .execute(connection)
+    .await
+    .map_err(log_error)?;
+    Ok(())
+}
+
+/// Explains why a conditional write on `id` touched no rows.
+async fn missing_or_modified(connection: &mut PgConnection, id: Uuid) -> RepositoryError {
+    let current = sqlx::query!("SELECT version FROM markings WHERE id = $1", id)
+        .fetch_optional(connection)
+        .await;
+    match current {
+        Ok(Some(row)) => RepositoryError::VersionMismatch(id, row.version),
+        Ok(None) => RepositoryError::NotFound(id),
+        Err(e) => log_error(e),
+    }
+}
+
+/// Explains why a status change on `id` touched no rows.
+async fn status_unchanged(
+    connection: &mut PgConnection,
+    id: Uuid,
+    expected_version: Option<i64>,
+) -> RepositoryError {
+    let current = sqlx::query!("SELECT version, status FROM markings WHERE id = $1", id)
+        .fetch_optional(connection)
+        .await;
+    match current {
+        Ok(Some(row)) if expected_version.is_some_and(|version| version != row.version) => {
+            RepositoryError::VersionMismatch(id, row.version)
+        }
+        Ok(Some(row)) => RepositoryError::WrongStatus(id, row.status),
+        Ok(None) => RepositoryError::NotFound(id),
+        Err(e) => log_error(e),
+    }
+}
+
+fn map_write_error(e: sqlx::Error, marking: &NewMarking) -> RepositoryError {
+    match &e {
+        sqlx::Error::Database(db_error) if db_error.code().as_deref() == Some("23505") => {
+            RepositoryError::DuplicateName(marking.name.as_ref().to_owned())
+        }
+        _ => log_error(e),
+    }
+}
+
+fn log_error(e: sqlx::Error) -> RepositoryError {
+    tracing::error!("Failed to execute query: {:?}", e);
+    RepositoryError::Database(e)
+}
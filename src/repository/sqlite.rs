@@ -0,0 +1,1121 @@
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{Sqlite, SqliteConnection, SqlitePool, Transaction};
+use uuid::Uuid;
+
+use crate::domain::{
+    webhook_sink_name, DeadLetter, Marking, MarkingAssignment, MarkingEvent, MarkingEventType,
+    MarkingSource, MarkingStatus, NewMarking, NewWebhook, ObjectRef, OutboxEvent, Webhook,
+    WebhookDelivery,
+};
+use crate::repository::{
+    migration_status, mirror_event_type, next_date_added, ConnectionUsage, IdempotencyRecord,
+    IdempotencyRepository, MarkingRepository, MarkingTransaction, MigrationStatus,
+    OutboxRepository, RepositoryError, SavedResponse, SchemaRepository, SearchResult,
+    WebhookRepository,
+};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations_sqlite");
+
+/// Stores markings in a local SQLite file, for hosts where running Postgres
+/// is not an option.
+///
+/// SQLite is not covered by the compile-time checked queries, which are bound
+/// to the Postgres schema, so the queries here are checked at runtime.
+pub struct SqliteMarkingRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteMarkingRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn connect(options: SqliteConnectOptions) -> Result<Self, RepositoryError> {
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options)
+            .await
+            .map_err(log_error)?;
+        Ok(Self::new(pool))
+    }
+}
+
+#[async_trait::async_trait]
+impl MarkingRepository for SqliteMarkingRepository {
+    #[tracing::instrument(name = "Saving new marking in the database", skip(self, new_marking))]
+    async fn insert_marking(&self, new_marking: &NewMarking) -> Result<Marking, RepositoryError> {
+        let mut transaction = self.pool.begin().await.map_err(log_error)?;
+        let marking = insert_marking(&mut transaction, new_marking).await?;
+        transaction.commit().await.map_err(log_error)?;
+        Ok(marking)
+    }
+
+    #[tracing::instrument(name = "Fetching marking from the database", skip(self))]
+    async fn get_marking(&self, id: Uuid) -> Result<Marking, RepositoryError> {
+        sqlx::query_as::<_, Marking>(
+            r#"
+            SELECT id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+                source_server, source_collection, fetched_at, status, date_added
+            FROM markings
+            WHERE id = ?1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(log_error)?
+        .ok_or(RepositoryError::NotFound(id))
+    }
+
+    #[tracing::instrument(name = "Listing markings from the database", skip(self))]
+    async fn list_markings(&self) -> Result<Vec<Marking>, RepositoryError> {
+        let markings = sqlx::query_as::<_, Marking>(
+            r#"
+            SELECT id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+                source_server, source_collection, fetched_at, status, date_added
+            FROM markings
+            ORDER BY name
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(markings)
+    }
+
+    #[tracing::instrument(name = "Listing added markings from the database", skip(self))]
+    async fn list_added_markings(&self) -> Result<Vec<Marking>, RepositoryError> {
+        let markings = sqlx::query_as::<_, Marking>(
+            r#"
+            SELECT id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+                source_server, source_collection, fetched_at, status, date_added
+            FROM markings
+            WHERE date_added IS NOT NULL
+            ORDER BY date_added, id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(markings)
+    }
+
+    async fn count_markings_by_type(&self) -> Result<Vec<(String, i64)>, RepositoryError> {
+        sqlx::query_as(
+            r#"
+            SELECT definition_type, COUNT(*)
+            FROM markings
+            GROUP BY definition_type
+            ORDER BY definition_type
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)
+    }
+
+    #[tracing::instrument(name = "Updating marking in the database", skip(self, marking))]
+    async fn update_marking(
+        &self,
+        id: Uuid,
+        marking: &NewMarking,
+        expected_version: Option<i64>,
+    ) -> Result<Marking, RepositoryError> {
+        let mut transaction = self.pool.begin().await.map_err(log_error)?;
+        let marking = update_marking(&mut transaction, id, marking, expected_version).await?;
+        transaction.commit().await.map_err(log_error)?;
+        Ok(marking)
+    }
+
+    #[tracing::instrument(name = "Deleting marking from the database", skip(self))]
+    async fn delete_marking(
+        &self,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<(), RepositoryError> {
+        let mut transaction = self.pool.begin().await.map_err(log_error)?;
+        delete_marking(&mut transaction, id, expected_version).await?;
+        transaction.commit().await.map_err(log_error)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Searching markings in the database", skip(self))]
+    async fn search_markings(
+        &self,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<SearchResult>, RepositoryError> {
+        // Quote every term so that user input is never parsed as FTS5 syntax.
+        let terms: Vec<_> = query
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|term| !term.is_empty())
+            .map(|term| format!("\"{}\"", term))
+            .collect();
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let results = sqlx::query_as::<_, SearchResult>(
+            r#"
+            SELECT m.id, m.name, m.definition_type, m.definition,
+                -bm25(markings_search, 0.0, 10.0, 4.0) AS rank,
+                highlight(markings_search, 2, '<mark>', '</mark>') AS snippet
+            FROM markings_search
+            JOIN markings m ON m.id = markings_search.id
+            WHERE markings_search MATCH ?1
+            ORDER BY rank DESC, m.name
+            LIMIT ?2
+            "#,
+        )
+        .bind(terms.join(" "))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(results)
+    }
+
+    #[tracing::instrument(name = "Changing marking status in the database", skip(self))]
+    async fn set_marking_status(
+        &self,
+        id: Uuid,
+        status: MarkingStatus,
+        expected_version: Option<i64>,
+    ) -> Result<Marking, RepositoryError> {
+        let mut transaction = self.pool.begin().await.map_err(log_error)?;
+        // The statuses left out are those `MarkingStatus::can_follow` refuses.
+        let updated = sqlx::query_as::<_, Marking>(
+            r#"
+            UPDATE markings
+            SET status = ?2, updated_at = ?3, updated_by = ?4, version = version + 1
+            WHERE id = ?1 AND (?5 IS NULL OR version = ?5) AND status NOT IN (?2, 'revoked')
+            RETURNING id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+                source_server, source_collection, fetched_at, status, date_added
+            "#,
+        )
+        .bind(id)
+        .bind(status.as_str())
+        .bind(Utc::now())
+        .bind(Uuid::new_v4())
+        .bind(expected_version)
+        .fetch_optional(&mut transaction)
+        .await
+        .map_err(log_error)?;
+        let marking = match updated {
+            Some(marking) => marking,
+            None => return Err(status_unchanged(&mut transaction, id, expected_version).await),
+        };
+        save_event(
+            &mut transaction,
+            MarkingEventType::of_status(status),
+            &marking,
+        )
+        .await?;
+        transaction.commit().await.map_err(log_error)?;
+        Ok(marking)
+    }
+
+    #[tracing::instrument(name = "Assigning marking in the database", skip(self))]
+    async fn assign_marking(
+        &self,
+        id: Uuid,
+        object_ref: &ObjectRef,
+    ) -> Result<MarkingAssignment, RepositoryError> {
+        let mut transaction = self.pool.begin().await.map_err(log_error)?;
+        let marking = sqlx::query_as::<_, Marking>(
+            r#"
+            SELECT id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+                source_server, source_collection, fetched_at, status, date_added
+            FROM markings
+            WHERE id = ?1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&mut transaction)
+        .await
+        .map_err(log_error)?
+        .ok_or(RepositoryError::NotFound(id))?;
+        if marking.status == MarkingStatus::Revoked.as_str() {
+            return Err(RepositoryError::WrongStatus(id, marking.status));
+        }
+        let assigned = sqlx::query_as::<_, MarkingAssignment>(
+            r#"
+            INSERT INTO marking_assignments (marking_id, object_ref, assigned_at)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT (marking_id, object_ref) DO NOTHING
+            RETURNING marking_id, object_ref, assigned_at
+            "#,
+        )
+        .bind(id)
+        .bind(object_ref.as_ref())
+        .bind(Utc::now())
+        .fetch_optional(&mut transaction)
+        .await
+        .map_err(log_error)?;
+        let assignment = match assigned {
+            Some(assignment) => {
+                save_marking_event(
+                    &mut transaction,
+                    &MarkingEvent::assigned(marking, &assignment),
+                )
+                .await?;
+                assignment
+            }
+            None => sqlx::query_as::<_, MarkingAssignment>(
+                r#"
+                SELECT marking_id, object_ref, assigned_at
+                FROM marking_assignments
+                WHERE marking_id = ?1 AND object_ref = ?2
+                "#,
+            )
+            .bind(id)
+            .bind(object_ref.as_ref())
+            .fetch_one(&mut transaction)
+            .await
+            .map_err(log_error)?,
+        };
+        transaction.commit().await.map_err(log_error)?;
+        Ok(assignment)
+    }
+
+    #[tracing::instrument(name = "Listing marking assignments from the database", skip(self))]
+    async fn list_assignments(&self, id: Uuid) -> Result<Vec<MarkingAssignment>, RepositoryError> {
+        sqlx::query_as::<_, MarkingAssignment>(
+            r#"
+            SELECT marking_id, object_ref, assigned_at
+            FROM marking_assignments
+            WHERE marking_id = ?1
+            ORDER BY assigned_at, object_ref
+            "#,
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)
+    }
+
+    #[tracing::instrument(name = "Mirroring marking in the database", skip(self, marking))]
+    async fn mirror_marking(
+        &self,
+        id: Uuid,
+        marking: &NewMarking,
+        source: &MarkingSource,
+    ) -> Result<Marking, RepositoryError> {
+        let mut transaction = self.pool.begin().await.map_err(log_error)?;
+        let previous_version =
+            sqlx::query_scalar::<_, i64>("SELECT version FROM markings WHERE id = ?1")
+                .bind(id)
+                .fetch_optional(&mut transaction)
+                .await
+                .map_err(log_error)?;
+        let mirrored = sqlx::query_as::<_, Marking>(
+            r#"
+            INSERT INTO markings (
+                id, name, definition_type, definition, created_at, created_by,
+                source_server, source_collection, fetched_at
+            )
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?5)
+            ON CONFLICT (id) DO UPDATE
+            SET name = excluded.name, definition_type = excluded.definition_type,
+                definition = excluded.definition,
+                updated_at = CASE WHEN (markings.name, markings.definition_type, markings.definition)
+                    <> (excluded.name, excluded.definition_type, excluded.definition)
+                    THEN excluded.created_at ELSE markings.updated_at END,
+                updated_by = CASE WHEN (markings.name, markings.definition_type, markings.definition)
+                    <> (excluded.name, excluded.definition_type, excluded.definition)
+                    THEN excluded.created_by ELSE markings.updated_by END,
+                version = CASE WHEN (markings.name, markings.definition_type, markings.definition)
+                    <> (excluded.name, excluded.definition_type, excluded.definition)
+                    THEN markings.version + 1 ELSE markings.version END,
+                source_server = excluded.source_server,
+                source_collection = excluded.source_collection,
+                fetched_at = excluded.fetched_at
+            WHERE markings.source_server = excluded.source_server
+                AND markings.source_collection = excluded.source_collection
+            RETURNING id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+                source_server, source_collection, fetched_at, status, date_added
+            "#,
+        )
+        .bind(id)
+        .bind(marking.name.as_ref())
+        .bind(marking.definition_type.as_ref())
+        .bind(marking.definition.as_ref())
+        .bind(source.fetched_at)
+        .bind(Uuid::new_v4())
+        .bind(&source.server)
+        .bind(&source.collection)
+        .fetch_optional(&mut transaction)
+        .await
+        .map_err(|e| map_write_error(e, marking))?
+        .ok_or(RepositoryError::ForeignMarking(id))?;
+        if let Some(event_type) = mirror_event_type(previous_version, &mirrored) {
+            save_event(&mut transaction, event_type, &mirrored).await?;
+        }
+        transaction.commit().await.map_err(log_error)?;
+        Ok(mirrored)
+    }
+
+    async fn get_sync_cursor(
+        &self,
+        api_root: &str,
+        collection: &str,
+    ) -> Result<Option<String>, RepositoryError> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT added_last FROM taxii_sync_cursors WHERE api_root = ?1 AND collection = ?2",
+        )
+        .bind(api_root)
+        .bind(collection)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(log_error)
+    }
+
+    async fn save_sync_cursor(
+        &self,
+        api_root: &str,
+        collection: &str,
+        added_last: &str,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(
+            r#"
+            INSERT INTO taxii_sync_cursors (api_root, collection, added_last, updated_at)
+            VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (api_root, collection) DO UPDATE
+            SET added_last = excluded.added_last, updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(api_root)
+        .bind(collection)
+        .bind(added_last)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(())
+    }
+
+    async fn begin(&self) -> Result<Box<dyn MarkingTransaction>, RepositoryError> {
+        let transaction = self.pool.begin().await.map_err(log_error)?;
+        Ok(Box::new(SqliteMarkingTransaction { transaction }))
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct IdempotencyRow {
+    request_fingerprint: String,
+    response_status_code: Option<i64>,
+    response_headers: Option<String>,
+    response_body: Option<Vec<u8>>,
+}
+
+#[async_trait::async_trait]
+impl SchemaRepository for SqliteMarkingRepository {
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map_err(log_error)?;
+        Ok(())
+    }
+
+    fn connections(&self) -> Option<ConnectionUsage> {
+        Some(ConnectionUsage {
+            open: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+        })
+    }
+
+    #[tracing::instrument(name = "Migrating the database", skip(self))]
+    async fn migrate(&self) -> Result<(), RepositoryError> {
+        MIGRATOR
+            .run(&self.pool)
+            .await
+            .map_err(|e| log_error(e.into()))
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, RepositoryError> {
+        let mut connection = self.pool.acquire().await.map_err(log_error)?;
+        // Until the first migration runs there is no table recording them.
+        let migrated: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations')",
+        )
+        .fetch_one(&mut connection)
+        .await
+        .map_err(log_error)?;
+        let applied = if migrated {
+            connection
+                .list_applied_migrations()
+                .await
+                .map_err(|e| log_error(e.into()))?
+        } else {
+            Vec::new()
+        };
+        Ok(migration_status(&MIGRATOR, applied))
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+#[async_trait::async_trait]
+impl IdempotencyRepository for SqliteMarkingRepository {
+    #[tracing::instrument(name = "Claiming idempotency key in the database", skip(self))]
+    async fn claim_idempotency_key(
+        &self,
+        scope: &str,
+        client: &str,
+        key: &str,
+        request_fingerprint: &str,
+        expired_before: DateTime<Utc>,
+        locked_until: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyRecord>, RepositoryError> {
+        // A record read below may be released in between by the request
+        // holding it, in which case the key is free to claim again.
+        loop {
+            sqlx::query(
+                r#"
+                DELETE FROM idempotency
+                WHERE scope = ?1 AND client = ?2 AND idempotency_key = ?3 AND created_at < ?4
+                "#,
+            )
+            .bind(scope)
+            .bind(client)
+            .bind(key)
+            .bind(expired_before)
+            .execute(&self.pool)
+            .await
+            .map_err(log_error)?;
+
+            let claimed = sqlx::query(
+                r#"
+                INSERT INTO idempotency
+                    (scope, client, idempotency_key, request_fingerprint, created_at, locked_until)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                ON CONFLICT (scope, client, idempotency_key) DO UPDATE
+                SET request_fingerprint = excluded.request_fingerprint,
+                    created_at = excluded.created_at,
+                    locked_until = excluded.locked_until
+                WHERE idempotency.response_status_code IS NULL
+                    AND idempotency.locked_until < ?5
+                "#,
+            )
+            .bind(scope)
+            .bind(client)
+            .bind(key)
+            .bind(request_fingerprint)
+            .bind(Utc::now())
+            .bind(locked_until)
+            .execute(&self.pool)
+            .await
+            .map_err(log_error)?
+            .rows_affected()
+                == 1;
+            if claimed {
+                return Ok(None);
+            }
+
+            let record = sqlx::query_as::<_, IdempotencyRow>(
+                r#"
+                SELECT request_fingerprint, response_status_code, response_headers, response_body
+                FROM idempotency
+                WHERE scope = ?1 AND client = ?2 AND idempotency_key = ?3
+                "#,
+            )
+            .bind(scope)
+            .bind(client)
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(log_error)?;
+            let record = match record {
+                Some(record) => record,
+                None => continue,
+            };
+
+            let response = match (
+                record.response_status_code,
+                record.response_headers,
+                record.response_body,
+            ) {
+                (Some(status_code), Some(headers), Some(body)) => Some(SavedResponse {
+                    status_code: status_code as u16,
+                    headers: serde_json::from_str(&headers).unwrap_or_default(),
+                    body,
+                }),
+                _ => None,
+            };
+            return Ok(Some(IdempotencyRecord {
+                request_fingerprint: record.request_fingerprint,
+                response,
+            }));
+        }
+    }
+
+    #[tracing::instrument(
+        name = "Saving idempotent response in the database",
+        skip(self, response)
+    )]
+    async fn save_idempotent_response(
+        &self,
+        scope: &str,
+        client: &str,
+        key: &str,
+        response: &SavedResponse,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(
+            r#"
+            UPDATE idempotency
+            SET response_status_code = ?4, response_headers = ?5, response_body = ?6
+            WHERE scope = ?1 AND client = ?2 AND idempotency_key = ?3
+            "#,
+        )
+        .bind(scope)
+        .bind(client)
+        .bind(key)
+        .bind(response.status_code as i64)
+        .bind(serde_json::to_string(&response.headers).expect("Headers are serializable"))
+        .bind(&response.body)
+        .execute(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Releasing idempotency key in the database", skip(self))]
+    async fn release_idempotency_key(
+        &self,
+        scope: &str,
+        client: &str,
+        key: &str,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "DELETE FROM idempotency WHERE scope = ?1 AND client = ?2 AND idempotency_key = ?3",
+        )
+        .bind(scope)
+        .bind(client)
+        .bind(key)
+        .execute(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct WebhookRow {
+    id: Uuid,
+    url: String,
+    events: String,
+    secret: String,
+    created_at: DateTime<Utc>,
+}
+
+impl From<WebhookRow> for Webhook {
+    /// Event types this build does not know, written by a newer one, are
+    /// left out.
+    fn from(row: WebhookRow) -> Self {
+        let events: Vec<String> = serde_json::from_str(&row.events).unwrap_or_default();
+        Self {
+            id: row.id,
+            url: row.url,
+            events: events
+                .iter()
+                .filter_map(|event_type| MarkingEventType::parse(event_type).ok())
+                .collect(),
+            secret: Secret::new(row.secret),
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl WebhookRepository for SqliteMarkingRepository {
+    #[tracing::instrument(name = "Saving new webhook in the database", skip(self, new_webhook))]
+    async fn insert_webhook(&self, new_webhook: &NewWebhook) -> Result<Webhook, RepositoryError> {
+        let events: Vec<_> = new_webhook
+            .events
+            .iter()
+            .map(|event_type| event_type.as_str())
+            .collect();
+        let mut transaction = self.pool.begin().await.map_err(log_error)?;
+        let row = sqlx::query_as::<_, WebhookRow>(
+            r#"
+            INSERT INTO webhooks (id, url, events, secret, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            RETURNING id, url, events, secret, created_at
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(&new_webhook.url)
+        .bind(serde_json::to_string(&events).expect("Event types are serializable"))
+        .bind(new_webhook.secret.expose_secret())
+        .bind(Utc::now())
+        .fetch_one(&mut transaction)
+        .await
+        .map_err(log_error)?;
+        // The webhook gets the events committed from now on, not the backlog.
+        sqlx::query(
+            r#"
+            INSERT INTO outbox_cursors (sink, sequence, updated_at)
+            SELECT ?1, COALESCE(MAX(sequence), 0), ?2 FROM outbox
+            "#,
+        )
+        .bind(webhook_sink_name(row.id))
+        .bind(Utc::now())
+        .execute(&mut transaction)
+        .await
+        .map_err(log_error)?;
+        transaction.commit().await.map_err(log_error)?;
+        Ok(row.into())
+    }
+
+    #[tracing::instrument(name = "Fetching webhook from the database", skip(self))]
+    async fn get_webhook(&self, id: Uuid) -> Result<Webhook, RepositoryError> {
+        sqlx::query_as::<_, WebhookRow>(
+            "SELECT id, url, events, secret, created_at FROM webhooks WHERE id = ?1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(log_error)?
+        .map(Webhook::from)
+        .ok_or(RepositoryError::WebhookNotFound(id))
+    }
+
+    #[tracing::instrument(name = "Listing webhooks from the database", skip(self))]
+    async fn list_webhooks(&self) -> Result<Vec<Webhook>, RepositoryError> {
+        let rows = sqlx::query_as::<_, WebhookRow>(
+            "SELECT id, url, events, secret, created_at FROM webhooks ORDER BY created_at, id",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(rows.into_iter().map(Webhook::from).collect())
+    }
+
+    #[tracing::instrument(name = "Deleting webhook from the database", skip(self))]
+    async fn delete_webhook(&self, id: Uuid) -> Result<(), RepositoryError> {
+        let result = sqlx::query("DELETE FROM webhooks WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(log_error)?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::WebhookNotFound(id));
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Saving webhook delivery in the database", skip(self, delivery))]
+    async fn record_delivery(&self, delivery: &WebhookDelivery) -> Result<(), RepositoryError> {
+        sqlx::query(
+            r#"
+            INSERT INTO webhook_deliveries (
+                id, webhook_id, event_id, event_type, attempt, status_code, error, delivered,
+                attempted_at
+            )
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            "#,
+        )
+        .bind(delivery.id)
+        .bind(delivery.webhook_id)
+        .bind(delivery.event_id)
+        .bind(&delivery.event_type)
+        .bind(delivery.attempt)
+        .bind(delivery.status_code)
+        .bind(&delivery.error)
+        .bind(delivery.delivered)
+        .bind(delivery.attempted_at)
+        .execute(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Listing webhook deliveries from the database", skip(self))]
+    async fn list_deliveries(
+        &self,
+        webhook_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, RepositoryError> {
+        let deliveries = sqlx::query_as::<_, WebhookDelivery>(
+            r#"
+            SELECT id, webhook_id, event_id, event_type, attempt, status_code, error, delivered,
+                attempted_at
+            FROM webhook_deliveries
+            WHERE webhook_id = ?1
+            ORDER BY attempted_at DESC, attempt DESC
+            LIMIT ?2
+            "#,
+        )
+        .bind(webhook_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(deliveries)
+    }
+
+    #[tracing::instrument(name = "Saving dead letter in the database", skip(self, dead_letter))]
+    async fn insert_dead_letter(&self, dead_letter: &DeadLetter) -> Result<(), RepositoryError> {
+        sqlx::query(
+            r#"
+            INSERT INTO webhook_dead_letters (
+                id, webhook_id, event_id, event_type, payload, attempts, last_error, created_at
+            )
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            "#,
+        )
+        .bind(dead_letter.id)
+        .bind(dead_letter.webhook_id)
+        .bind(dead_letter.event_id)
+        .bind(&dead_letter.event_type)
+        .bind(&dead_letter.payload)
+        .bind(dead_letter.attempts)
+        .bind(&dead_letter.last_error)
+        .bind(dead_letter.created_at)
+        .execute(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Listing dead letters from the database", skip(self))]
+    async fn list_dead_letters(
+        &self,
+        webhook_id: Uuid,
+    ) -> Result<Vec<DeadLetter>, RepositoryError> {
+        let dead_letters = sqlx::query_as::<_, DeadLetter>(
+            r#"
+            SELECT id, webhook_id, event_id, event_type, payload, attempts, last_error, created_at
+            FROM webhook_dead_letters
+            WHERE webhook_id = ?1
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(webhook_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(dead_letters)
+    }
+}
+
+#[async_trait::async_trait]
+impl OutboxRepository for SqliteMarkingRepository {
+    async fn list_outbox_events(
+        &self,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<OutboxEvent>, RepositoryError> {
+        let events = sqlx::query_as::<_, OutboxEvent>(
+            r#"
+            SELECT sequence, event_id, event_type, marking_id, payload, occurred_at
+            FROM outbox
+            WHERE sequence > ?1
+            ORDER BY sequence
+            LIMIT ?2
+            "#,
+        )
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(events)
+    }
+
+    async fn last_outbox_sequence(&self) -> Result<i64, RepositoryError> {
+        sqlx::query_scalar::<_, i64>("SELECT COALESCE(MAX(sequence), 0) FROM outbox")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(log_error)
+    }
+
+    async fn get_outbox_cursor(&self, sink: &str) -> Result<i64, RepositoryError> {
+        let cursor =
+            sqlx::query_scalar::<_, i64>("SELECT sequence FROM outbox_cursors WHERE sink = ?1")
+                .bind(sink)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(log_error)?;
+        Ok(cursor.unwrap_or(0))
+    }
+
+    async fn save_outbox_cursor(&self, sink: &str, sequence: i64) -> Result<(), RepositoryError> {
+        sqlx::query(
+            r#"
+            INSERT INTO outbox_cursors (sink, sequence, updated_at)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT (sink) DO UPDATE
+            SET sequence = MAX(outbox_cursors.sequence, excluded.sequence),
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(sink)
+        .bind(sequence)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Pruning the outbox", skip(self))]
+    async fn prune_outbox_events(&self, before: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        // Webhook ids are blobs, spelled out as in `webhook_sink_name`.
+        let result = sqlx::query(
+            r#"
+            DELETE FROM outbox
+            WHERE occurred_at < ?1
+            AND sequence < (
+                SELECT MIN(bound) FROM (
+                    SELECT MAX(sequence) AS bound FROM outbox
+                    UNION ALL
+                    SELECT sequence FROM outbox_cursors
+                    WHERE sink NOT LIKE 'webhook:%'
+                    OR EXISTS (
+                        SELECT 1 FROM webhooks
+                        WHERE outbox_cursors.sink = 'webhook:' || lower(
+                            substr(hex(webhooks.id), 1, 8) || '-' ||
+                            substr(hex(webhooks.id), 9, 4) || '-' ||
+                            substr(hex(webhooks.id), 13, 4) || '-' ||
+                            substr(hex(webhooks.id), 17, 4) || '-' ||
+                            substr(hex(webhooks.id), 21)
+                        )
+                    )
+                )
+            )
+            "#,
+        )
+        .bind(before)
+        .execute(&self.pool)
+        .await
+        .map_err(log_error)?;
+        Ok(result.rows_affected())
+    }
+}
+
+pub struct SqliteMarkingTransaction {
+    transaction: Transaction<'static, Sqlite>,
+}
+
+#[async_trait::async_trait]
+impl MarkingTransaction for SqliteMarkingTransaction {
+    async fn insert_marking(
+        &mut self,
+        new_marking: &NewMarking,
+    ) -> Result<Marking, RepositoryError> {
+        insert_marking(&mut self.transaction, new_marking).await
+    }
+
+    async fn update_marking(
+        &mut self,
+        id: Uuid,
+        marking: &NewMarking,
+        expected_version: Option<i64>,
+    ) -> Result<Marking, RepositoryError> {
+        update_marking(&mut self.transaction, id, marking, expected_version).await
+    }
+
+    async fn delete_marking(
+        &mut self,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<(), RepositoryError> {
+        delete_marking(&mut self.transaction, id, expected_version).await
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), RepositoryError> {
+        self.transaction.commit().await.map_err(log_error)?;
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), RepositoryError> {
+        self.transaction.rollback().await.map_err(log_error)?;
+        Ok(())
+    }
+}
+
+async fn insert_marking(
+    connection: &mut SqliteConnection,
+    new_marking: &NewMarking,
+) -> Result<Marking, RepositoryError> {
+    let marking = sqlx::query_as::<_, Marking>(
+        r#"
+        INSERT INTO markings (id, name, definition_type, definition, created_at, created_by)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        RETURNING id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+            source_server, source_collection, fetched_at, status, date_added
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(new_marking.name.as_ref())
+    .bind(new_marking.definition_type.as_ref())
+    .bind(new_marking.definition.as_ref())
+    .bind(Utc::now())
+    .bind(Uuid::new_v4())
+    .fetch_one(&mut *connection)
+    .await
+    .map_err(|e| map_write_error(e, new_marking))?;
+    save_event(connection, MarkingEventType::Created, &marking).await?;
+    Ok(marking)
+}
+
+async fn update_marking(
+    connection: &mut SqliteConnection,
+    id: Uuid,
+    marking: &NewMarking,
+    expected_version: Option<i64>,
+) -> Result<Marking, RepositoryError> {
+    let updated = sqlx::query_as::<_, Marking>(
+        r#"
+        UPDATE markings
+        SET name = ?2, definition_type = ?3, definition = ?4, updated_at = ?5, updated_by = ?6,
+            version = version + 1
+        WHERE id = ?1 AND (?7 IS NULL OR version = ?7)
+        RETURNING id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+            source_server, source_collection, fetched_at, status, date_added
+        "#,
+    )
+    .bind(id)
+    .bind(marking.name.as_ref())
+    .bind(marking.definition_type.as_ref())
+    .bind(marking.definition.as_ref())
+    .bind(Utc::now())
+    .bind(Uuid::new_v4())
+    .bind(expected_version)
+    .fetch_optional(&mut *connection)
+    .await
+    .map_err(|e| map_write_error(e, marking))?;
+
+    match updated {
+        Some(marking) => {
+            save_event(connection, MarkingEventType::Updated, &marking).await?;
+            Ok(marking)
+        }
+        None => Err(missing_or_modified(connection, id).await),
+    }
+}
+
+async fn delete_marking(
+    connection: &mut SqliteConnection,
+    id: Uuid,
+    expected_version: Option<i64>,
+) -> Result<(), RepositoryError> {
+    let deleted = sqlx::query_as::<_, Marking>(
+        r#"
+        DELETE FROM markings
+        WHERE id = ?1 AND (?2 IS NULL OR version = ?2)
+        RETURNING id, name, definition_type, definition, created_at, updated_at, created_by, updated_by, version,
+            source_server, source_collection, fetched_at, status, date_added
+        "#,
+    )
+    .bind(id)
+    .bind(expected_version)
+    .fetch_optional(&mut *connection)
+    .await
+    .map_err(log_error)?;
+    match deleted {
+        Some(marking) => save_event(connection, MarkingEventType::Deleted, &marking).await,
+        None => Err(missing_or_modified(connection, id).await),
+    }
+}
+
+/// Saves the event of a write to the outbox, in the transaction of the
+/// write. SQLite lets a single transaction write at a time, so events are
+/// committed in the order of their sequence.
+async fn save_event(
+    connection: &mut SqliteConnection,
+    event_type: MarkingEventType,
+    marking: &Marking,
+) -> Result<(), RepositoryError> {
+    save_marking_event(connection, &MarkingEvent::new(event_type, marking.clone())).await
+}
+
+async fn save_marking_event(
+    connection: &mut SqliteConnection,
+    event: &MarkingEvent,
+) -> Result<(), RepositoryError> {
+    // The write before the event holds the write lock of the database, so
+    // events are added in the order they are committed.
+    let last = sqlx::query_scalar::<_, Option<DateTime<Utc>>>("SELECT MAX(added_at) FROM outbox")
+        .fetch_one(&mut *connection)
+        .await
+        .map_err(log_error)?;
+    let added_at = next_date_added(last);
+    sqlx::query(
+        r#"
+        INSERT INTO outbox (event_id, event_type, marking_id, payload, occurred_at, added_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        "#,
+    )
+    .bind(event.id)
+    .bind(event.event_type.as_str())
+    .bind(event.marking.id)
+    .bind(serde_json::to_string(event).expect("Marking events are serializable"))
+    .bind(event.occurred_at)
+    .bind(added_at)
+    .execute(&mut *connection)
+    .await
+    .map_err(log_error)?;
+    sqlx::query("UPDATE markings SET date_added = ?1 WHERE id = ?2")
+        .bind(added_at)
+        .bind(event.marking.id)
+        .execute(connection)
+        .await
+        .map_err(log_error)?;
+    Ok(())
+}
+
+/// Explains why a conditional write on `id` touched no rows.
+async fn missing_or_modified(connection: &mut SqliteConnection, id: Uuid) -> RepositoryError {
+    let current = sqlx::query_scalar::<_, i64>("SELECT version FROM markings WHERE id = ?1")
+        .bind(id)
+        .fetch_optional(connection)
+        .await;
+    match current {
+        Ok(Some(version)) => RepositoryError::VersionMismatch(id, version),
+        Ok(None) => RepositoryError::NotFound(id),
+        Err(e) => log_error(e),
+    }
+}
+
+/// Explains why a status change on `id` touched no rows.
+async fn status_unchanged(
+    connection: &mut SqliteConnection,
+    id: Uuid,
+    expected_version: Option<i64>,
+) -> RepositoryError {
+    let current =
+        sqlx::query_as::<_, (i64, String)>("SELECT version, status FROM markings WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(connection)
+            .await;
+    match current {
+        Ok(Some((version, _))) if expected_version.is_some_and(|expected| expected != version) => {
+            RepositoryError::VersionMismatch(id, version)
+        }
+        Ok(Some((_, status))) => RepositoryError::WrongStatus(id, status),
+        Ok(None) => RepositoryError::NotFound(id),
+        Err(e) => log_error(e),
+    }
+}
+
+fn map_write_error(e: sqlx::Error, marking: &NewMarking) -> RepositoryError {
+    match &e {
+        // SQLITE_CONSTRAINT_UNIQUE
+        sqlx::Error::Database(db_error) if db_error.code().as_deref() == Some("2067") => {
+            RepositoryError::DuplicateName(marking.name.as_ref().to_owned())
+        }
+        _ => log_error(e),
+    }
+}
+
+fn log_error(e: sqlx::Error) -> RepositoryError {
+    tracing::error!("Failed to execute query: {:?}", e);
+    RepositoryError::Database(e)
+}
@@ -0,0 +1,169 @@
+use actix_web::body::{to_bytes, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::{Error, HttpMessage};
+use tracing::Span;
+use tracing_actix_web::{root_span, DefaultRootSpanBuilder, RootSpanBuilder};
+use uuid::Uuid;
+
+use crate::tls::ClientIdentity;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Identifies a request to both its caller and our logs: taken from the
+/// `X-Request-Id` header when the caller sent a usable one, generated
+/// otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Reads or generates the id of `request` and attaches it to the request.
+    pub fn assign(request: &ServiceRequest) -> Self {
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| Self::try_from(value.to_owned()).ok())
+            .unwrap_or_else(|| Self(Uuid::new_v4().to_string()));
+        request.extensions_mut().insert(request_id.clone());
+        request_id
+    }
+
+    /// Echoes the id in the response headers and in the body of errors.
+    ///
+    /// Error bodies become a JSON object carrying a `request_id` field: empty
+    /// and plain text bodies are wrapped into an `error` field, and existing
+    /// objects gain the field. Other JSON bodies, such as the per-item arrays
+    /// of bulk endpoints, are left alone and only carry the header.
+    pub async fn decorate(&self, response: ServiceResponse<BoxBody>) -> ServiceResponse<BoxBody> {
+        let (request, mut response) = response.into_parts();
+        let value = HeaderValue::from_str(&self.0).expect("Request ids are valid header values");
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static("x-request-id"), value);
+        if !response.status().is_client_error() && !response.status().is_server_error() {
+            return ServiceResponse::new(request, response);
+        }
+
+        let (response, body) = response.into_parts();
+        let body = match to_bytes(body).await {
+            Ok(body) => body,
+            Err(_) => return ServiceResponse::new(request, response.set_body(BoxBody::new(()))),
+        };
+        let error_body = match serde_json::from_slice::<serde_json::Value>(&body) {
+            Ok(serde_json::Value::Object(mut fields)) => {
+                fields.insert("request_id".into(), self.0.clone().into());
+                Some(fields.into())
+            }
+            Ok(_) => None,
+            Err(_) => {
+                let error = match std::str::from_utf8(&body) {
+                    Ok(text) if !text.trim().is_empty() => text.to_owned(),
+                    _ => response
+                        .status()
+                        .canonical_reason()
+                        .unwrap_or("Error")
+                        .to_owned(),
+                };
+                serde_json::to_value(ErrorBody {
+                    error,
+                    request_id: self.0.clone(),
+                })
+                .ok()
+            }
+        };
+
+        let response = match error_body {
+            Some(error_body) => {
+                let mut response = response.set_body(BoxBody::new(error_body.to_string()));
+                // JSON bodies keep their media type, such as TAXII's.
+                let is_json = matches!(
+                    response.headers().get(header::CONTENT_TYPE).map(|value| value.to_str()),
+                    Some(Ok(value)) if value.contains("json")
+                );
+                if !is_json {
+                    response.headers_mut().insert(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static("application/json"),
+                    );
+                }
+                response
+            }
+            None => response.set_body(BoxBody::new(body)),
+        };
+        ServiceResponse::new(request, response)
+    }
+}
+
+/// The body of error responses that have no more specific one.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct ErrorBody {
+    pub error: String,
+    /// The `X-Request-Id` of the request, to quote when reporting the error.
+    pub request_id: String,
+}
+
+impl TryFrom<String> for RequestId {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let is_printable = s.chars().all(|c| c.is_ascii_graphic());
+        if s.is_empty() || s.len() > 128 || !is_printable {
+            return Err(format!("{} is not a valid request id.", s));
+        }
+        Ok(Self(s))
+    }
+}
+
+impl AsRef<str> for RequestId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Records the id assigned by `RequestId::assign` on the root span of each
+/// request, next to the fields `TracingLogger` records by default, along
+/// with the identity of the client when it authenticated with a certificate.
+pub struct RequestIdRootSpan;
+
+impl RootSpanBuilder for RequestIdRootSpan {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let correlation_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| id.0.clone())
+            .unwrap_or_default();
+        let client_identity = request
+            .conn_data::<ClientIdentity>()
+            .map(|identity| identity.as_ref().to_owned());
+        root_span!(
+            request,
+            correlation_id = %correlation_id,
+            client_identity = client_identity.as_deref()
+        )
+    }
+
+    fn on_request_end<B: MessageBody>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::request_id::RequestId;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn a_uuid_is_a_valid_request_id() {
+        assert_ok!(RequestId::try_from(
+            "9b2b8c8e-3f44-4c1e-9a53-2d9c3f0d7a11".to_string()
+        ));
+    }
+
+    #[test]
+    fn empty_long_or_unprintable_request_ids_are_rejected() {
+        for request_id in ["".to_string(), "a".repeat(129), "a b".into(), "é".into()] {
+            assert_err!(RequestId::try_from(request_id));
+        }
+    }
+}
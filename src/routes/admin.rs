@@ -0,0 +1,24 @@
+use actix_web::{web, HttpResponse};
+
+use crate::repository::SchemaRepository;
+use crate::tls::Operator;
+
+#[utoipa::path(
+    get,
+    path = "/admin/migrations",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Every migration, by version.", body = [MigrationStatus]),
+        (status = 403, description = "The client did not authenticate with a certificate.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Reporting migration status", skip(_operator, schema))]
+pub async fn migration_status(
+    _operator: Operator,
+    schema: web::Data<dyn SchemaRepository>,
+) -> HttpResponse {
+    match schema.migration_status().await {
+        Ok(migrations) => HttpResponse::Ok().json(migrations),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
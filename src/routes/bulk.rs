@@ -0,0 +1,319 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use uuid::Uuid;
+
+use crate::domain::{Marking, NewMarking};
+use crate::idempotency::{fingerprint, save_response, try_processing, IdempotencyKey, NextAction};
+use crate::repository::{IdempotencyRepository, MarkingRepository, RepositoryError};
+use crate::routes::markings::error_status;
+use crate::routes::JsonData;
+
+const MAX_BULK_ITEMS: usize = 500;
+
+#[derive(
+    serde::Deserialize,
+    serde::Serialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkMode {
+    /// Every item is written in a single transaction, or none is.
+    #[default]
+    Atomic,
+    /// Every valid item is written on its own, regardless of the others.
+    BestEffort,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BulkParameters {
+    /// Defaults to `atomic`.
+    #[serde(default)]
+    #[param(inline)]
+    mode: BulkMode,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+pub struct BulkUpdateData {
+    #[schema(value_type = String, format = "uuid")]
+    id: Uuid,
+    /// The version the update is based on.
+    version: i64,
+    #[serde(flatten)]
+    marking: JsonData,
+}
+
+/// The outcome of one item of a batch, in the order of the request.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct BulkItemResult {
+    /// The status the item would have had on its own, or 424 when it was not
+    /// written because another item failed.
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    marking: Option<Marking>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl BulkItemResult {
+    fn written(status: StatusCode, marking: Marking) -> Self {
+        Self {
+            status: status.as_u16(),
+            marking: Some(marking),
+            error: None,
+        }
+    }
+
+    fn failed(status: StatusCode, error: String) -> Self {
+        Self {
+            status: status.as_u16(),
+            marking: None,
+            error: Some(error),
+        }
+    }
+
+    /// Only tells clients why a write was refused: database errors are
+    /// logged where they happen and kept out of responses.
+    fn refused(e: RepositoryError) -> Self {
+        let error = match e {
+            RepositoryError::Database(_) => "The marking could not be saved.".into(),
+            ref e => e.to_string(),
+        };
+        Self::failed(error_status(&e), error)
+    }
+
+    fn not_attempted() -> Self {
+        Self::failed(
+            StatusCode::FAILED_DEPENDENCY,
+            "Another item of the batch failed.".into(),
+        )
+    }
+}
+
+enum Operation {
+    Insert(NewMarking),
+    Update {
+        id: Uuid,
+        version: i64,
+        marking: NewMarking,
+    },
+}
+
+impl Operation {
+    fn success_status(&self) -> StatusCode {
+        match self {
+            Operation::Insert(_) => StatusCode::CREATED,
+            Operation::Update { .. } => StatusCode::OK,
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/markings/bulk",
+    tag = "markings",
+    request_body = [JsonData],
+    params(
+        BulkParameters,
+        ("Idempotency-Key" = Option<String>, Header, description = "Replays the response of an earlier request with the same key."),
+    ),
+    responses(
+        (status = 201, description = "`atomic` mode: every marking was created.", body = [BulkItemResult]),
+        (status = 207, description = "`best_effort` mode: the outcome of each item.", body = [BulkItemResult]),
+        (status = 400, description = "The batch is empty or holds more than 500 items.", body = ErrorBody),
+        (status = 422, description = "`atomic` mode: an item failed and nothing was written.", body = [BulkItemResult]),
+    )
+)]
+#[tracing::instrument(
+    name = "Adding markings in bulk",
+    skip(request, form, repository, idempotency),
+    fields(mode = ?parameters.mode, items = form.len())
+)]
+pub async fn bulk_create_markings(
+    request: HttpRequest,
+    parameters: web::Query<BulkParameters>,
+    form: web::Json<Vec<JsonData>>,
+    repository: web::Data<dyn MarkingRepository>,
+    idempotency: web::Data<dyn IdempotencyRepository>,
+) -> HttpResponse {
+    const SCOPE: &str = "POST /markings/bulk";
+
+    let key = match IdempotencyKey::from_request(&request) {
+        Ok(key) => key,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    if let Some(key) = &key {
+        let request_fingerprint = fingerprint(&(&parameters.0, &form.0));
+        match try_processing(&**idempotency, SCOPE, key, &request_fingerprint).await {
+            Ok(NextAction::StartProcessing) => {}
+            Ok(NextAction::ReturnSavedResponse(response)) => return response,
+            Err(response) => return response,
+        }
+    }
+
+    let operations = form
+        .into_inner()
+        .into_iter()
+        .map(|item| item.try_into().map(Operation::Insert))
+        .collect();
+    let response = apply(parameters.mode, operations, &**repository).await;
+
+    match &key {
+        Some(key) => save_response(&**idempotency, SCOPE, key, response).await,
+        None => response,
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/markings/bulk",
+    tag = "markings",
+    request_body = [BulkUpdateData],
+    params(BulkParameters),
+    responses(
+        (status = 200, description = "`atomic` mode: every marking was replaced.", body = [BulkItemResult]),
+        (status = 207, description = "`best_effort` mode: the outcome of each item.", body = [BulkItemResult]),
+        (status = 400, description = "The batch is empty or holds more than 500 items.", body = ErrorBody),
+        (status = 422, description = "`atomic` mode: an item failed and nothing was written.", body = [BulkItemResult]),
+    )
+)]
+#[tracing::instrument(
+    name = "Updating markings in bulk",
+    skip(form, repository),
+    fields(mode = ?parameters.mode, items = form.len())
+)]
+pub async fn bulk_update_markings(
+    parameters: web::Query<BulkParameters>,
+    form: web::Json<Vec<BulkUpdateData>>,
+    repository: web::Data<dyn MarkingRepository>,
+) -> HttpResponse {
+    let operations = form
+        .into_inner()
+        .into_iter()
+        .map(|item| {
+            let id = item.id;
+            let version = item.version;
+            item.marking.try_into().map(|marking| Operation::Update {
+                id,
+                version,
+                marking,
+            })
+        })
+        .collect();
+    apply(parameters.mode, operations, &**repository).await
+}
+
+async fn apply(
+    mode: BulkMode,
+    operations: Vec<Result<Operation, String>>,
+    repository: &dyn MarkingRepository,
+) -> HttpResponse {
+    if operations.is_empty() || operations.len() > MAX_BULK_ITEMS {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    match mode {
+        BulkMode::Atomic => apply_atomically(operations, repository).await,
+        BulkMode::BestEffort => {
+            let mut results = Vec::with_capacity(operations.len());
+            for operation in operations {
+                results.push(match operation {
+                    Ok(operation) => {
+                        let status = operation.success_status();
+                        match write(repository, operation).await {
+                            Ok(marking) => BulkItemResult::written(status, marking),
+                            Err(e) => BulkItemResult::refused(e),
+                        }
+                    }
+                    Err(e) => BulkItemResult::failed(StatusCode::BAD_REQUEST, e),
+                });
+            }
+            HttpResponse::MultiStatus().json(results)
+        }
+    }
+}
+
+async fn apply_atomically(
+    operations: Vec<Result<Operation, String>>,
+    repository: &dyn MarkingRepository,
+) -> HttpResponse {
+    if operations.iter().any(Result::is_err) {
+        let results: Vec<_> = operations
+            .into_iter()
+            .map(|operation| match operation {
+                Ok(_) => BulkItemResult::not_attempted(),
+                Err(e) => BulkItemResult::failed(StatusCode::BAD_REQUEST, e),
+            })
+            .collect();
+        return HttpResponse::UnprocessableEntity().json(results);
+    }
+    let operations: Vec<_> = operations.into_iter().flatten().collect();
+    let success_status = operations[0].success_status();
+
+    let mut transaction = match repository.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let mut results = Vec::with_capacity(operations.len());
+    let mut failed = false;
+    for operation in operations {
+        if failed {
+            results.push(BulkItemResult::not_attempted());
+            continue;
+        }
+        let status = operation.success_status();
+        let written = match operation {
+            Operation::Insert(marking) => transaction.insert_marking(&marking).await,
+            Operation::Update {
+                id,
+                version,
+                marking,
+            } => {
+                transaction
+                    .update_marking(id, &marking, Some(version))
+                    .await
+            }
+        };
+        results.push(match written {
+            Ok(marking) => BulkItemResult::written(status, marking),
+            Err(e) => {
+                failed = true;
+                BulkItemResult::refused(e)
+            }
+        });
+    }
+
+    if failed {
+        if transaction.rollback().await.is_err() {
+            return HttpResponse::InternalServerError().finish();
+        }
+        for result in results.iter_mut().filter(|result| result.marking.is_some()) {
+            *result = BulkItemResult::not_attempted();
+        }
+        return HttpResponse::UnprocessableEntity().json(results);
+    }
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::build(success_status).json(results)
+}
+
+async fn write(
+    repository: &dyn MarkingRepository,
+    operation: Operation,
+) -> Result<Marking, RepositoryError> {
+    match operation {
+        Operation::Insert(marking) => repository.insert_marking(&marking).await,
+        Operation::Update {
+            id,
+            version,
+            marking,
+        } => repository.update_marking(id, &marking, Some(version)).await,
+    }
+}
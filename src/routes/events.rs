@@ -0,0 +1,160 @@
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+use crate::domain::OutboxEvent;
+use crate::outbox::EventFeed;
+use crate::repository::{OutboxRepository, RepositoryError};
+use crate::routes::markings::error_status;
+
+/// How long a quiet stream waits before sending a comment, so that proxies
+/// do not close it as idle.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+/// Most events read from the outbox at once.
+const BATCH_SIZE: i64 = 100;
+
+/// Closed as the server shuts down, which ends every stream: they would
+/// otherwise hold up its graceful shutdown until it times out.
+pub struct StreamShutdown(pub watch::Receiver<()>);
+
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "markings",
+    params(
+        ("Last-Event-ID" = Option<String>, Header, description = "Id of the last event received: the stream resumes right after it, provided the events that followed are within the outbox retention. Without it, only the events saved from now on are sent."),
+    ),
+    responses(
+        (status = 200, description = "Marking events as they are saved, as server-sent events. The `id` of each is its sequence, its `event` the type of the `MarkingEvent` in its `data`.", content_type = "text/event-stream", body = String),
+        (status = 400, description = "`Last-Event-ID` is not an event id.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "Streaming marking events",
+    skip(request, outbox, feed, shutdown)
+)]
+pub async fn stream_events(
+    request: HttpRequest,
+    outbox: web::Data<dyn OutboxRepository>,
+    feed: web::Data<EventFeed>,
+    shutdown: web::Data<StreamShutdown>,
+) -> HttpResponse {
+    let cursor = match request.headers().get("Last-Event-ID") {
+        Some(value) => {
+            match value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<i64>().ok())
+            {
+                Some(sequence) if sequence >= 0 => sequence,
+                _ => return HttpResponse::BadRequest().finish(),
+            }
+        }
+        None => match outbox.last_outbox_sequence().await {
+            Ok(sequence) => sequence,
+            Err(e) => return HttpResponse::build(error_status(&e)).finish(),
+        },
+    };
+
+    let stream = EventStream {
+        outbox: outbox.into_inner(),
+        feed: feed.subscribe(),
+        shutdown: shutdown.0.clone(),
+        cursor,
+        pending: VecDeque::new(),
+    };
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .content_type("text/event-stream")
+        .streaming(futures::stream::unfold(stream, EventStream::next_frame))
+}
+
+/// The events of one client, read from the outbox after `cursor` whenever
+/// `feed` tells of newer ones, until `shutdown` is closed.
+struct EventStream {
+    outbox: Arc<dyn OutboxRepository>,
+    feed: watch::Receiver<i64>,
+    shutdown: watch::Receiver<()>,
+    cursor: i64,
+    pending: VecDeque<OutboxEvent>,
+}
+
+impl EventStream {
+    async fn next_frame(mut self) -> Option<(Result<Bytes, RepositoryError>, Self)> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                self.cursor = event.sequence;
+                return Some((Ok(frame(&event)), self));
+            }
+            let last = *self.feed.borrow_and_update();
+            if last > self.cursor {
+                match self
+                    .outbox
+                    .list_outbox_events(self.cursor, BATCH_SIZE)
+                    .await
+                {
+                    Ok(events) if !events.is_empty() => {
+                        self.pending.extend(events);
+                        continue;
+                    }
+                    Ok(_) => {}
+                    // Ends the stream: the client reconnects with the id of
+                    // the last event it got.
+                    Err(e) => return Some((Err(e), self)),
+                }
+            }
+            tokio::select! {
+                // The client reconnects to another replica, or to this one
+                // once restarted.
+                _ = self.shutdown.changed() => return None,
+                changed = self.feed.changed() => {
+                    if changed.is_err() {
+                        return None;
+                    }
+                }
+                _ = tokio::time::sleep(KEEP_ALIVE) => {
+                    return Some((Ok(Bytes::from_static(b": keep-alive\n\n")), self));
+                }
+            }
+        }
+    }
+}
+
+/// Payloads are compact JSON, so `data` always fits on a single line.
+fn frame(event: &OutboxEvent) -> Bytes {
+    Bytes::from(format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        event.sequence, event.event_type, event.payload
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repository::InMemoryMarkingRepository;
+    use crate::routes::events::EventStream;
+    use claim::assert_none;
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+    use tokio::sync::watch;
+
+    #[tokio::test]
+    async fn streams_end_once_the_server_shuts_down() {
+        let (_feed, feed_receiver) = watch::channel(0);
+        let (shutdown, shutdown_receiver) = watch::channel(());
+        let stream = EventStream {
+            outbox: Arc::new(InMemoryMarkingRepository::new()),
+            feed: feed_receiver,
+            shutdown: shutdown_receiver,
+            cursor: 0,
+            pending: VecDeque::new(),
+        };
+
+        drop(shutdown);
+
+        assert_none!(stream.next_frame().await.map(|(frame, _)| frame));
+    }
+}
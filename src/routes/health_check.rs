@@ -1,5 +1,120 @@
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
+use std::collections::BTreeMap;
 
+use crate::repository::{MigrationState, SchemaRepository};
+
+const UNREACHABLE: &str = "unreachable";
+
+#[utoipa::path(
+    get,
+    path = "/health_check",
+    tag = "operations",
+    responses((status = 200, description = "The process is up."))
+)]
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+/// The process is up and serving requests, whatever the state of its
+/// dependencies.
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "operations",
+    responses((status = 200, description = "The process is up."))
+)]
+pub async fn health_live() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Readiness {
+    ready: bool,
+    /// The `database` and `migrations` checks.
+    #[schema(value_type = BTreeMap<String, Check>)]
+    checks: BTreeMap<&'static str, Check>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Check {
+    ready: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl Check {
+    fn ready() -> Self {
+        Self {
+            ready: true,
+            detail: None,
+        }
+    }
+
+    /// Readiness is served to anyone, so `detail` only says what is wrong:
+    /// why is logged, against the id of the request.
+    fn failed(detail: &str) -> Self {
+        Self {
+            ready: false,
+            detail: Some(detail.to_owned()),
+        }
+    }
+}
+
+/// The process can serve traffic: its database is reachable and its schema
+/// is the one this build expects. Answers 503 with the failed checks when not.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Every check passed.", body = Readiness),
+        (status = 503, description = "A check failed.", body = Readiness),
+    )
+)]
+#[tracing::instrument(name = "Checking readiness", skip(schema))]
+pub async fn health_ready(schema: web::Data<dyn SchemaRepository>) -> HttpResponse {
+    let mut checks = BTreeMap::new();
+    checks.insert(
+        "database",
+        match schema.ping().await {
+            Ok(()) => Check::ready(),
+            Err(e) => {
+                tracing::error!(error.message = %e, "The database is unreachable.");
+                Check::failed(UNREACHABLE)
+            }
+        },
+    );
+    checks.insert(
+        "migrations",
+        match schema.migration_status().await {
+            Ok(migrations) => {
+                let outdated = migrations
+                    .iter()
+                    .filter(|m| {
+                        matches!(m.state, MigrationState::Pending | MigrationState::Modified)
+                    })
+                    .count();
+                if outdated == 0 {
+                    Check::ready()
+                } else {
+                    tracing::error!("{} migrations are pending or modified.", outdated);
+                    Check::failed("schema mismatch")
+                }
+            }
+            Err(e) => {
+                tracing::error!(error.message = %e, "Failed to read the migration status.");
+                Check::failed(UNREACHABLE)
+            }
+        },
+    );
+
+    let readiness = Readiness {
+        ready: checks.values().all(|check| check.ready),
+        checks,
+    };
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
@@ -1,11 +1,20 @@
-use actix_web::{web, HttpResponse};
-use chrono::Utc;
-use sqlx::PgPool;
+use actix_web::http::header::{self, ETag, EntityTag, IfMatch, IfNoneMatch};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
 use uuid::Uuid;
 
-use crate::domain::{MarkingDefinition, MarkingDefinitionType, MarkingName, NewMarking};
+use crate::domain::{Marking, MarkingStatus, NewMarking, ObjectRef};
+use crate::idempotency::{fingerprint, save_response, try_processing, IdempotencyKey, NextAction};
+use crate::metrics::record_validation_failure;
+use crate::repository::{IdempotencyRepository, MarkingRepository, RepositoryError};
 
-#[derive(serde::Deserialize)]
+/// A marking as written by clients.
+#[derive(serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+#[schema(example = json!({
+    "name": "tlp_red",
+    "definition_type": "tlp",
+    "definition": "TLP Red"
+}))]
 pub struct JsonData {
     name: String,
     definition_type: String,
@@ -15,59 +24,539 @@ pub struct JsonData {
 impl TryFrom<JsonData> for NewMarking {
     type Error = String;
 
+    /// Counts each invalid field of a rejected payload.
     fn try_from(value: JsonData) -> Result<Self, Self::Error> {
-        let name = MarkingName::parse(value.name)?;
-        let definition = MarkingDefinition::parse(value.definition)?;
-        let definition_type = MarkingDefinitionType::parse(value.definition_type)?;
-
-        Ok(Self {
-            name,
-            definition_type,
-            definition,
+        NewMarking::parse(value.name, value.definition_type, value.definition).map_err(|e| {
+            for field in e.fields() {
+                record_validation_failure(field);
+            }
+            e.to_string()
         })
     }
 }
 
+impl From<Marking> for JsonData {
+    fn from(marking: Marking) -> Self {
+        Self {
+            name: marking.name,
+            definition_type: marking.definition_type,
+            definition: marking.definition,
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/markings",
+    tag = "markings",
+    request_body = JsonData,
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Replays the response of an earlier request with the same key."),
+    ),
+    responses(
+        (status = 201, description = "The marking was created.", body = Marking),
+        (status = 400, description = "A field is missing or invalid.", body = ErrorBody),
+        (status = 409, description = "A marking with this name already exists.", body = ErrorBody),
+        (status = 422, description = "The idempotency key was used for another request.", body = ErrorBody),
+    )
+)]
 #[tracing::instrument(
     name = "Adding a new marking",
-    skip(form, pool),
+    skip(request, form, repository, idempotency),
+    fields(
+        marking_name = %form.name,
+        marking_type = %form.definition_type,
+        marking_definition = %form.definition
+    )
+)]
+pub async fn create_marking(
+    request: HttpRequest,
+    form: web::Json<JsonData>,
+    repository: web::Data<dyn MarkingRepository>,
+    idempotency: web::Data<dyn IdempotencyRepository>,
+) -> HttpResponse {
+    const SCOPE: &str = "POST /markings";
+
+    let key = match IdempotencyKey::from_request(&request) {
+        Ok(Some(key)) => key,
+        Ok(None) => return insert_new_marking(form.0, &**repository).await,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    match try_processing(&**idempotency, SCOPE, &key, &fingerprint(&form.0)).await {
+        Ok(NextAction::StartProcessing) => {}
+        Ok(NextAction::ReturnSavedResponse(response)) => return response,
+        Err(response) => return response,
+    }
+
+    let response = insert_new_marking(form.0, &**repository).await;
+    save_response(&**idempotency, SCOPE, &key, response).await
+}
+
+async fn insert_new_marking(form: JsonData, repository: &dyn MarkingRepository) -> HttpResponse {
+    let new_marking = match form.try_into() {
+        Ok(marking) => marking,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+
+    match repository.insert_marking(&new_marking).await {
+        Ok(marking) => HttpResponse::Created()
+            .insert_header(ETag(etag(&marking)))
+            .json(marking),
+        Err(e) => error_response(e),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/markings",
+    tag = "markings",
+    responses((status = 200, description = "Every marking.", body = [Marking]))
+)]
+#[tracing::instrument(name = "Listing markings", skip(repository))]
+pub async fn list_markings(repository: web::Data<dyn MarkingRepository>) -> HttpResponse {
+    match repository.list_markings().await {
+        Ok(markings) => HttpResponse::Ok().json(markings),
+        Err(e) => error_response(e),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/markings/{id}",
+    tag = "markings",
+    params(
+        ("id" = String, Path, description = "Id of the marking, a UUID."),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a version the client has."),
+    ),
+    responses(
+        (status = 200, description = "The marking, with its version as ETag.", body = Marking),
+        (status = 304, description = "The marking is still at the version in `If-None-Match`."),
+        (status = 404, description = "No marking has this id.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Fetching a marking", skip(request, repository))]
+pub async fn get_marking(
+    request: HttpRequest,
+    id: web::Path<Uuid>,
+    repository: web::Data<dyn MarkingRepository>,
+) -> HttpResponse {
+    let marking = match repository.get_marking(id.into_inner()).await {
+        Ok(marking) => marking,
+        Err(e) => return error_response(e),
+    };
+
+    let etag = etag(&marking);
+    let is_unchanged = match request.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        None => false,
+    };
+    if is_unchanged {
+        return HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish();
+    }
+    HttpResponse::Ok().insert_header(ETag(etag)).json(marking)
+}
+
+#[utoipa::path(
+    put,
+    path = "/markings/{id}",
+    tag = "markings",
+    request_body = JsonData,
+    params(
+        ("id" = String, Path, description = "Id of the marking, a UUID."),
+        ("If-Match" = String, Header, description = "ETag of the version the write is based on."),
+    ),
+    responses(
+        (status = 200, description = "The marking was replaced.", body = Marking),
+        (status = 400, description = "A field is invalid.", body = ErrorBody),
+        (status = 404, description = "No marking has this id.", body = ErrorBody),
+        (status = 409, description = "Another marking has this name.", body = ErrorBody),
+        (status = 412, description = "The marking has changed since `If-Match`.", body = ErrorBody),
+        (status = 428, description = "`If-Match` is missing.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "Updating a marking",
+    skip(request, form, repository),
     fields(
         marking_name = %form.name,
         marking_type = %form.definition_type,
         marking_definition = %form.definition
     )
 )]
-pub async fn create_marking(form: web::Json<JsonData>, pool: web::Data<PgPool>) -> HttpResponse {
-    let new_marking = match form.0.try_into() {
+pub async fn update_marking(
+    request: HttpRequest,
+    id: web::Path<Uuid>,
+    form: web::Json<JsonData>,
+    repository: web::Data<dyn MarkingRepository>,
+) -> HttpResponse {
+    let marking = match form.0.try_into() {
         Ok(marking) => marking,
         Err(_) => return HttpResponse::BadRequest().finish(),
     };
+    let current = match current_marking(&request, id.into_inner(), &**repository).await {
+        Ok(current) => current,
+        Err(response) => return response,
+    };
+
+    match repository
+        .update_marking(current.id, &marking, Some(current.version))
+        .await
+    {
+        Ok(marking) => HttpResponse::Ok()
+            .insert_header(ETag(etag(&marking)))
+            .json(marking),
+        Err(e) => error_response(e),
+    }
+}
+
+/// The fields of a marking to change, the others are kept.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct PatchData {
+    name: Option<String>,
+    definition_type: Option<String>,
+    definition: Option<String>,
+}
 
-    match insert_marking(&pool, &new_marking).await {
-        Ok(_) => HttpResponse::Created().finish(),
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
-}
-
-#[tracing::instrument(name = "Saving new marking in the database", skip(new_marking, pool))]
-pub async fn insert_marking(pool: &PgPool, new_marking: &NewMarking) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"
-        INSERT INTO markings (id, name, definition_type, definition, created_at, created_by)
-        VALUES ($1, $2, $3, $4, $5, $6)
-        "#,
-        Uuid::new_v4(),
-        new_marking.name.as_ref(),
-        new_marking.definition_type.as_ref(),
-        new_marking.definition.as_ref(),
-        Utc::now(),
-        Uuid::new_v4()
+#[utoipa::path(
+    patch,
+    path = "/markings/{id}",
+    tag = "markings",
+    request_body = PatchData,
+    params(
+        ("id" = String, Path, description = "Id of the marking, a UUID."),
+        ("If-Match" = String, Header, description = "ETag of the version the write is based on."),
+    ),
+    responses(
+        (status = 200, description = "The marking was changed.", body = Marking),
+        (status = 400, description = "A field is invalid.", body = ErrorBody),
+        (status = 404, description = "No marking has this id.", body = ErrorBody),
+        (status = 409, description = "Another marking has this name.", body = ErrorBody),
+        (status = 412, description = "The marking has changed since `If-Match`.", body = ErrorBody),
+        (status = 428, description = "`If-Match` is missing.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Patching a marking", skip(request, form, repository))]
+pub async fn patch_marking(
+    request: HttpRequest,
+    id: web::Path<Uuid>,
+    form: web::Json<PatchData>,
+    repository: web::Data<dyn MarkingRepository>,
+) -> HttpResponse {
+    let current = match current_marking(&request, id.into_inner(), &**repository).await {
+        Ok(current) => current,
+        Err(response) => return response,
+    };
+    let PatchData {
+        name,
+        definition_type,
+        definition,
+    } = form.into_inner();
+    let merged = JsonData {
+        name: name.unwrap_or(current.name),
+        definition_type: definition_type.unwrap_or(current.definition_type),
+        definition: definition.unwrap_or(current.definition),
+    };
+    let marking = match merged.try_into() {
+        Ok(marking) => marking,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+
+    match repository
+        .update_marking(current.id, &marking, Some(current.version))
+        .await
+    {
+        Ok(marking) => HttpResponse::Ok()
+            .insert_header(ETag(etag(&marking)))
+            .json(marking),
+        Err(e) => error_response(e),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/markings/{id}",
+    tag = "markings",
+    params(
+        ("id" = String, Path, description = "Id of the marking, a UUID."),
+        ("If-Match" = String, Header, description = "ETag of the version the write is based on."),
+    ),
+    responses(
+        (status = 204, description = "The marking was deleted."),
+        (status = 404, description = "No marking has this id.", body = ErrorBody),
+        (status = 412, description = "The marking has changed since `If-Match`.", body = ErrorBody),
+        (status = 428, description = "`If-Match` is missing.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Deleting a marking", skip(request, repository))]
+pub async fn delete_marking(
+    request: HttpRequest,
+    id: web::Path<Uuid>,
+    repository: web::Data<dyn MarkingRepository>,
+) -> HttpResponse {
+    let current = match current_marking(&request, id.into_inner(), &**repository).await {
+        Ok(current) => current,
+        Err(response) => return response,
+    };
+
+    match repository
+        .delete_marking(current.id, Some(current.version))
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => error_response(e),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/markings/{id}/deprecate",
+    tag = "markings",
+    params(
+        ("id" = String, Path, description = "Id of the marking, a UUID."),
+        ("If-Match" = String, Header, description = "ETag of the version the write is based on."),
+    ),
+    responses(
+        (status = 200, description = "The marking is deprecated: it should no longer be applied.", body = Marking),
+        (status = 404, description = "No marking has this id.", body = ErrorBody),
+        (status = 409, description = "The marking is already deprecated, or revoked.", body = ErrorBody),
+        (status = 412, description = "The marking has changed since `If-Match`.", body = ErrorBody),
+        (status = 428, description = "`If-Match` is missing.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Deprecating a marking", skip(request, repository))]
+pub async fn deprecate_marking(
+    request: HttpRequest,
+    id: web::Path<Uuid>,
+    repository: web::Data<dyn MarkingRepository>,
+) -> HttpResponse {
+    change_status(
+        &request,
+        id.into_inner(),
+        MarkingStatus::Deprecated,
+        &**repository,
+    )
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/markings/{id}/revoke",
+    tag = "markings",
+    params(
+        ("id" = String, Path, description = "Id of the marking, a UUID."),
+        ("If-Match" = String, Header, description = "ETag of the version the write is based on."),
+    ),
+    responses(
+        (status = 200, description = "The marking is revoked: it no longer holds, and cannot be assigned.", body = Marking),
+        (status = 404, description = "No marking has this id.", body = ErrorBody),
+        (status = 409, description = "The marking is already revoked.", body = ErrorBody),
+        (status = 412, description = "The marking has changed since `If-Match`.", body = ErrorBody),
+        (status = 428, description = "`If-Match` is missing.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Revoking a marking", skip(request, repository))]
+pub async fn revoke_marking(
+    request: HttpRequest,
+    id: web::Path<Uuid>,
+    repository: web::Data<dyn MarkingRepository>,
+) -> HttpResponse {
+    change_status(
+        &request,
+        id.into_inner(),
+        MarkingStatus::Revoked,
+        &**repository,
     )
-    .execute(pool)
     .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    Ok(())
+}
+
+async fn change_status(
+    request: &HttpRequest,
+    id: Uuid,
+    status: MarkingStatus,
+    repository: &dyn MarkingRepository,
+) -> HttpResponse {
+    let current = match current_marking(request, id, repository).await {
+        Ok(current) => current,
+        Err(response) => return response,
+    };
+
+    match repository
+        .set_marking_status(current.id, status, Some(current.version))
+        .await
+    {
+        Ok(marking) => HttpResponse::Ok()
+            .insert_header(ETag(etag(&marking)))
+            .json(marking),
+        Err(e) => error_response(e),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/markings/{id}/assignments/{object_ref}",
+    tag = "markings",
+    params(
+        ("id" = String, Path, description = "Id of the marking, a UUID."),
+        ("object_ref" = String, Path, description = "STIX id of the object, such as `indicator--8e2e2d2b-17d4-4cbf-938f-98ee46b3cd3f`."),
+    ),
+    responses(
+        (status = 200, description = "The marking is assigned to the object, since the first time it was.", body = MarkingAssignment),
+        (status = 400, description = "`object_ref` is not a STIX id.", body = ErrorBody),
+        (status = 404, description = "No marking has this id.", body = ErrorBody),
+        (status = 409, description = "The marking is revoked.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Assigning a marking", skip(path, repository))]
+pub async fn assign_marking(
+    path: web::Path<(Uuid, String)>,
+    repository: web::Data<dyn MarkingRepository>,
+) -> HttpResponse {
+    let (id, object_ref) = path.into_inner();
+    let object_ref = match ObjectRef::parse(object_ref) {
+        Ok(object_ref) => object_ref,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+
+    match repository.assign_marking(id, &object_ref).await {
+        Ok(assignment) => HttpResponse::Ok().json(assignment),
+        Err(e) => error_response(e),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/markings/{id}/assignments",
+    tag = "markings",
+    params(("id" = String, Path, description = "Id of the marking, a UUID.")),
+    responses(
+        (status = 200, description = "The objects the marking is assigned to, first assigned first.", body = [MarkingAssignment]),
+        (status = 404, description = "No marking has this id.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Listing marking assignments", skip(repository))]
+pub async fn list_marking_assignments(
+    id: web::Path<Uuid>,
+    repository: web::Data<dyn MarkingRepository>,
+) -> HttpResponse {
+    let id = id.into_inner();
+    if let Err(e) = repository.get_marking(id).await {
+        return error_response(e);
+    }
+
+    match repository.list_assignments(id).await {
+        Ok(assignments) => HttpResponse::Ok().json(assignments),
+        Err(e) => error_response(e),
+    }
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchParameters {
+    /// Words to look for in names and definitions.
+    q: String,
+    /// Largest number of results, from 1 to 100. Defaults to 20.
+    limit: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/markings/search",
+    tag = "markings",
+    params(SearchParameters),
+    responses(
+        (status = 200, description = "The best matches first.", body = [SearchResult]),
+        (status = 400, description = "The query is empty.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "Searching markings",
+    skip(parameters, repository),
+    fields(search_query = %parameters.q)
+)]
+pub async fn search_markings(
+    parameters: web::Query<SearchParameters>,
+    repository: web::Data<dyn MarkingRepository>,
+) -> HttpResponse {
+    if parameters.q.trim().is_empty() {
+        return HttpResponse::BadRequest().finish();
+    }
+    let limit = parameters.limit.unwrap_or(20).clamp(1, 100);
+
+    match repository.search_markings(&parameters.q, limit).await {
+        Ok(results) => HttpResponse::Ok().json(results),
+        Err(e) => error_response(e),
+    }
+}
+
+fn etag(marking: &Marking) -> EntityTag {
+    EntityTag::new_strong(marking.version.to_string())
+}
+
+/// Fetches the marking a write is aimed at and checks it against the
+/// `If-Match` header, which every write must carry so that concurrent edits
+/// cannot silently overwrite each other.
+async fn current_marking(
+    request: &HttpRequest,
+    id: Uuid,
+    repository: &dyn MarkingRepository,
+) -> Result<Marking, HttpResponse> {
+    if !request.headers().contains_key(header::IF_MATCH) {
+        return Err(HttpResponse::PreconditionRequired().finish());
+    }
+    let if_match = request
+        .get_header::<IfMatch>()
+        .ok_or_else(|| HttpResponse::BadRequest().finish())?;
+
+    let current = repository.get_marking(id).await.map_err(error_response)?;
+    let matches = match if_match {
+        IfMatch::Any => true,
+        IfMatch::Items(tags) => tags.iter().any(|tag| tag.strong_eq(&etag(&current))),
+    };
+    if !matches {
+        return Err(HttpResponse::PreconditionFailed().finish());
+    }
+    Ok(current)
+}
+
+fn error_response(e: RepositoryError) -> HttpResponse {
+    HttpResponse::build(error_status(&e)).finish()
+}
+
+pub(crate) fn error_status(e: &RepositoryError) -> StatusCode {
+    match e {
+        RepositoryError::DuplicateName(_)
+        | RepositoryError::ForeignMarking(_)
+        | RepositoryError::WrongStatus(..) => StatusCode::CONFLICT,
+        RepositoryError::NotFound(_) | RepositoryError::WebhookNotFound(_) => StatusCode::NOT_FOUND,
+        RepositoryError::VersionMismatch(..) => StatusCode::PRECONDITION_FAILED,
+        RepositoryError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::NewMarking;
+    use crate::metrics::VALIDATION_FAILURES;
+    use crate::routes::JsonData;
+    use claim::assert_err;
+
+    #[test]
+    fn every_invalid_field_of_a_payload_is_counted() {
+        let fields = ["name", "definition_type", "definition"];
+        let before = fields.map(|field| VALIDATION_FAILURES.with_label_values(&[field]).get());
+        let payload = JsonData {
+            name: "".into(),
+            definition_type: "unknown".into(),
+            definition: "".into(),
+        };
+
+        assert_err!(NewMarking::try_from(payload));
+
+        for (field, before) in fields.into_iter().zip(before) {
+            assert!(VALIDATION_FAILURES.with_label_values(&[field]).get() > before);
+        }
+    }
 }
@@ -0,0 +1,31 @@
+use actix_web::{web, HttpResponse};
+use prometheus::TEXT_FORMAT;
+
+use crate::metrics::{encode, record_connections, record_markings};
+use crate::repository::{MarkingRepository, SchemaRepository};
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "operations",
+    responses((status = 200, description = "Every metric, in the Prometheus text format.", body = String, content_type = "text/plain"))
+)]
+#[tracing::instrument(name = "Exporting metrics", skip(markings, schema))]
+pub async fn metrics(
+    markings: web::Data<dyn MarkingRepository>,
+    schema: web::Data<dyn SchemaRepository>,
+) -> HttpResponse {
+    // Stored state is sampled on scrape rather than tracked on every write.
+    match markings.count_markings_by_type().await {
+        Ok(counts) => record_markings(counts),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    if let Some(connections) = schema.connections() {
+        record_connections(connections);
+    }
+
+    match encode() {
+        Ok(body) => HttpResponse::Ok().content_type(TEXT_FORMAT).body(body),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
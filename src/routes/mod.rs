@@ -1,5 +1,17 @@
+mod admin;
+mod bulk;
+mod events;
 mod health_check;
 mod markings;
+mod metrics;
+mod taxii;
+mod webhooks;
 
+pub use admin::*;
+pub use bulk::*;
+pub use events::*;
 pub use health_check::*;
 pub use markings::*;
+pub use metrics::*;
+pub use taxii::*;
+pub use webhooks::*;
@@ -0,0 +1,546 @@
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::domain::Marking;
+use crate::repository::MarkingRepository;
+use crate::taxii::{
+    date_added, last_written, stix_id, timestamp, ApiRoot, Collection, Collections, Discovery,
+    Envelope, Manifest, ManifestRecord, StixMarkingDefinition, TaxiiError, Versions,
+    DATE_ADDED_FIRST_HEADER, DATE_ADDED_LAST_HEADER, STIX_MEDIA_TYPE, TAXII_MEDIA_TYPE,
+};
+
+const API_ROOT_PATH: &str = "/taxii2/api/";
+/// The only collection, which holds every marking.
+pub const MARKINGS_COLLECTION_ID: &str = "4e0b6a5e-3c9c-4c4a-9a36-0f5d7f6a1c2b";
+pub const MARKINGS_COLLECTION_ALIAS: &str = "marking-definitions";
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// What the API root advertises, taken from `application` at startup.
+pub struct TaxiiConfig {
+    pub max_content_length: usize,
+}
+
+#[derive(serde::Deserialize, Default, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TaxiiFilters {
+    /// Only objects added after this timestamp.
+    added_after: Option<String>,
+    /// Objects per page, at most 1000. Defaults to 100.
+    limit: Option<usize>,
+    /// The `next` of the previous page.
+    next: Option<String>,
+    /// Comma separated STIX ids.
+    #[serde(rename = "match[id]")]
+    #[param(rename = "match[id]")]
+    match_id: Option<String>,
+    /// Comma separated STIX types.
+    #[serde(rename = "match[type]")]
+    #[param(rename = "match[type]")]
+    match_type: Option<String>,
+    /// `last`, `first`, `all` or comma separated timestamps. Defaults to
+    /// `last`.
+    #[serde(rename = "match[version]")]
+    #[param(rename = "match[version]")]
+    match_version: Option<String>,
+    /// Comma separated STIX versions.
+    #[serde(rename = "match[spec_version]")]
+    #[param(rename = "match[spec_version]")]
+    match_spec_version: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/taxii2/",
+    tag = "taxii",
+    responses(
+        (status = 200, description = "The API roots of this server.", body = Discovery, content_type = "application/taxii+json;version=2.1"),
+        (status = 406, description = "The client does not accept TAXII 2.1.", body = TaxiiError),
+    )
+)]
+pub async fn taxii_discovery(request: HttpRequest) -> HttpResponse {
+    if let Err(rejection) = check_accept(&request) {
+        return rejection.into();
+    }
+    let connection = request.connection_info();
+    let api_root = format!(
+        "{}://{}{}",
+        connection.scheme(),
+        connection.host(),
+        API_ROOT_PATH
+    );
+    taxii_response(
+        StatusCode::OK,
+        &Discovery {
+            title: "metaman".into(),
+            description: Some("Data markings, as STIX marking definitions.".into()),
+            default: Some(api_root.clone()),
+            api_roots: vec![api_root],
+        },
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/taxii2/api/",
+    tag = "taxii",
+    responses(
+        (status = 200, description = "The versions and limits of the API root.", body = ApiRoot, content_type = "application/taxii+json;version=2.1"),
+        (status = 406, description = "The client does not accept TAXII 2.1.", body = TaxiiError),
+    )
+)]
+pub async fn taxii_api_root(request: HttpRequest, config: web::Data<TaxiiConfig>) -> HttpResponse {
+    if let Err(rejection) = check_accept(&request) {
+        return rejection.into();
+    }
+    taxii_response(
+        StatusCode::OK,
+        &ApiRoot {
+            title: "metaman".into(),
+            description: None,
+            versions: vec![TAXII_MEDIA_TYPE.into()],
+            max_content_length: config.max_content_length,
+        },
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/taxii2/api/collections/",
+    tag = "taxii",
+    responses(
+        (status = 200, description = "Every collection.", body = Collections, content_type = "application/taxii+json;version=2.1"),
+        (status = 406, description = "The client does not accept TAXII 2.1.", body = TaxiiError),
+    )
+)]
+pub async fn taxii_collections(request: HttpRequest) -> HttpResponse {
+    if let Err(rejection) = check_accept(&request) {
+        return rejection.into();
+    }
+    taxii_response(
+        StatusCode::OK,
+        &Collections {
+            collections: vec![markings_collection()],
+        },
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/taxii2/api/collections/{collection}/",
+    tag = "taxii",
+    params(("collection" = String, Path, description = "Id or alias of the collection.")),
+    responses(
+        (status = 200, description = "The collection.", body = Collection, content_type = "application/taxii+json;version=2.1"),
+        (status = 404, description = "No collection has this id.", body = TaxiiError),
+        (status = 406, description = "The client does not accept TAXII 2.1.", body = TaxiiError),
+    )
+)]
+pub async fn taxii_collection(request: HttpRequest, collection: web::Path<String>) -> HttpResponse {
+    if let Err(rejection) = check_accept(&request).and_then(|()| check_collection(&collection)) {
+        return rejection.into();
+    }
+    taxii_response(StatusCode::OK, &markings_collection())
+}
+
+#[utoipa::path(
+    get,
+    path = "/taxii2/api/collections/{collection}/objects/",
+    tag = "taxii",
+    params(
+        ("collection" = String, Path, description = "Id or alias of the collection."),
+        TaxiiFilters,
+    ),
+    responses(
+        (status = 200, description = "A page of marking definitions, oldest first.", body = Envelope, content_type = "application/taxii+json;version=2.1"),
+        (status = 400, description = "A filter is invalid.", body = TaxiiError),
+        (status = 404, description = "No collection has this id.", body = TaxiiError),
+        (status = 406, description = "The client does not accept TAXII 2.1.", body = TaxiiError),
+    )
+)]
+#[tracing::instrument(name = "Serving TAXII objects", skip(request, repository))]
+pub async fn taxii_objects(
+    request: HttpRequest,
+    collection: web::Path<String>,
+    repository: web::Data<dyn MarkingRepository>,
+) -> HttpResponse {
+    match select(&request, &collection, None, &**repository).await {
+        Ok(page) => envelope_response(page),
+        Err(rejection) => rejection.into(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/taxii2/api/collections/{collection}/objects/{object_id}/",
+    tag = "taxii",
+    params(
+        ("collection" = String, Path, description = "Id or alias of the collection."),
+        ("object_id" = String, Path, description = "STIX id of the marking definition."),
+        TaxiiFilters,
+    ),
+    responses(
+        (status = 200, description = "The marking definition.", body = Envelope, content_type = "application/taxii+json;version=2.1"),
+        (status = 404, description = "No such collection or object.", body = TaxiiError),
+        (status = 406, description = "The client does not accept TAXII 2.1.", body = TaxiiError),
+    )
+)]
+#[tracing::instrument(name = "Serving a TAXII object", skip(request, repository))]
+pub async fn taxii_object(
+    request: HttpRequest,
+    path: web::Path<(String, String)>,
+    repository: web::Data<dyn MarkingRepository>,
+) -> HttpResponse {
+    let (collection, object_id) = path.into_inner();
+    match select(&request, &collection, Some(&object_id), &**repository).await {
+        Ok(page) if page.markings.is_empty() => object_not_found(&object_id).into(),
+        Ok(page) => envelope_response(page),
+        Err(rejection) => rejection.into(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/taxii2/api/collections/{collection}/objects/{object_id}/versions/",
+    tag = "taxii",
+    params(
+        ("collection" = String, Path, description = "Id or alias of the collection."),
+        ("object_id" = String, Path, description = "STIX id of the marking definition."),
+        TaxiiFilters,
+    ),
+    responses(
+        (status = 200, description = "The versions of the marking definition.", body = Versions, content_type = "application/taxii+json;version=2.1"),
+        (status = 404, description = "No such collection or object.", body = TaxiiError),
+        (status = 406, description = "The client does not accept TAXII 2.1.", body = TaxiiError),
+    )
+)]
+#[tracing::instrument(name = "Serving TAXII object versions", skip(request, repository))]
+pub async fn taxii_versions(
+    request: HttpRequest,
+    path: web::Path<(String, String)>,
+    repository: web::Data<dyn MarkingRepository>,
+) -> HttpResponse {
+    let (collection, object_id) = path.into_inner();
+    let page = match select(&request, &collection, Some(&object_id), &**repository).await {
+        Ok(page) if page.markings.is_empty() => return object_not_found(&object_id).into(),
+        Ok(page) => page,
+        Err(rejection) => return rejection.into(),
+    };
+    let versions = Versions {
+        more: page.more,
+        next: page.next.clone(),
+        versions: page
+            .markings
+            .iter()
+            .map(|marking| timestamp(last_written(marking)))
+            .collect(),
+    };
+    page.with_date_headers(taxii_response(StatusCode::OK, &versions))
+}
+
+#[utoipa::path(
+    get,
+    path = "/taxii2/api/collections/{collection}/manifest/",
+    tag = "taxii",
+    params(
+        ("collection" = String, Path, description = "Id or alias of the collection."),
+        TaxiiFilters,
+    ),
+    responses(
+        (status = 200, description = "A page of the marking definitions, without their content.", body = Manifest, content_type = "application/taxii+json;version=2.1"),
+        (status = 400, description = "A filter is invalid.", body = TaxiiError),
+        (status = 404, description = "No collection has this id.", body = TaxiiError),
+        (status = 406, description = "The client does not accept TAXII 2.1.", body = TaxiiError),
+    )
+)]
+#[tracing::instrument(name = "Serving the TAXII manifest", skip(request, repository))]
+pub async fn taxii_manifest(
+    request: HttpRequest,
+    collection: web::Path<String>,
+    repository: web::Data<dyn MarkingRepository>,
+) -> HttpResponse {
+    let page = match select(&request, &collection, None, &**repository).await {
+        Ok(page) => page,
+        Err(rejection) => return rejection.into(),
+    };
+    let manifest = Manifest {
+        more: page.more,
+        next: page.next.clone(),
+        objects: page
+            .markings
+            .iter()
+            .map(|marking| ManifestRecord {
+                id: stix_id(marking.id),
+                date_added: timestamp(date_added(marking)),
+                version: timestamp(last_written(marking)),
+                media_type: STIX_MEDIA_TYPE.into(),
+            })
+            .collect(),
+    };
+    page.with_date_headers(taxii_response(StatusCode::OK, &manifest))
+}
+
+fn markings_collection() -> Collection {
+    Collection {
+        id: MARKINGS_COLLECTION_ID.into(),
+        title: "Marking definitions".into(),
+        description: Some(
+            "Every marking, as a STIX marking definition. Definitions keep their id when \
+             edited, under a new version, and are removed when deleted."
+                .into(),
+        ),
+        alias: Some(MARKINGS_COLLECTION_ALIAS.into()),
+        can_read: true,
+        can_write: false,
+        media_types: vec![STIX_MEDIA_TYPE.into()],
+    }
+}
+
+/// A page of the markings matching the filters of a request, ordered by the
+/// date they were added.
+struct Page {
+    markings: Vec<Marking>,
+    more: bool,
+    next: Option<String>,
+}
+
+impl Page {
+    fn with_date_headers(&self, mut response: HttpResponse) -> HttpResponse {
+        let dates = self.markings.first().zip(self.markings.last());
+        if let Some((first, last)) = dates {
+            for (name, marking) in [
+                (DATE_ADDED_FIRST_HEADER, first),
+                (DATE_ADDED_LAST_HEADER, last),
+            ] {
+                let value = HeaderValue::from_str(&timestamp(date_added(marking)))
+                    .expect("Timestamps are valid header values");
+                let name = header::HeaderName::from_static(name);
+                response.headers_mut().insert(name, value);
+            }
+        }
+        response
+    }
+}
+
+fn envelope_response(page: Page) -> HttpResponse {
+    let envelope = Envelope {
+        more: page.more,
+        next: page.next.clone(),
+        objects: page
+            .markings
+            .iter()
+            .map(|marking| {
+                serde_json::to_value(StixMarkingDefinition::from(marking))
+                    .expect("Marking definitions are serializable")
+            })
+            .collect(),
+    };
+    page.with_date_headers(taxii_response(StatusCode::OK, &envelope))
+}
+
+/// Applies the TAXII filters and pagination of `request` to the markings.
+///
+/// Markings are few, so they are filtered here rather than by each storage
+/// backend. Each has a single version, written when it was last changed.
+async fn select(
+    request: &HttpRequest,
+    collection: &str,
+    object_id: Option<&str>,
+    repository: &dyn MarkingRepository,
+) -> Result<Page, Rejection> {
+    check_accept(request)?;
+    check_collection(collection)?;
+    let filters = web::Query::<TaxiiFilters>::from_query(request.query_string())
+        .map_err(|e| bad_request(e.to_string()))?
+        .into_inner();
+    let added_after = filters
+        .added_after
+        .as_deref()
+        .map(|added_after| {
+            DateTime::parse_from_rfc3339(added_after)
+                .map(|at| at.with_timezone(&Utc))
+                .map_err(|_| bad_request(format!("{} is not a timestamp.", added_after)))
+        })
+        .transpose()?;
+    let after = filters
+        .next
+        .as_deref()
+        .map(|next| parse_next(next).ok_or_else(|| bad_request("Invalid next.".into())))
+        .transpose()?;
+    let limit = filters
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let ids = Match::new(&filters.match_id);
+    let versions = Match::new(&filters.match_version);
+    // Markings have a single version, which is both the first and the last.
+    let any_version = ["last", "first", "all"]
+        .iter()
+        .any(|keyword| versions.allows(keyword));
+
+    if !Match::new(&filters.match_type).allows("marking-definition")
+        || !Match::new(&filters.match_spec_version).allows("2.1")
+    {
+        return Ok(Page {
+            markings: Vec::new(),
+            more: false,
+            next: None,
+        });
+    }
+
+    let mut markings: Vec<_> = repository
+        .list_added_markings()
+        .await
+        .map_err(|_| taxii_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal error", None))?
+        .into_iter()
+        .filter(|marking| {
+            let id = stix_id(marking.id);
+            let added = date_added(marking);
+            object_id.into_iter().all(|object_id| object_id == id)
+                && ids.allows(&id)
+                && added_after
+                    .into_iter()
+                    .all(|added_after| added > added_after)
+                && (any_version || versions.allows(&timestamp(last_written(marking))))
+                && after.into_iter().all(|after| (added, marking.id) > after)
+        })
+        .collect();
+    markings.sort_by_key(|marking| (date_added(marking), marking.id));
+
+    let more = markings.len() > limit;
+    markings.truncate(limit);
+    let next = if more {
+        markings
+            .last()
+            .map(|marking| format_next(date_added(marking), marking.id))
+    } else {
+        None
+    };
+    Ok(Page {
+        markings,
+        more,
+        next,
+    })
+}
+
+/// A `match[...]` filter, which allows everything when absent.
+struct Match<'a>(Option<HashSet<&'a str>>);
+
+impl<'a> Match<'a> {
+    fn new(values: &'a Option<String>) -> Self {
+        Self(
+            values
+                .as_deref()
+                .map(|values| values.split(',').map(str::trim).collect()),
+        )
+    }
+
+    fn allows(&self, value: &str) -> bool {
+        match &self.0 {
+            Some(values) => values.contains(value),
+            None => true,
+        }
+    }
+}
+
+/// `next` tokens point past the last marking of a page, so that markings
+/// added while a client pages through are not skipped.
+fn format_next(added: DateTime<Utc>, id: Uuid) -> String {
+    format!("{}_{}", added.timestamp_millis(), id)
+}
+
+fn parse_next(next: &str) -> Option<(DateTime<Utc>, Uuid)> {
+    let (millis, id) = next.split_once('_')?;
+    let added = Utc.timestamp_millis_opt(millis.parse().ok()?).single()?;
+    Some((added, Uuid::parse_str(id).ok()?))
+}
+
+/// TAXII clients must accept TAXII 2.1. A missing `Accept` header or a
+/// wildcard is taken as such.
+fn check_accept(request: &HttpRequest) -> Result<(), Rejection> {
+    let accept = match request.headers().get(header::ACCEPT) {
+        Some(accept) => accept.to_str().unwrap_or_default(),
+        None => return Ok(()),
+    };
+    let accepted = accept.split(',').any(|media_range| {
+        let mut parameters = media_range.split(';').map(str::trim);
+        let media_type = parameters.next().unwrap_or_default().to_lowercase();
+        let version = parameters.find_map(|parameter| parameter.strip_prefix("version="));
+        match media_type.as_str() {
+            "*/*" | "application/*" => true,
+            "application/taxii+json" => version.into_iter().all(|version| version == "2.1"),
+            _ => false,
+        }
+    });
+    if accepted {
+        Ok(())
+    } else {
+        Err(taxii_error(
+            StatusCode::NOT_ACCEPTABLE,
+            "Not acceptable",
+            Some(format!("Only {} is served.", TAXII_MEDIA_TYPE)),
+        ))
+    }
+}
+
+fn check_collection(collection: &str) -> Result<(), Rejection> {
+    if collection == MARKINGS_COLLECTION_ID || collection == MARKINGS_COLLECTION_ALIAS {
+        Ok(())
+    } else {
+        Err(taxii_error(
+            StatusCode::NOT_FOUND,
+            "Collection not found",
+            Some(format!("No collection has the id {}.", collection)),
+        ))
+    }
+}
+
+fn object_not_found(object_id: &str) -> Rejection {
+    taxii_error(
+        StatusCode::NOT_FOUND,
+        "Object not found",
+        Some(format!("No object matches {}.", object_id)),
+    )
+}
+
+fn bad_request(description: String) -> Rejection {
+    taxii_error(StatusCode::BAD_REQUEST, "Invalid filter", Some(description))
+}
+
+/// A request TAXII clients get an error for.
+struct Rejection {
+    status: StatusCode,
+    title: &'static str,
+    description: Option<String>,
+}
+
+impl From<Rejection> for HttpResponse {
+    fn from(rejection: Rejection) -> Self {
+        taxii_response(
+            rejection.status,
+            &TaxiiError {
+                title: rejection.title.into(),
+                description: rejection.description,
+                http_status: rejection.status.as_u16().to_string(),
+            },
+        )
+    }
+}
+
+fn taxii_error(status: StatusCode, title: &'static str, description: Option<String>) -> Rejection {
+    Rejection {
+        status,
+        title,
+        description,
+    }
+}
+
+fn taxii_response(status: StatusCode, body: &impl serde::Serialize) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type(TAXII_MEDIA_TYPE)
+        .body(serde_json::to_string(body).expect("TAXII resources are serializable"))
+}
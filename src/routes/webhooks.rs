@@ -0,0 +1,231 @@
+use actix_web::{web, HttpResponse};
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
+use crate::configuration::WebhookSettings;
+use crate::domain::{MarkingEventType, NewWebhook};
+use crate::repository::WebhookRepository;
+use crate::routes::markings::error_status;
+use crate::tls::Operator;
+use crate::webhooks::private_target;
+
+const MIN_SECRET_LENGTH: usize = 16;
+
+/// A webhook as registered by subscribers.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(example = json!({
+    "url": "https://tip.example.com/hooks/metaman",
+    "events": ["marking.updated", "marking.deleted"],
+    "secret": "4f1c2bd9e1a07c3e8d5b"
+}))]
+pub struct WebhookData {
+    /// An `http` or `https` URL, posted every event.
+    url: String,
+    /// The events to post, every one of them when left out.
+    #[serde(default)]
+    events: Vec<MarkingEventType>,
+    /// Key of the payload signatures, at least 16 characters long.
+    #[schema(value_type = String)]
+    secret: Secret<String>,
+}
+
+impl TryFrom<WebhookData> for NewWebhook {
+    type Error = String;
+
+    fn try_from(value: WebhookData) -> Result<Self, Self::Error> {
+        let url = reqwest::Url::parse(&value.url)
+            .map_err(|e| format!("{} is not a valid URL: {}", value.url, e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("{} is not an HTTP URL.", value.url));
+        }
+        if value.secret.expose_secret().chars().count() < MIN_SECRET_LENGTH {
+            return Err(format!(
+                "The secret must be at least {} characters long.",
+                MIN_SECRET_LENGTH
+            ));
+        }
+        let events = MarkingEventType::ALL
+            .into_iter()
+            .filter(|event_type| value.events.is_empty() || value.events.contains(event_type))
+            .collect();
+
+        Ok(Self {
+            url: value.url,
+            events,
+            secret: value.secret,
+        })
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/webhooks",
+    tag = "webhooks",
+    request_body = WebhookData,
+    responses(
+        (status = 201, description = "The webhook was registered.", body = Webhook),
+        (status = 400, description = "A field is missing or invalid, or the URL targets a private address.", body = ErrorBody),
+        (status = 403, description = "The client did not authenticate with a certificate.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "Registering a webhook",
+    skip(_operator, form, repository, settings),
+    fields(webhook_url = %form.url)
+)]
+pub async fn create_webhook(
+    _operator: Operator,
+    form: web::Json<WebhookData>,
+    repository: web::Data<dyn WebhookRepository>,
+    settings: web::Data<WebhookSettings>,
+) -> HttpResponse {
+    let new_webhook: NewWebhook = match form.into_inner().try_into() {
+        Ok(webhook) => webhook,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    if !settings.allow_private_targets && private_target(&new_webhook.url).is_some() {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    match repository.insert_webhook(&new_webhook).await {
+        Ok(webhook) => HttpResponse::Created().json(webhook),
+        Err(e) => HttpResponse::build(error_status(&e)).finish(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Every webhook, oldest first.", body = [Webhook]),
+        (status = 403, description = "The client did not authenticate with a certificate.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Listing webhooks", skip(_operator, repository))]
+pub async fn list_webhooks(
+    _operator: Operator,
+    repository: web::Data<dyn WebhookRepository>,
+) -> HttpResponse {
+    match repository.list_webhooks().await {
+        Ok(webhooks) => HttpResponse::Ok().json(webhooks),
+        Err(e) => HttpResponse::build(error_status(&e)).finish(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Id of the webhook, a UUID.")),
+    responses(
+        (status = 200, description = "The webhook.", body = Webhook),
+        (status = 403, description = "The client did not authenticate with a certificate.", body = ErrorBody),
+        (status = 404, description = "No webhook has this id.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Fetching a webhook", skip(_operator, repository))]
+pub async fn get_webhook(
+    _operator: Operator,
+    id: web::Path<Uuid>,
+    repository: web::Data<dyn WebhookRepository>,
+) -> HttpResponse {
+    match repository.get_webhook(id.into_inner()).await {
+        Ok(webhook) => HttpResponse::Ok().json(webhook),
+        Err(e) => HttpResponse::build(error_status(&e)).finish(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Id of the webhook, a UUID.")),
+    responses(
+        (status = 204, description = "The webhook, its deliveries and its dead letters were deleted."),
+        (status = 403, description = "The client did not authenticate with a certificate.", body = ErrorBody),
+        (status = 404, description = "No webhook has this id.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Deleting a webhook", skip(_operator, repository))]
+pub async fn delete_webhook(
+    _operator: Operator,
+    id: web::Path<Uuid>,
+    repository: web::Data<dyn WebhookRepository>,
+) -> HttpResponse {
+    match repository.delete_webhook(id.into_inner()).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => HttpResponse::build(error_status(&e)).finish(),
+    }
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeliveryParameters {
+    /// Largest number of deliveries, from 1 to 500. Defaults to 100.
+    limit: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    params(
+        ("id" = String, Path, description = "Id of the webhook, a UUID."),
+        DeliveryParameters,
+    ),
+    responses(
+        (status = 200, description = "Every attempt at posting an event, latest first.", body = [WebhookDelivery]),
+        (status = 403, description = "The client did not authenticate with a certificate.", body = ErrorBody),
+        (status = 404, description = "No webhook has this id.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(
+    name = "Listing webhook deliveries",
+    skip(_operator, parameters, repository)
+)]
+pub async fn list_webhook_deliveries(
+    _operator: Operator,
+    id: web::Path<Uuid>,
+    parameters: web::Query<DeliveryParameters>,
+    repository: web::Data<dyn WebhookRepository>,
+) -> HttpResponse {
+    let webhook = match repository.get_webhook(id.into_inner()).await {
+        Ok(webhook) => webhook,
+        Err(e) => return HttpResponse::build(error_status(&e)).finish(),
+    };
+    let limit = parameters.limit.unwrap_or(100).clamp(1, 500);
+
+    match repository.list_deliveries(webhook.id, limit).await {
+        Ok(deliveries) => HttpResponse::Ok().json(deliveries),
+        Err(e) => HttpResponse::build(error_status(&e)).finish(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks/{id}/dead_letters",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Id of the webhook, a UUID.")),
+    responses(
+        (status = 200, description = "The events no attempt could deliver, latest first.", body = [DeadLetter]),
+        (status = 403, description = "The client did not authenticate with a certificate.", body = ErrorBody),
+        (status = 404, description = "No webhook has this id.", body = ErrorBody),
+    )
+)]
+#[tracing::instrument(name = "Listing webhook dead letters", skip(_operator, repository))]
+pub async fn list_webhook_dead_letters(
+    _operator: Operator,
+    id: web::Path<Uuid>,
+    repository: web::Data<dyn WebhookRepository>,
+) -> HttpResponse {
+    let webhook = match repository.get_webhook(id.into_inner()).await {
+        Ok(webhook) => webhook,
+        Err(e) => return HttpResponse::build(error_status(&e)).finish(),
+    };
+
+    match repository.list_dead_letters(webhook.id).await {
+        Ok(dead_letters) => HttpResponse::Ok().json(dead_letters),
+        Err(e) => HttpResponse::build(error_status(&e)).finish(),
+    }
+}
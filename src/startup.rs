@@ -1,22 +1,356 @@
-use crate::routes::{create_marking, health_check};
-use actix_web::dev::Server;
+use crate::configuration::{
+    ApplicationSettings, CorsSettings, DatabaseKind, DatabaseSettings, SinkSettings,
+};
+use crate::metrics::RequestTimer;
+use crate::openapi::ApiDoc;
+use crate::outbox::{
+    configured_sinks, spawn_outbox, spawn_outbox_pruning, spawn_webhook_outbox, EventFeed,
+    EventSink,
+};
+use crate::rate_limit::RateLimiter;
+use crate::repository::{
+    InMemoryMarkingRepository, PostgresMarkingRepository, RepositoryError, SchemaRepository,
+    SqliteMarkingRepository, Storage,
+};
+use crate::request_id::{RequestId, RequestIdRootSpan};
+use crate::routes::{
+    assign_marking, bulk_create_markings, bulk_update_markings, create_marking, create_webhook,
+    delete_marking, delete_webhook, deprecate_marking, get_marking, get_webhook, health_check,
+    health_live, health_ready, list_marking_assignments, list_markings, list_webhook_dead_letters,
+    list_webhook_deliveries, list_webhooks, metrics, migration_status, patch_marking,
+    revoke_marking, search_markings, stream_events, taxii_api_root, taxii_collection,
+    taxii_collections, taxii_discovery, taxii_manifest, taxii_object, taxii_objects,
+    taxii_versions, update_marking, StreamShutdown, TaxiiConfig,
+};
+use crate::tls::{server_config, spawn_reloader, ClientIdentities, OperatorRoutes};
+use actix_cors::Cors;
+use actix_web::dev::{Server, ServerHandle, Service};
+use actix_web::error::{InternalError, JsonPayloadError};
+use actix_web::http::header::HeaderName;
+use actix_web::http::Method;
+use actix_web::middleware::Condition;
 use actix_web::web::Data;
-use actix_web::{web, App, HttpServer};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
 use tracing_actix_web::TracingLogger;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
 
-pub fn run(listener: TcpListener, db_pool: PgPool) -> Result<Server, std::io::Error> {
-    let db_pool = Data::new(db_pool);
+pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
+    PgPoolOptions::new()
+        .connect_timeout(std::time::Duration::from_secs(2))
+        .connect_lazy_with(configuration.with_db())
+}
+
+/// Builds the storage selected by `database.kind`.
+///
+/// A SQLite database is a local file owned by this process, so its schema is
+/// brought up to date here rather than by `scripts/init_db.sh`.
+pub async fn get_storage(configuration: &DatabaseSettings) -> Result<Storage, RepositoryError> {
+    let storage = match configuration.kind {
+        DatabaseKind::Postgres => Storage::new(PostgresMarkingRepository::new(
+            get_connection_pool(configuration),
+        )),
+        DatabaseKind::Memory => Storage::new(InMemoryMarkingRepository::new()),
+        DatabaseKind::Sqlite => {
+            let repository = SqliteMarkingRepository::connect(configuration.sqlite()).await?;
+            repository.migrate().await?;
+            Storage::new(repository)
+        }
+    };
+    Ok(storage)
+}
+
+/// Serves the API on `listener`, over HTTPS when `settings.tls` is set, and
+/// publishes the changes made to markings to the sinks of `settings.outbox`.
+///
+/// The server stops on SIGINT and SIGTERM, after the requests in flight have
+/// completed or `settings.shutdown_timeout` has elapsed. `GET /events`
+/// streams end at once.
+pub fn run(
+    listener: TcpListener,
+    storage: Storage,
+    settings: &ApplicationSettings,
+) -> Result<Server, std::io::Error> {
+    run_with_sinks(listener, storage, settings, Vec::new())
+}
+
+/// Like `run`, also publishing marking events to `sinks`, such as message
+/// brokers this crate has no client for.
+pub fn run_with_sinks(
+    listener: TcpListener,
+    storage: Storage,
+    settings: &ApplicationSettings,
+    mut sinks: Vec<Arc<dyn EventSink>>,
+) -> Result<Server, std::io::Error> {
+    validate_cors(&settings.cors)?;
+    if settings.outbox.enabled {
+        sinks.extend(configured_sinks(&settings.outbox)?);
+        spawn_outbox(storage.outbox.clone(), sinks, &settings.outbox);
+        spawn_outbox_pruning(storage.outbox.clone(), &settings.outbox);
+        if settings.outbox.sinks.contains(&SinkSettings::Webhooks) {
+            spawn_webhook_outbox(&storage, &settings.outbox, &settings.webhooks)?;
+        }
+    }
+    let feed = Data::new(EventFeed::new(storage.outbox.clone(), &settings.outbox));
+    let (end_streams, stream_shutdown) = watch::channel(());
+    let stream_shutdown = Data::new(StreamShutdown(stream_shutdown));
+    let markings = Data::from(storage.markings);
+    let idempotency = Data::from(storage.idempotency);
+    let webhooks = Data::from(storage.webhooks);
+    let outbox = Data::from(storage.outbox);
+    let schema = Data::from(storage.schema);
+    let webhook_settings = Data::new(settings.webhooks.clone());
+    let cors_settings = settings.cors.clone();
+    let rate_limiter = Arc::new(RateLimiter::new(settings.rate_limit.clone()));
+    let openapi = ApiDoc::openapi();
+    let operator_routes = OperatorRoutes {
+        open: settings.open_operator_routes,
+    };
+    let taxii_config = Data::new(TaxiiConfig {
+        max_content_length: settings.payload_limit,
+    });
+    let json_config = web::JsonConfig::default()
+        .limit(settings.payload_limit)
+        .error_handler(json_error);
     let server = HttpServer::new(move || {
+        let rate_limiter = rate_limiter.clone();
         App::new()
-            .wrap(TracingLogger::default())
+            .wrap_fn(|request, service| {
+                let timer = RequestTimer::start(&request);
+                let response = service.call(request);
+                async move {
+                    let response = response.await;
+                    match &response {
+                        Ok(response) => timer.observe(response.status()),
+                        Err(e) => timer.observe(e.as_response_error().status_code()),
+                    }
+                    response
+                }
+            })
+            .wrap(TracingLogger::<RequestIdRootSpan>::new())
+            // Rejected requests are counted but not logged, so that a runaway
+            // client does not flood the logs.
+            .wrap_fn(move |request, service| {
+                let response = match rate_limiter.reject(&request) {
+                    None => Ok(service.call(request)),
+                    Some(rejection) => Err(request.into_response(rejection)),
+                };
+                async move {
+                    match response {
+                        Ok(response) => Ok(response.await?.map_into_boxed_body()),
+                        Err(rejection) => Ok(rejection),
+                    }
+                }
+            })
+            .wrap_fn(|request, service| {
+                let request_id = RequestId::assign(&request);
+                let response = service.call(request);
+                async move {
+                    let response = response.await?.map_into_boxed_body();
+                    Ok(request_id.decorate(response).await)
+                }
+            })
+            .wrap(Condition::new(
+                !cors_settings.allowed_origins.is_empty(),
+                cors(&cors_settings),
+            ))
             .route("/health_check", web::get().to(health_check))
+            .route("/health/live", web::get().to(health_live))
+            .route("/health/ready", web::get().to(health_ready))
+            .route("/metrics", web::get().to(metrics))
+            .route("/admin/migrations", web::get().to(migration_status))
             .route("/markings", web::post().to(create_marking))
-            .app_data(db_pool.clone())
+            .route("/markings", web::get().to(list_markings))
+            .route("/markings/search", web::get().to(search_markings))
+            .route("/markings/bulk", web::post().to(bulk_create_markings))
+            .route("/markings/bulk", web::put().to(bulk_update_markings))
+            .route("/markings/{id}", web::get().to(get_marking))
+            .route("/markings/{id}", web::put().to(update_marking))
+            .route("/markings/{id}", web::patch().to(patch_marking))
+            .route("/markings/{id}", web::delete().to(delete_marking))
+            .route(
+                "/markings/{id}/deprecate",
+                web::post().to(deprecate_marking),
+            )
+            .route("/markings/{id}/revoke", web::post().to(revoke_marking))
+            .route(
+                "/markings/{id}/assignments",
+                web::get().to(list_marking_assignments),
+            )
+            .route(
+                "/markings/{id}/assignments/{object_ref}",
+                web::put().to(assign_marking),
+            )
+            .route("/events", web::get().to(stream_events))
+            .route("/webhooks", web::post().to(create_webhook))
+            .route("/webhooks", web::get().to(list_webhooks))
+            .route("/webhooks/{id}", web::get().to(get_webhook))
+            .route("/webhooks/{id}", web::delete().to(delete_webhook))
+            .route(
+                "/webhooks/{id}/deliveries",
+                web::get().to(list_webhook_deliveries),
+            )
+            .route(
+                "/webhooks/{id}/dead_letters",
+                web::get().to(list_webhook_dead_letters),
+            )
+            .route("/taxii2/", web::get().to(taxii_discovery))
+            .route("/taxii2/api/", web::get().to(taxii_api_root))
+            .route("/taxii2/api/collections/", web::get().to(taxii_collections))
+            .route(
+                "/taxii2/api/collections/{collection}/",
+                web::get().to(taxii_collection),
+            )
+            .route(
+                "/taxii2/api/collections/{collection}/objects/",
+                web::get().to(taxii_objects),
+            )
+            .route(
+                "/taxii2/api/collections/{collection}/objects/{object_id}/",
+                web::get().to(taxii_object),
+            )
+            .route(
+                "/taxii2/api/collections/{collection}/objects/{object_id}/versions/",
+                web::get().to(taxii_versions),
+            )
+            .route(
+                "/taxii2/api/collections/{collection}/manifest/",
+                web::get().to(taxii_manifest),
+            )
+            .service(web::redirect("/docs", "/docs/"))
+            .service(SwaggerUi::new("/docs/{_:.*}").url("/openapi.json", openapi.clone()))
+            .app_data(markings.clone())
+            .app_data(idempotency.clone())
+            .app_data(webhooks.clone())
+            .app_data(outbox.clone())
+            .app_data(feed.clone())
+            .app_data(stream_shutdown.clone())
+            .app_data(schema.clone())
+            .app_data(webhook_settings.clone())
+            .app_data(json_config.clone())
+            .app_data(taxii_config.clone())
+            .app_data(operator_routes)
     })
-    .listen(listener)?
+    .keep_alive(Duration::from_secs(settings.keep_alive))
+    .shutdown_timeout(settings.shutdown_timeout)
+    // See `stop_on_signal`.
+    .disable_signals();
+    let server = match settings.workers {
+        Some(workers) => server.workers(workers),
+        None => server,
+    };
+    let server = match &settings.tls {
+        Some(tls) => {
+            let (config, resolver) = server_config(tls)?;
+            spawn_reloader(&resolver, Duration::from_secs(tls.reload_interval));
+            let identities = tls.client_auth.as_ref().map(ClientIdentities::new);
+            server
+                .on_connect(move |connection, extensions| {
+                    let identity = identities
+                        .as_ref()
+                        .and_then(|identities| identities.identify_connection(connection));
+                    if let Some(identity) = identity {
+                        extensions.insert(identity);
+                    }
+                })
+                .listen_rustls_0_23(listener, config)?
+        }
+        None => server.listen(listener)?,
+    }
     .run();
+    stop_on_signal(server.handle(), end_streams);
 
     Ok(server)
 }
+
+/// Stops `server` gracefully on SIGINT or SIGTERM, in place of its own
+/// signal handling, once `GET /events` streams are told to end by dropping
+/// `end_streams`.
+fn stop_on_signal(server: ServerHandle, end_streams: watch::Sender<()>) {
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut terminate =
+                signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = tokio::signal::ctrl_c().await;
+        tracing::info!("Shutting down.");
+        drop(end_streams);
+        server.stop(true).await;
+    });
+}
+
+/// Rejects bodies over the payload limit with a 413 whose JSON body says
+/// what the limit is; other payload errors keep their default response.
+fn json_error(error: JsonPayloadError, _request: &HttpRequest) -> actix_web::Error {
+    match error {
+        JsonPayloadError::OverflowKnownLength { limit, .. }
+        | JsonPayloadError::Overflow { limit } => {
+            let response = HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                "error": format!("The request body exceeds the limit of {} bytes.", limit),
+            }));
+            InternalError::from_response(error, response).into()
+        }
+        error => error.into(),
+    }
+}
+
+fn cors(settings: &CorsSettings) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(settings.allowed_methods.iter().map(String::as_str))
+        .allowed_headers(settings.allowed_headers.iter().map(String::as_str))
+        .expose_headers(settings.exposed_headers.iter().map(String::as_str))
+        .max_age(settings.max_age);
+    for origin in &settings.allowed_origins {
+        cors = match origin.as_str() {
+            "*" => cors.allow_any_origin(),
+            origin => cors.allowed_origin(origin),
+        };
+    }
+    if settings.allow_credentials {
+        cors = cors.supports_credentials();
+    }
+    cors
+}
+
+/// Rejects the settings `cors` would only report once workers start.
+fn validate_cors(settings: &CorsSettings) -> Result<(), std::io::Error> {
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+    if settings.allow_credentials && settings.allowed_origins.iter().any(|o| o == "*") {
+        return Err(invalid(
+            "CORS credentials cannot be allowed for any origin.".into(),
+        ));
+    }
+    for origin in &settings.allowed_origins {
+        if origin != "*" && origin.parse::<actix_web::http::Uri>().is_err() {
+            return Err(invalid(format!("{} is not a valid CORS origin.", origin)));
+        }
+    }
+    for method in &settings.allowed_methods {
+        if Method::from_bytes(method.as_bytes()).is_err() {
+            return Err(invalid(format!("{} is not a valid HTTP method.", method)));
+        }
+    }
+    for header in settings
+        .allowed_headers
+        .iter()
+        .chain(&settings.exposed_headers)
+    {
+        if HeaderName::from_bytes(header.as_bytes()).is_err() {
+            return Err(invalid(format!("{} is not a valid header name.", header)));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,233 @@
+//! TAXII 2.1 resources and the STIX 2.1 form of markings.
+//!
+//! STIX holds marking definitions immutable: they have no `modified`
+//! property and cannot be revoked. Markings are edited in place though, and
+//! metaman departs from the standard rather than mint a new id for each
+//! edit: a marking keeps its STIX id, and the time it was last written is
+//! its TAXII version, so that `added_after` polls pick edits up. Consumers
+//! must replace the definitions they hold rather than skip ids they have
+//! seen.
+//!
+//! Nor can STIX say that a marking definition was withdrawn, so deleted
+//! markings leave no tombstone: they drop out of the collection, and their
+//! id is answered with a 404. Consumers that must know of deletions follow
+//! the `deleted` events of `/events` or of a webhook, or compare the
+//! manifest with what they hold.
+use chrono::{DateTime, SecondsFormat, SubsecRound, Utc};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::domain::Marking;
+
+pub const TAXII_MEDIA_TYPE: &str = "application/taxii+json;version=2.1";
+pub const STIX_MEDIA_TYPE: &str = "application/stix+json;version=2.1";
+pub const DATE_ADDED_FIRST_HEADER: &str = "x-taxii-date-added-first";
+pub const DATE_ADDED_LAST_HEADER: &str = "x-taxii-date-added-last";
+
+const MARKING_DEFINITION_PREFIX: &str = "marking-definition--";
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct Discovery {
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub api_roots: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ApiRoot {
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub versions: Vec<String>,
+    pub max_content_length: usize,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct Collections {
+    #[serde(default)]
+    pub collections: Vec<Collection>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, utoipa::ToSchema)]
+pub struct Collection {
+    pub id: String,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    pub can_read: bool,
+    pub can_write: bool,
+    #[serde(default)]
+    pub media_types: Vec<String>,
+}
+
+/// A page of STIX objects, of any type.
+#[derive(serde::Serialize, serde::Deserialize, Default, utoipa::ToSchema)]
+pub struct Envelope {
+    #[serde(default)]
+    pub more: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(value_type = Vec<Object>)]
+    pub objects: Vec<serde_json::Value>,
+}
+
+/// A page of the versions of an object.
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct Versions {
+    #[serde(default)]
+    pub more: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<String>,
+}
+
+/// A page of the objects of a collection, described without their content.
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct Manifest {
+    #[serde(default)]
+    pub more: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub objects: Vec<ManifestRecord>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ManifestRecord {
+    pub id: String,
+    pub date_added: String,
+    pub version: String,
+    pub media_type: String,
+}
+
+/// The body of TAXII error responses.
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct TaxiiError {
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub http_status: String,
+}
+
+/// A STIX 2.1 `marking-definition`, the form markings take over TAXII.
+///
+/// Its `definition` is an object keyed by the definition type, such as
+/// `{"statement": "Copyright Acme"}`.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq, utoipa::ToSchema)]
+pub struct StixMarkingDefinition {
+    #[serde(rename = "type")]
+    pub object_type: String,
+    pub spec_version: String,
+    pub id: String,
+    pub created: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub definition_type: String,
+    pub definition: BTreeMap<String, String>,
+}
+
+impl From<&Marking> for StixMarkingDefinition {
+    fn from(marking: &Marking) -> Self {
+        Self {
+            object_type: "marking-definition".into(),
+            spec_version: "2.1".into(),
+            id: stix_id(marking.id),
+            created: timestamp(marking.created_at),
+            name: Some(marking.name.clone()),
+            definition_type: marking.definition_type.clone(),
+            definition: [(marking.definition_type.clone(), marking.definition.clone())].into(),
+        }
+    }
+}
+
+pub fn stix_id(id: Uuid) -> String {
+    format!("{}{}", MARKING_DEFINITION_PREFIX, id)
+}
+
+/// The id of the marking behind a `marking-definition--<uuid>` STIX id.
+pub fn marking_id(stix_id: &str) -> Option<Uuid> {
+    stix_id
+        .strip_prefix(MARKING_DEFINITION_PREFIX)
+        .and_then(|id| Uuid::parse_str(id).ok())
+}
+
+/// Formats a STIX timestamp, which has a millisecond precision.
+pub fn timestamp(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// When a marking was last written, to the millisecond: its TAXII version.
+pub fn last_written(marking: &Marking) -> DateTime<Utc> {
+    marking
+        .updated_at
+        .unwrap_or(marking.created_at)
+        .trunc_subsecs(3)
+}
+
+/// When the current version of a marking was added to the collection: once
+/// its write was committed, unlike `last_written`, which is set as the write
+/// starts.
+pub fn date_added(marking: &Marking) -> DateTime<Utc> {
+    marking.date_added.unwrap_or_else(|| last_written(marking))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::Marking;
+    use crate::taxii::{marking_id, StixMarkingDefinition};
+    use chrono::{DateTime, Utc};
+    use uuid::Uuid;
+
+    #[test]
+    fn markings_become_stix_marking_definitions() {
+        let id = Uuid::new_v4();
+        let marking = Marking {
+            id,
+            name: "copyright_acme".into(),
+            definition_type: "statement".into(),
+            definition: "Copyright Acme".into(),
+            created_at: "2022-03-01T10:00:00.123456Z"
+                .parse::<DateTime<Utc>>()
+                .unwrap(),
+            updated_at: None,
+            created_by: Uuid::new_v4(),
+            updated_by: None,
+            version: 1,
+            status: "active".into(),
+            source_server: None,
+            source_collection: None,
+            fetched_at: None,
+            date_added: None,
+        };
+
+        let stix = serde_json::to_value(StixMarkingDefinition::from(&marking)).unwrap();
+
+        assert_eq!(
+            serde_json::json!({
+                "type": "marking-definition",
+                "spec_version": "2.1",
+                "id": format!("marking-definition--{}", id),
+                "created": "2022-03-01T10:00:00.123Z",
+                "name": "copyright_acme",
+                "definition_type": "statement",
+                "definition": { "statement": "Copyright Acme" }
+            }),
+            stix
+        );
+        assert_eq!(Some(id), marking_id(stix["id"].as_str().unwrap()));
+    }
+
+    #[test]
+    fn only_marking_definition_ids_map_to_markings() {
+        assert_eq!(None, marking_id(&format!("indicator--{}", Uuid::new_v4())));
+        assert_eq!(None, marking_id("marking-definition--not-a-uuid"));
+    }
+}
@@ -0,0 +1,256 @@
+//! Mirrors the marking definitions of partner TAXII 2.1 collections.
+use chrono::Utc;
+use reqwest::header::ACCEPT;
+use secrecy::ExposeSecret;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::configuration::TaxiiSourceSettings;
+use crate::domain::{
+    MarkingDefinition, MarkingDefinitionType, MarkingName, MarkingSource, NewMarking,
+};
+use crate::repository::{MarkingRepository, RepositoryError};
+use crate::taxii::{
+    marking_id, Envelope, StixMarkingDefinition, DATE_ADDED_LAST_HEADER, TAXII_MEDIA_TYPE,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum SyncError {
+    #[error("Failed to pull the TAXII collection: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Repository(#[from] RepositoryError),
+}
+
+/// What a pull of a collection did.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Marking definitions written, new or not.
+    pub mirrored: usize,
+    /// Marking definitions that cannot be stored as markings.
+    pub skipped: usize,
+    /// The date the last marking definition pulled was added, where the next
+    /// pull resumes.
+    pub added_last: Option<String>,
+}
+
+pub struct TaxiiSource {
+    settings: TaxiiSourceSettings,
+    client: reqwest::Client,
+}
+
+impl TaxiiSource {
+    pub fn new(settings: TaxiiSourceSettings) -> Result<Self, SyncError> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()?;
+        Ok(Self { settings, client })
+    }
+
+    fn objects_url(&self) -> String {
+        format!(
+            "{}/collections/{}/objects/",
+            self.settings.api_root.trim_end_matches('/'),
+            self.settings.collection
+        )
+    }
+
+    /// Pulls the marking definitions added since the previous pull, or all
+    /// of them on the first, and mirrors them into `repository`, which keeps
+    /// where the next pull resumes.
+    ///
+    /// Marking definitions whose name, type or definition is not valid for a
+    /// marking, whose name is already taken, or whose id is that of a marking
+    /// created here or pulled from another source, are skipped.
+    #[tracing::instrument(
+        name = "Pulling a TAXII collection",
+        skip(self, repository),
+        fields(
+            api_root = %self.settings.api_root,
+            collection = %self.settings.collection
+        )
+    )]
+    pub async fn sync(&self, repository: &dyn MarkingRepository) -> Result<SyncReport, SyncError> {
+        let source = MarkingSource {
+            server: self.settings.api_root.clone(),
+            collection: self.settings.collection.clone(),
+            fetched_at: Utc::now(),
+        };
+        let added_after = repository
+            .get_sync_cursor(&source.server, &source.collection)
+            .await?;
+        let mut report = SyncReport {
+            added_last: added_after.clone(),
+            ..SyncReport::default()
+        };
+        let mut next = None;
+        loop {
+            let mut query = vec![("match[type]", "marking-definition".to_owned())];
+            if let Some(added_after) = &added_after {
+                query.push(("added_after", added_after.clone()));
+            }
+            if let Some(next) = next {
+                query.push(("next", next));
+            }
+            let mut request = self
+                .client
+                .get(self.objects_url())
+                .header(ACCEPT, TAXII_MEDIA_TYPE)
+                .query(&query);
+            if let Some(username) = &self.settings.username {
+                let password = self.settings.password.as_ref();
+                request = request.basic_auth(username, password.map(|p| p.expose_secret()));
+            }
+            let response = request.send().await?.error_for_status()?;
+            let added_last = response
+                .headers()
+                .get(DATE_ADDED_LAST_HEADER)
+                .and_then(|value| value.to_str().ok());
+            if let Some(added_last) = added_last {
+                report.added_last = Some(added_last.to_owned());
+            }
+            let envelope: Envelope = response.json().await?;
+
+            for object in envelope.objects {
+                let (id, marking) = match to_marking(object) {
+                    Ok(marking) => marking,
+                    Err(reason) => {
+                        tracing::warn!(reason = %reason, "Skipped a TAXII object.");
+                        report.skipped += 1;
+                        continue;
+                    }
+                };
+                match repository.mirror_marking(id, &marking, &source).await {
+                    Ok(_) => report.mirrored += 1,
+                    Err(
+                        e
+                        @ (RepositoryError::DuplicateName(_) | RepositoryError::ForeignMarking(_)),
+                    ) => {
+                        tracing::warn!(marking.id = %id, reason = %e, "Skipped a TAXII object.");
+                        report.skipped += 1;
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            match envelope.next {
+                Some(token) if envelope.more => next = Some(token),
+                _ => break,
+            }
+        }
+        if let Some(added_last) = report.added_last.as_deref() {
+            repository
+                .save_sync_cursor(&source.server, &source.collection, added_last)
+                .await?;
+        }
+        Ok(report)
+    }
+}
+
+/// Pulls every source on its interval, for as long as the runtime runs. Each
+/// pull resumes after the last marking definition of the previous one, even
+/// across restarts.
+pub fn spawn_sync(repository: Arc<dyn MarkingRepository>, sources: Vec<TaxiiSource>) {
+    for source in sources {
+        let repository = repository.clone();
+        tokio::spawn(async move {
+            let interval = Duration::from_secs(source.settings.interval.max(1));
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                match source.sync(&*repository).await {
+                    Ok(report) => {
+                        tracing::info!(
+                            mirrored = report.mirrored,
+                            skipped = report.skipped,
+                            "Pulled marking definitions."
+                        );
+                    }
+                    Err(e) => tracing::warn!(
+                        error.message = %e,
+                        "Failed to pull marking definitions, retrying at the next poll."
+                    ),
+                }
+            }
+        });
+    }
+}
+
+/// The marking behind a STIX `marking-definition`, keeping its id so that
+/// later pulls update it.
+fn to_marking(object: serde_json::Value) -> Result<(Uuid, NewMarking), String> {
+    let stix: StixMarkingDefinition =
+        serde_json::from_value(object).map_err(|e| format!("Not a marking definition: {}", e))?;
+    let id = marking_id(&stix.id)
+        .ok_or_else(|| format!("{} is not the id of a marking definition.", stix.id))?;
+    let definition = stix
+        .definition
+        .get(&stix.definition_type)
+        .ok_or_else(|| format!("{} has no {} definition.", stix.id, stix.definition_type))?;
+    let name = match &stix.name {
+        Some(name) => MarkingName::from_label(name)?,
+        None => MarkingName::from_label(&format!("{} {}", stix.definition_type, definition))?,
+    };
+    let marking = NewMarking {
+        name,
+        definition_type: MarkingDefinitionType::parse(stix.definition_type.clone())?,
+        definition: MarkingDefinition::parse(definition.clone())?,
+    };
+    Ok((id, marking))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::taxii_sync::to_marking;
+    use claim::{assert_err, assert_ok};
+    use uuid::Uuid;
+
+    fn marking_definition(name: Option<&str>, definition: serde_json::Value) -> serde_json::Value {
+        serde_json::json!({
+            "type": "marking-definition",
+            "spec_version": "2.1",
+            "id": format!("marking-definition--{}", Uuid::new_v4()),
+            "created": "2022-03-01T10:00:00.000Z",
+            "name": name,
+            "definition_type": "statement",
+            "definition": definition
+        })
+    }
+
+    #[test]
+    fn statements_become_markings_with_the_same_id() {
+        let object = marking_definition(
+            Some("Copyright Acme"),
+            serde_json::json!({ "statement": "Copyright Acme" }),
+        );
+        let id = object["id"].as_str().unwrap().to_owned();
+
+        let (marking_id, marking) = assert_ok!(to_marking(object));
+
+        assert_eq!(format!("marking-definition--{}", marking_id), id);
+        assert_eq!("copyright_acme", marking.name.as_ref());
+        assert_eq!("statement", marking.definition_type.as_ref());
+        assert_eq!("Copyright Acme", marking.definition.as_ref());
+    }
+
+    #[test]
+    fn unnamed_marking_definitions_are_named_after_their_definition() {
+        let object = marking_definition(None, serde_json::json!({ "statement": "Acme only" }));
+
+        let (_, marking) = assert_ok!(to_marking(object));
+
+        assert_eq!("statement_acme_only", marking.name.as_ref());
+    }
+
+    #[test]
+    fn other_objects_are_skipped() {
+        let cases = [
+            serde_json::json!({ "type": "indicator", "id": "indicator--1" }),
+            marking_definition(Some("acme"), serde_json::json!({ "tlp": "red" })),
+            marking_definition(Some("acme"), serde_json::json!({ "statement": "(c) Acme" })),
+        ];
+        for object in cases {
+            assert_err!(to_marking(object));
+        }
+    }
+}
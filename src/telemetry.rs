@@ -1,13 +1,24 @@
+use opentelemetry::trace::TraceError;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::{runtime, trace, Resource};
 use tracing::{subscriber::set_global_default, Subscriber};
+use tracing_appender::non_blocking::{NonBlockingBuilder, WorkerGuard};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
-use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{fmt, fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Layer, Registry};
+
+use crate::configuration::{LogFormat, LogOutput, LogRotation, OtlpSettings, TelemetrySettings};
 
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
-) -> impl Subscriber + Send + Sync
+) -> impl Subscriber + for<'a> LookupSpan<'a> + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
@@ -20,7 +31,167 @@ where
         .with(formatting_layer)
 }
 
+/// Builds the subscriber described by the `telemetry` settings.
+///
+/// Logs are written from a background thread, which stops when the returned
+/// guard is dropped: keep it alive until the process exits, or the last logs
+/// may be lost.
+pub fn get_configured_subscriber(
+    name: String,
+    settings: &TelemetrySettings,
+) -> (
+    impl Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+    WorkerGuard,
+) {
+    // Logging blocks when the writer falls behind rather than dropping lines.
+    let non_blocking = NonBlockingBuilder::default().lossy(false);
+    let (writer, guard) = match settings.output {
+        LogOutput::Stdout => non_blocking.finish(std::io::stdout()),
+        LogOutput::File => {
+            let rotation = match settings.file.rotation {
+                LogRotation::Minutely => Rotation::MINUTELY,
+                LogRotation::Hourly => Rotation::HOURLY,
+                LogRotation::Daily => Rotation::DAILY,
+                LogRotation::Never => Rotation::NEVER,
+            };
+            non_blocking.finish(RollingFileAppender::new(
+                rotation,
+                &settings.file.directory,
+                &settings.file.prefix,
+            ))
+        }
+    };
+    let env_filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&settings.level));
+    // Files are read by tools rather than terminals, which have no use for
+    // colour codes.
+    let ansi = settings.output == LogOutput::Stdout;
+
+    let (bunyan, pretty, compact) = match settings.format {
+        LogFormat::Bunyan => (
+            Some(JsonStorageLayer.and_then(BunyanFormattingLayer::new(name, writer))),
+            None,
+            None,
+        ),
+        LogFormat::Pretty => (
+            None,
+            Some(fmt::layer().pretty().with_ansi(ansi).with_writer(writer)),
+            None,
+        ),
+        LogFormat::Compact => (
+            None,
+            None,
+            Some(fmt::layer().compact().with_ansi(ansi).with_writer(writer)),
+        ),
+    };
+    let subscriber = Registry::default()
+        .with(env_filter)
+        .with(bunyan)
+        .with(pretty)
+        .with(compact);
+    (subscriber, guard)
+}
+
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to set subscriber");
 }
+
+/// Builds a layer exporting spans to an OpenTelemetry collector over
+/// OTLP/HTTP, and makes incoming W3C `traceparent` headers the parents of
+/// request spans so that they join the callers' traces.
+///
+/// Spans are exported in batches, from a thread of their own running a
+/// current-thread Tokio runtime, so this can be called from any thread. Call
+/// `opentelemetry::global::shutdown_tracer_provider` before exiting to flush
+/// the last batch.
+pub fn get_otlp_layer<S>(
+    name: String,
+    settings: &OtlpSettings,
+) -> Result<OpenTelemetryLayer<S, trace::Tracer>, TraceError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(&settings.endpoint),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new([KeyValue::new("service.name", name)])),
+        )
+        .install_batch(runtime::TokioCurrentThread)?;
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{
+        LogFileSettings, LogFormat, LogOutput, LogRotation, TelemetrySettings,
+    };
+    use crate::telemetry::get_configured_subscriber;
+    use uuid::Uuid;
+
+    /// Logs one event to a file in a fresh directory, in `format`, and
+    /// returns what was written.
+    fn log_to_file(format: LogFormat) -> String {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let settings = TelemetrySettings {
+            level: "info".into(),
+            format,
+            output: LogOutput::File,
+            file: LogFileSettings {
+                directory: directory.to_string_lossy().into_owned(),
+                prefix: "test.log".into(),
+                rotation: LogRotation::Never,
+            },
+            otlp: None,
+        };
+        let (subscriber, guard) = get_configured_subscriber("test".into(), &settings);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(marking_name = "tlp_red", "Marking saved");
+        });
+        // Waits for the background thread to write the event.
+        drop(guard);
+
+        let logs = std::fs::read_to_string(directory.join("test.log")).unwrap();
+        std::fs::remove_dir_all(directory).unwrap();
+        logs
+    }
+
+    #[test]
+    fn bunyan_logs_are_written_as_json_lines() {
+        let logs = log_to_file(LogFormat::Bunyan);
+
+        let line: serde_json::Value = serde_json::from_str(logs.lines().next().unwrap()).unwrap();
+        assert_eq!("Marking saved", line["msg"]);
+        assert_eq!("tlp_red", line["marking_name"]);
+    }
+
+    #[test]
+    fn compact_logs_are_written_as_plain_lines() {
+        let logs = log_to_file(LogFormat::Compact);
+
+        assert_eq!(1, logs.lines().count());
+        assert!(serde_json::from_str::<serde_json::Value>(&logs).is_err());
+        assert!(logs.contains("Marking saved marking_name=\"tlp_red\""));
+    }
+
+    #[test]
+    fn pretty_logs_span_several_lines() {
+        let logs = log_to_file(LogFormat::Pretty);
+
+        assert!(logs.lines().count() > 1);
+        assert!(logs.contains("Marking saved"));
+    }
+
+    #[test]
+    fn log_files_have_no_colour_codes() {
+        for format in [LogFormat::Compact, LogFormat::Pretty] {
+            assert!(!log_to_file(format).contains('\u{1b}'));
+        }
+    }
+}
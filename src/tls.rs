@@ -0,0 +1,338 @@
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::dev::Payload;
+use actix_web::rt::net::TcpStream;
+use actix_web::{FromRequest, HttpRequest};
+use futures::future::{ready, Ready};
+use rustls::crypto::ring::{default_provider, sign::any_supported_type};
+use rustls::pki_types::CertificateDer;
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use sha2::{Digest, Sha256};
+use std::any::Any;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+
+use crate::configuration::{ClientAuthSettings, TlsSettings};
+
+/// Builds the rustls configuration described by `application.tls`.
+///
+/// The returned resolver serves the configured certificate; hand it to
+/// `spawn_reloader` to pick up renewals.
+pub fn server_config(
+    settings: &TlsSettings,
+) -> Result<(ServerConfig, Arc<CertificateResolver>), io::Error> {
+    let resolver = Arc::new(CertificateResolver::load(
+        settings.certificate.clone(),
+        settings.private_key.clone(),
+    )?);
+    let provider = Arc::new(default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_data)?;
+    let builder = match &settings.client_auth {
+        Some(client_auth) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in read_certificates(&client_auth.ca_certificate)? {
+                roots.add(certificate).map_err(invalid_data)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(invalid_data)?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    Ok((builder.with_cert_resolver(resolver.clone()), resolver))
+}
+
+/// Checks the certificate files of `resolver` every `interval`, for as long
+/// as a server holds on to it.
+pub fn spawn_reloader(resolver: &Arc<CertificateResolver>, interval: Duration) {
+    let resolver: Weak<CertificateResolver> = Arc::downgrade(resolver);
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            let resolver = match resolver.upgrade() {
+                Some(resolver) => resolver,
+                None => return,
+            };
+            match resolver.reload() {
+                Ok(true) => tracing::info!("Reloaded the TLS certificate."),
+                Ok(false) => {}
+                Err(e) => tracing::warn!(
+                    error.message = %e,
+                    "Failed to reload the TLS certificate, the previous one is still served."
+                ),
+            }
+        }
+    });
+}
+
+/// Serves the certificate read from disk, which `reload` swaps when its
+/// files change.
+#[derive(Debug)]
+pub struct CertificateResolver {
+    certificate_path: String,
+    private_key_path: String,
+    current: RwLock<LoadedCertificate>,
+}
+
+#[derive(Debug)]
+struct LoadedCertificate {
+    files: (Vec<u8>, Vec<u8>),
+    key: Arc<CertifiedKey>,
+}
+
+impl CertificateResolver {
+    pub fn load(certificate_path: String, private_key_path: String) -> Result<Self, io::Error> {
+        let current = LoadedCertificate::read(&certificate_path, &private_key_path)?;
+        Ok(Self {
+            certificate_path,
+            private_key_path,
+            current: RwLock::new(current),
+        })
+    }
+
+    /// Reads the certificate files again, and serves them from now on if they
+    /// changed. Returns whether they did.
+    ///
+    /// A certificate that does not match its key is rejected, so that the
+    /// previous one is still served while the files are being replaced.
+    pub fn reload(&self) -> Result<bool, io::Error> {
+        let files = (
+            std::fs::read(&self.certificate_path)?,
+            std::fs::read(&self.private_key_path)?,
+        );
+        if self.current.read().unwrap().files == files {
+            return Ok(false);
+        }
+        *self.current.write().unwrap() = LoadedCertificate::parse(files)?;
+        Ok(true)
+    }
+
+    pub fn certificate_chain(&self) -> Vec<CertificateDer<'static>> {
+        self.current.read().unwrap().key.cert.clone()
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().key.clone())
+    }
+}
+
+impl LoadedCertificate {
+    fn read(certificate_path: &str, private_key_path: &str) -> Result<Self, io::Error> {
+        Self::parse((
+            std::fs::read(certificate_path)?,
+            std::fs::read(private_key_path)?,
+        ))
+    }
+
+    fn parse(files: (Vec<u8>, Vec<u8>)) -> Result<Self, io::Error> {
+        let chain =
+            rustls_pemfile::certs(&mut files.0.as_slice()).collect::<Result<Vec<_>, _>>()?;
+        if chain.is_empty() {
+            return Err(invalid_data("No certificate found."));
+        }
+        let private_key = rustls_pemfile::private_key(&mut files.1.as_slice())?
+            .ok_or_else(|| invalid_data("No private key found."))?;
+        let key = CertifiedKey::new(
+            chain,
+            any_supported_type(&private_key).map_err(invalid_data)?,
+        );
+        key.keys_match().map_err(invalid_data)?;
+        Ok(Self {
+            files,
+            key: Arc::new(key),
+        })
+    }
+}
+
+fn read_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, io::Error> {
+    let file = std::fs::read(path)?;
+    rustls_pemfile::certs(&mut file.as_slice()).collect()
+}
+
+fn invalid_data(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Who is on the other end of a mutually authenticated connection: the name
+/// configured for its certificate, or the certificate fingerprint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity(String);
+
+impl AsRef<str> for ClientIdentity {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A client allowed on the operator routes, `/admin` and `/webhooks`: one that
+/// authenticated with a certificate, or any client when
+/// `application.open_operator_routes` is set.
+pub struct Operator;
+
+/// Whether operator routes are served to clients without an identity.
+#[derive(Clone, Copy)]
+pub struct OperatorRoutes {
+    pub open: bool,
+}
+
+impl FromRequest for Operator {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let open = request
+            .app_data::<OperatorRoutes>()
+            .is_some_and(|routes| routes.open);
+        if open || request.conn_data::<ClientIdentity>().is_some() {
+            ready(Ok(Operator))
+        } else {
+            ready(Err(actix_web::error::ErrorForbidden(
+                "This route is only served to clients authenticated with a certificate.",
+            )))
+        }
+    }
+}
+
+/// Maps client certificates to the identities of `application.tls.client_auth`.
+#[derive(Clone)]
+pub struct ClientIdentities(HashMap<String, String>);
+
+impl ClientIdentities {
+    pub fn new(settings: &ClientAuthSettings) -> Self {
+        // Fingerprints are often written in upper case, colon separated.
+        let identities = settings
+            .identities
+            .iter()
+            .map(|(fingerprint, name)| {
+                let fingerprint = fingerprint.replace(':', "").to_lowercase();
+                (fingerprint, name.clone())
+            })
+            .collect();
+        Self(identities)
+    }
+
+    pub fn identify(&self, certificate: &CertificateDer<'_>) -> ClientIdentity {
+        let fingerprint = fingerprint(certificate);
+        match self.0.get(&fingerprint) {
+            Some(name) => ClientIdentity(name.clone()),
+            None => ClientIdentity(fingerprint),
+        }
+    }
+
+    /// Identifies the client of a connection accepted by `listen_rustls_0_23`.
+    pub fn identify_connection(&self, connection: &dyn Any) -> Option<ClientIdentity> {
+        let stream = connection.downcast_ref::<TlsStream<TcpStream>>()?;
+        let (_, session) = stream.get_ref();
+        let certificate = session.peer_certificates()?.first()?;
+        Some(self.identify(certificate))
+    }
+}
+
+/// The lowercase hexadecimal SHA-256 digest of a DER certificate.
+pub fn fingerprint(certificate: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(certificate))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::ClientAuthSettings;
+    use crate::tls::{fingerprint, CertificateResolver, ClientIdentities, ClientIdentity};
+    use claim::{assert_err, assert_ok};
+    use rustls::pki_types::CertificateDer;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    struct Files {
+        certificate: PathBuf,
+        private_key: PathBuf,
+    }
+
+    impl Files {
+        fn new() -> Self {
+            let directory = std::env::temp_dir();
+            let id = Uuid::new_v4();
+            Self {
+                certificate: directory.join(format!("metaman-{}.crt", id)),
+                private_key: directory.join(format!("metaman-{}.key", id)),
+            }
+        }
+
+        fn write(&self, certificate: &rcgen::Certificate) {
+            std::fs::write(&self.certificate, certificate.serialize_pem().unwrap()).unwrap();
+            std::fs::write(&self.private_key, certificate.serialize_private_key_pem()).unwrap();
+        }
+
+        fn resolver(&self) -> Result<CertificateResolver, std::io::Error> {
+            CertificateResolver::load(
+                self.certificate.to_string_lossy().into_owned(),
+                self.private_key.to_string_lossy().into_owned(),
+            )
+        }
+    }
+
+    fn self_signed() -> rcgen::Certificate {
+        rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap()
+    }
+
+    #[test]
+    fn a_changed_certificate_is_served_after_a_reload() {
+        let files = Files::new();
+        files.write(&self_signed());
+        let resolver = files.resolver().unwrap();
+        let before = resolver.certificate_chain();
+
+        assert!(!resolver.reload().unwrap());
+        files.write(&self_signed());
+        assert!(resolver.reload().unwrap());
+
+        assert_ne!(before, resolver.certificate_chain());
+    }
+
+    #[test]
+    fn a_certificate_that_does_not_match_its_key_is_not_served() {
+        let files = Files::new();
+        files.write(&self_signed());
+        let resolver = files.resolver().unwrap();
+        let before = resolver.certificate_chain();
+
+        let other = self_signed();
+        std::fs::write(&files.certificate, other.serialize_pem().unwrap()).unwrap();
+
+        assert_err!(resolver.reload());
+        assert_eq!(before, resolver.certificate_chain());
+        files.write(&other);
+        assert_ok!(resolver.reload());
+    }
+
+    #[test]
+    fn clients_are_identified_by_name_or_fingerprint() {
+        let known = CertificateDer::from(vec![1, 2, 3]);
+        let unknown = CertificateDer::from(vec![4, 5, 6]);
+        let known_fingerprint = fingerprint(&known).to_uppercase();
+        let settings = ClientAuthSettings {
+            ca_certificate: "ca.crt".into(),
+            identities: [(known_fingerprint, "steward".to_string())].into(),
+        };
+
+        let identities = ClientIdentities::new(&settings);
+
+        assert_eq!(
+            ClientIdentity("steward".into()),
+            identities.identify(&known)
+        );
+        assert_eq!(
+            ClientIdentity(fingerprint(&unknown)),
+            identities.identify(&unknown)
+        );
+    }
+}
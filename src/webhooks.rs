@@ -0,0 +1,370 @@
+//! Posts marking events to the webhooks subscribed to them.
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use secrecy::ExposeSecret;
+use sha2::Sha256;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::configuration::WebhookSettings;
+use crate::domain::{
+    webhook_sink_name, DeadLetter, MarkingEventType, OutboxEvent, Webhook, WebhookDelivery,
+};
+use crate::outbox::{EventSink, SinkError};
+use crate::repository::{RepositoryError, WebhookRepository};
+
+/// `sha256=` followed by the hex HMAC-SHA256 of the body, keyed with the
+/// secret of the webhook.
+pub const SIGNATURE_HEADER: &str = "X-Metaman-Signature-256";
+/// The type of the event, such as `marking.updated`.
+pub const EVENT_HEADER: &str = "X-Metaman-Event";
+/// The id of the event, the same for every attempt at posting it.
+pub const DELIVERY_HEADER: &str = "X-Metaman-Delivery";
+
+/// The signature of `payload`, as sent in the `X-Metaman-Signature-256`
+/// header.
+pub fn sign(secret: &str, payload: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(payload);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+/// Whether `ip` belongs to this host or a private network rather than to a
+/// receiver on the internet.
+pub fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // Carrier-grade NAT, 100.64.0.0/10.
+                || (first == 100 && second & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    // Unique local, fc00::/7, and link-local, fe80::/10.
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80
+            }
+        },
+    }
+}
+
+/// Why `url` cannot be a webhook when private targets are not allowed:
+/// its host is a private address, or a name for this host. Other names are
+/// checked once resolved, when events are posted.
+pub fn private_target(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let host = url
+        .host_str()?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let private = match host.parse::<IpAddr>() {
+        Ok(ip) => is_private(ip),
+        Err(_) => {
+            let name = host.trim_end_matches('.').to_lowercase();
+            name == "localhost" || name.ends_with(".localhost")
+        }
+    };
+    private.then(|| format!("{} targets a private address.", url))
+}
+
+/// Resolves the names of receivers, failing for those with a private
+/// address. Checking at every delivery keeps a name registered with a
+/// public address from being repointed at the internal network.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            if let Some(address) = addresses.iter().find(|address| is_private(address.ip())) {
+                return Err(format!(
+                    "{} resolves to the private address {}.",
+                    name.as_str(),
+                    address.ip()
+                )
+                .into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// How long to wait before attempt number `attempt`, from the second one on.
+fn retry_delay(settings: &WebhookSettings, attempt: u32) -> Duration {
+    let doublings = attempt.saturating_sub(2).min(16);
+    Duration::from_millis(settings.retry_delay_ms.saturating_mul(1 << doublings))
+}
+
+/// Builds the client events are posted to webhooks with, shared by their
+/// sinks.
+pub fn webhook_client(settings: &WebhookSettings) -> Result<reqwest::Client, reqwest::Error> {
+    let client = reqwest::Client::builder().timeout(Duration::from_secs(settings.timeout));
+    // Redirects could lead to private addresses, which names resolved by
+    // `PublicResolver` cannot.
+    if settings.allow_private_targets {
+        client
+    } else {
+        client
+            .dns_resolver(Arc::new(PublicResolver))
+            .redirect(Policy::none())
+    }
+    .build()
+}
+
+/// Publishes outbox events to one webhook: those it subscribes to, from the
+/// cursor its registration started at the last event in the outbox.
+pub struct WebhookSink {
+    webhook_id: Uuid,
+    repository: Arc<dyn WebhookRepository>,
+    client: reqwest::Client,
+    settings: WebhookSettings,
+}
+
+impl WebhookSink {
+    pub fn new(
+        webhook_id: Uuid,
+        repository: Arc<dyn WebhookRepository>,
+        client: reqwest::Client,
+        settings: WebhookSettings,
+    ) -> Self {
+        Self {
+            webhook_id,
+            repository,
+            client,
+            settings,
+        }
+    }
+
+    /// Posts an event until the webhook accepts it, recording every attempt,
+    /// and dead-letters it once `max_attempts` have failed.
+    async fn deliver(
+        &self,
+        webhook: &Webhook,
+        event_id: Uuid,
+        event_type: MarkingEventType,
+        payload: &[u8],
+    ) {
+        let attempts = self.settings.max_attempts.max(1);
+        let mut last_error = None;
+        for attempt in 1..=attempts {
+            if attempt > 1 {
+                tokio::time::sleep(retry_delay(&self.settings, attempt)).await;
+            }
+            let (status_code, error) = self.post(webhook, event_id, event_type, payload).await;
+            let delivery = WebhookDelivery {
+                id: Uuid::new_v4(),
+                webhook_id: webhook.id,
+                event_id,
+                event_type: event_type.as_str().to_owned(),
+                attempt: attempt as i32,
+                status_code: status_code.map(i32::from),
+                error: error.clone(),
+                delivered: error.is_none(),
+                attempted_at: Utc::now(),
+            };
+            if let Err(e) = self.repository.record_delivery(&delivery).await {
+                tracing::warn!(error.message = %e, "Failed to record a webhook delivery.");
+            }
+            if error.is_none() {
+                return;
+            }
+            last_error = error;
+        }
+
+        tracing::warn!(
+            webhook.id = %webhook.id,
+            event.id = %event_id,
+            "Dead-lettered an event no attempt could deliver."
+        );
+        let dead_letter = DeadLetter {
+            id: Uuid::new_v4(),
+            webhook_id: webhook.id,
+            event_id,
+            event_type: event_type.as_str().to_owned(),
+            payload: String::from_utf8_lossy(payload).into_owned(),
+            attempts: attempts as i32,
+            last_error,
+            created_at: Utc::now(),
+        };
+        if let Err(e) = self.repository.insert_dead_letter(&dead_letter).await {
+            tracing::error!(error.message = %e, "Failed to save a dead letter.");
+        }
+    }
+
+    /// The status the receiver answered with, if any, and why the attempt
+    /// failed, if it did.
+    async fn post(
+        &self,
+        webhook: &Webhook,
+        event_id: Uuid,
+        event_type: MarkingEventType,
+        payload: &[u8],
+    ) -> (Option<u16>, Option<String>) {
+        if !self.settings.allow_private_targets {
+            if let Some(reason) = private_target(&webhook.url) {
+                return (None, Some(reason));
+            }
+        }
+        let response = self
+            .client
+            .post(&webhook.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, event_type.as_str())
+            .header(DELIVERY_HEADER, event_id.to_string())
+            .header(
+                SIGNATURE_HEADER,
+                sign(webhook.secret.expose_secret(), payload),
+            )
+            .body(payload.to_vec())
+            .send()
+            .await;
+        match response {
+            Ok(response) if response.status().is_success() => {
+                (Some(response.status().as_u16()), None)
+            }
+            Ok(response) => (
+                Some(response.status().as_u16()),
+                Some(format!("The receiver answered {}.", response.status())),
+            ),
+            Err(e) => (None, Some(e.to_string())),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventSink for WebhookSink {
+    fn name(&self) -> String {
+        webhook_sink_name(self.webhook_id)
+    }
+
+    /// Posts `event` to the webhook if it subscribes to it. The event counts
+    /// as published once the webhook has accepted or dead-lettered it.
+    #[tracing::instrument(
+        name = "Posting a marking event to a webhook",
+        skip(self, event),
+        fields(
+            webhook.id = %self.webhook_id,
+            event.id = %event.event_id,
+            event.type = %event.event_type
+        )
+    )]
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), SinkError> {
+        // Written by a newer build: no webhook can subscribe to it.
+        let event_type = match MarkingEventType::parse(&event.event_type) {
+            Ok(event_type) => event_type,
+            Err(_) => return Ok(()),
+        };
+        let webhook = match self.repository.get_webhook(self.webhook_id).await {
+            Ok(webhook) => webhook,
+            // Deleted since its sink started.
+            Err(RepositoryError::WebhookNotFound(_)) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if !webhook.subscribes_to(event_type) {
+            return Ok(());
+        }
+        self.deliver(
+            &webhook,
+            event.event_id,
+            event_type,
+            event.payload.as_bytes(),
+        )
+        .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::WebhookSettings;
+    use crate::webhooks::{is_private, private_target, retry_delay, sign, PublicResolver};
+    use claim::{assert_none, assert_some};
+    use reqwest::dns::Resolve;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    #[test]
+    fn payloads_are_signed_with_hmac_sha256() {
+        // From RFC 4231, test case 2.
+        assert_eq!(
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            sign("Jefe", b"what do ya want for nothing?")
+        );
+    }
+
+    #[test]
+    fn retries_back_off_exponentially() {
+        let settings = WebhookSettings {
+            max_attempts: 5,
+            retry_delay_ms: 100,
+            timeout: 1,
+            allow_private_targets: false,
+        };
+
+        let delays: Vec<_> = (2..=5)
+            .map(|attempt| retry_delay(&settings, attempt))
+            .collect();
+
+        assert_eq!(
+            [100, 200, 400, 800].map(Duration::from_millis).to_vec(),
+            delays
+        );
+    }
+
+    #[test]
+    fn addresses_of_this_host_and_private_networks_are_private() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:192.168.1.1",
+        ] {
+            assert!(is_private(ip.parse::<IpAddr>().unwrap()), "{}", ip);
+        }
+        for ip in ["93.184.216.34", "100.128.0.1", "2606:2800:220:1::"] {
+            assert!(!is_private(ip.parse::<IpAddr>().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn urls_naming_this_host_are_private_targets() {
+        assert_some!(private_target("http://localhost:8080/hooks"));
+        assert_some!(private_target("http://api.localhost/hooks"));
+        assert_some!(private_target("http://[::1]/hooks"));
+        assert_none!(private_target("https://tip.example.com/hooks"));
+        assert_none!(private_target("https://93.184.216.34/hooks"));
+    }
+
+    #[tokio::test]
+    async fn names_resolving_to_private_addresses_are_not_resolved() {
+        let resolved = PublicResolver.resolve("localhost".parse().unwrap()).await;
+
+        assert!(resolved.is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use crate::helpers::{create_database, spawn_app, spawn_app_with};
+use metaman::configuration::get_configuration;
+use metaman::repository::{MigrationState, PostgresMarkingRepository, SchemaRepository};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn migration_status_lists_every_applied_migration() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/migrations", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let migrations: Vec<serde_json::Value> = response.json().await.unwrap();
+    let shipped = std::fs::read_dir("migrations").unwrap().count();
+    assert_eq!(shipped, migrations.len());
+    assert!(migrations.iter().all(|m| m["state"] == "applied"));
+    assert_eq!("create markings table", migrations[0]["description"]);
+}
+
+#[tokio::test]
+async fn concurrent_replicas_migrate_a_fresh_database_once() {
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    let pool = create_database(&configuration.database).await;
+    let first = PostgresMarkingRepository::new(pool.clone());
+    let second = PostgresMarkingRepository::new(pool);
+
+    let pending = first.migration_status().await.unwrap();
+    assert!(pending.iter().all(|m| m.state == MigrationState::Pending));
+
+    let (a, b) = tokio::join!(first.migrate(), second.migrate());
+    assert!(a.is_ok(), "{:?}", a);
+    assert!(b.is_ok(), "{:?}", b);
+    let applied = first.migration_status().await.unwrap();
+    assert_eq!(pending.len(), applied.len());
+    assert!(applied.iter().all(|m| m.state == MigrationState::Applied));
+}
+
+#[tokio::test]
+async fn migration_status_is_refused_to_clients_without_a_certificate() {
+    let configuration = get_configuration().expect("Failed to read configuration");
+    let address = spawn_app_with(configuration).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/migrations", address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn migration_status_leaves_an_unmigrated_database_alone() {
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    let pool = create_database(&configuration.database).await;
+    let repository = PostgresMarkingRepository::new(pool.clone());
+
+    let migrations = repository.migration_status().await.unwrap();
+
+    assert!(migrations
+        .iter()
+        .all(|m| m.state == MigrationState::Pending));
+    let tables = sqlx::query!("SELECT tablename FROM pg_tables WHERE schemaname = 'public'")
+        .fetch_all(&pool)
+        .await
+        .expect("Failed to list tables.");
+    assert!(tables.is_empty());
+}
@@ -0,0 +1,200 @@
+use crate::helpers::{spawn_app, TestApp};
+
+fn marking(name: &str) -> serde_json::Value {
+    serde_json::json!({
+        "name": name,
+        "definition_type": "statement",
+        "definition": format!("Copyright {}", name)
+    })
+}
+
+async fn count_markings(app: &TestApp) -> i64 {
+    sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM markings")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count markings.")
+        .count
+}
+
+fn statuses(results: &serde_json::Value) -> Vec<u64> {
+    results
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["status"].as_u64().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn bulk_create_inserts_every_marking_and_returns_a_201() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/markings/bulk", &app.address))
+        .json(&[marking("acme"), marking("globex"), marking("initech")])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(201, response.status().as_u16());
+    let results: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(vec![201, 201, 201], statuses(&results));
+    assert_eq!("globex", results[1]["marking"]["name"]);
+    assert_eq!(3, count_markings(&app).await);
+}
+
+#[tokio::test]
+async fn an_atomic_bulk_create_writes_nothing_when_an_item_fails() {
+    let app = spawn_app().await;
+    app.post_markings(
+        r#"{"name": "globex", "definition_type": "statement", "definition": "Globex"}"#,
+    )
+    .await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/markings/bulk", &app.address))
+        .json(&[marking("acme"), marking("globex"), marking("initech")])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(422, response.status().as_u16());
+    let results: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(vec![424, 409, 424], statuses(&results));
+    assert_eq!(1, count_markings(&app).await);
+}
+
+#[tokio::test]
+async fn an_atomic_bulk_create_reports_invalid_items_without_writing() {
+    let app = spawn_app().await;
+    let invalid = serde_json::json!({
+        "name": "",
+        "definition_type": "statement",
+        "definition": "Nameless"
+    });
+
+    let response = app
+        .api_client
+        .post(format!("{}/markings/bulk", &app.address))
+        .json(&[marking("acme"), invalid])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(422, response.status().as_u16());
+    let results: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(vec![424, 400], statuses(&results));
+    assert!(results[1]["error"].is_string());
+    assert_eq!(0, count_markings(&app).await);
+}
+
+#[tokio::test]
+async fn a_best_effort_bulk_create_writes_the_items_that_succeed() {
+    let app = spawn_app().await;
+    app.post_markings(
+        r#"{"name": "globex", "definition_type": "statement", "definition": "Globex"}"#,
+    )
+    .await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/markings/bulk?mode=best_effort", &app.address))
+        .json(&[marking("acme"), marking("globex"), marking("initech")])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(207, response.status().as_u16());
+    let results: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(vec![201, 409, 201], statuses(&results));
+    assert_eq!(3, count_markings(&app).await);
+}
+
+#[tokio::test]
+async fn bulk_results_do_not_leak_database_errors() {
+    let app = spawn_app().await;
+    sqlx::query!("ALTER TABLE outbox RENAME TO outbox_moved")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to rename the outbox.");
+
+    let response = app
+        .api_client
+        .post(format!("{}/markings/bulk?mode=best_effort", &app.address))
+        .json(&[marking("acme")])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(207, response.status().as_u16());
+    let results: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(vec![500], statuses(&results));
+    assert_eq!("The marking could not be saved.", results[0]["error"]);
+}
+
+#[tokio::test]
+async fn bulk_create_returns_a_400_for_an_empty_or_oversized_batch() {
+    let app = spawn_app().await;
+    let oversized: Vec<_> = (0..501).map(|i| marking(&format!("m{}", i))).collect();
+
+    for batch in [Vec::new(), oversized] {
+        let response = app
+            .api_client
+            .post(format!("{}/markings/bulk", &app.address))
+            .json(&batch)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        assert_eq!(400, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn bulk_update_checks_the_version_of_every_item() {
+    let app = spawn_app().await;
+    let response = app
+        .api_client
+        .post(format!("{}/markings/bulk", &app.address))
+        .json(&[marking("acme"), marking("globex")])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let created: serde_json::Value = response.json().await.unwrap();
+    let update = |index: usize, version: i64| {
+        let current = &created[index]["marking"];
+        serde_json::json!({
+            "id": current["id"],
+            "version": version,
+            "name": current["name"],
+            "definition_type": "statement",
+            "definition": "Updated"
+        })
+    };
+    let version = created[0]["marking"]["version"].as_i64().unwrap();
+
+    let response = app
+        .api_client
+        .put(format!("{}/markings/bulk", &app.address))
+        .json(&[update(0, version), update(1, version + 1)])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(422, response.status().as_u16());
+    let results: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(vec![424, 412], statuses(&results));
+
+    let response = app
+        .api_client
+        .put(format!("{}/markings/bulk", &app.address))
+        .json(&[update(0, version), update(1, version)])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    let results: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(vec![200, 200], statuses(&results));
+    assert_eq!("Updated", results[1]["marking"]["definition"]);
+}
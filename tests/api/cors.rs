@@ -0,0 +1,91 @@
+use crate::helpers::spawn_app_with;
+use metaman::configuration::get_configuration;
+
+const UI_ORIGIN: &str = "https://stewards.example.com";
+
+async fn spawn_app_for_ui() -> String {
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.application.cors.allowed_origins = vec![UI_ORIGIN.into()];
+    spawn_app_with(configuration).await
+}
+
+#[tokio::test]
+async fn preflight_requests_from_an_allowed_origin_succeed() {
+    let address = spawn_app_for_ui().await;
+
+    let response = reqwest::Client::new()
+        .request(reqwest::Method::OPTIONS, format!("{}/markings/1", address))
+        .header("Origin", UI_ORIGIN)
+        .header("Access-Control-Request-Method", "PUT")
+        .header("Access-Control-Request-Headers", "content-type, if-match")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let headers = response.headers();
+    assert_eq!(UI_ORIGIN, headers["access-control-allow-origin"]);
+    assert!(headers["access-control-allow-methods"]
+        .to_str()
+        .unwrap()
+        .contains("PUT"));
+}
+
+#[tokio::test]
+async fn responses_to_an_allowed_origin_expose_api_headers() {
+    let address = spawn_app_for_ui().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/markings", address))
+        .header("Origin", UI_ORIGIN)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let headers = response.headers();
+    assert_eq!(UI_ORIGIN, headers["access-control-allow-origin"]);
+    let exposed = headers["access-control-expose-headers"]
+        .to_str()
+        .unwrap()
+        .to_lowercase();
+    assert!(exposed.contains("etag"));
+    assert!(exposed.contains("x-request-id"));
+}
+
+#[tokio::test]
+async fn other_origins_are_not_allowed() {
+    let address = spawn_app_for_ui().await;
+
+    let response = reqwest::Client::new()
+        .request(reqwest::Method::OPTIONS, format!("{}/markings", address))
+        .header("Origin", "https://evil.example.com")
+        .header("Access-Control-Request-Method", "POST")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert!(response
+        .headers()
+        .get("access-control-allow-origin")
+        .is_none());
+}
+
+#[tokio::test]
+async fn cors_is_off_when_no_origin_is_configured() {
+    let configuration = get_configuration().expect("Failed to read configuration");
+    let address = spawn_app_with(configuration).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/markings", address))
+        .header("Origin", UI_ORIGIN)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response
+        .headers()
+        .get("access-control-allow-origin")
+        .is_none());
+}
@@ -0,0 +1,105 @@
+use crate::helpers::{spawn_app, TestApp};
+use std::time::Duration;
+
+async fn create_marking(app: &TestApp, name: &str) {
+    let response = app
+        .api_client
+        .post(format!("{}/markings", &app.address))
+        .json(&serde_json::json!({
+            "name": name,
+            "definition_type": "statement",
+            "definition": format!("Copyright {}", name)
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(201, response.status().as_u16());
+}
+
+async fn open_stream(app: &TestApp, last_event_id: Option<&str>) -> reqwest::Response {
+    let mut request = app.api_client.get(format!("{}/events", &app.address));
+    if let Some(id) = last_event_id {
+        request = request.header("Last-Event-ID", id);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+/// Reads `response` until it has sent `count` events, returning the fields
+/// of each.
+async fn read_events(response: &mut reqwest::Response, count: usize) -> Vec<Vec<(String, String)>> {
+    let mut text = String::new();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while text.matches("\n\n").count() < count {
+            let chunk = response.chunk().await.unwrap().expect("The stream ended.");
+            text.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    })
+    .await
+    .expect("The stream did not send every event.");
+
+    text.split_terminator("\n\n")
+        .map(|event| {
+            event
+                .lines()
+                .filter_map(|line| line.split_once(": "))
+                .map(|(field, value)| (field.to_owned(), value.to_owned()))
+                .collect()
+        })
+        .collect()
+}
+
+fn field<'a>(event: &'a [(String, String)], name: &str) -> &'a str {
+    &event.iter().find(|(field, _)| field == name).unwrap().1
+}
+
+#[tokio::test]
+async fn new_events_are_streamed_as_they_are_saved() {
+    let app = spawn_app().await;
+    create_marking(&app, "acme").await;
+
+    let mut response = open_stream(&app, None).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "text/event-stream",
+        response.headers()["Content-Type"].to_str().unwrap()
+    );
+    create_marking(&app, "globex").await;
+
+    let events = read_events(&mut response, 1).await;
+    assert_eq!("2", field(&events[0], "id"));
+    assert_eq!("marking.created", field(&events[0], "event"));
+    let data: serde_json::Value = serde_json::from_str(field(&events[0], "data")).unwrap();
+    assert_eq!("globex", data["marking"]["name"]);
+}
+
+#[tokio::test]
+async fn streams_resume_after_the_last_event_id() {
+    let app = spawn_app().await;
+    for name in ["acme", "globex", "initech"] {
+        create_marking(&app, name).await;
+    }
+
+    let mut response = open_stream(&app, Some("1")).await;
+
+    let events = read_events(&mut response, 2).await;
+    let ids: Vec<_> = events.iter().map(|event| field(event, "id")).collect();
+    assert_eq!(vec!["2", "3"], ids);
+    let data: serde_json::Value = serde_json::from_str(field(&events[1], "data")).unwrap();
+    assert_eq!("initech", data["marking"]["name"]);
+}
+
+#[tokio::test]
+async fn streams_reject_an_invalid_last_event_id() {
+    let app = spawn_app().await;
+
+    for id in ["latest", "-1"] {
+        let response = open_stream(&app, Some(id)).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not reject Last-Event-ID {}.",
+            id
+        );
+    }
+}
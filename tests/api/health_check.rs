@@ -0,0 +1,109 @@
+use crate::helpers::{create_database, spawn_app};
+use metaman::configuration::get_configuration;
+use metaman::repository::{PostgresMarkingRepository, Storage};
+use metaman::startup::{get_storage, run};
+use std::net::TcpListener;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn health_check_works() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/health_check", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert!(response.status().is_success());
+    assert_eq!(Some(0), response.content_length());
+}
+
+#[tokio::test]
+async fn liveness_and_readiness_succeed_with_a_migrated_database() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/health/live", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+
+    let response = app
+        .api_client
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    let readiness: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(true, readiness["ready"]);
+    assert_eq!(true, readiness["checks"]["database"]["ready"]);
+    assert_eq!(true, readiness["checks"]["migrations"]["ready"]);
+}
+
+#[tokio::test]
+async fn readiness_fails_when_the_database_is_unreachable() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let address = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.database.port = 1;
+    let storage = get_storage(&configuration.database)
+        .await
+        .expect("Failed to open the marking storage.");
+    #[allow(clippy::let_underscore_future)]
+    let _ = tokio::spawn(
+        run(listener, storage, &configuration.application).expect("Failed to bind address"),
+    );
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("{}/health/live", address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+
+    let response = client
+        .get(format!("{}/health/ready", address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(503, response.status().as_u16());
+    let readiness: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(false, readiness["ready"]);
+    assert_eq!(false, readiness["checks"]["database"]["ready"]);
+    assert_eq!("unreachable", readiness["checks"]["database"]["detail"]);
+}
+
+#[tokio::test]
+async fn readiness_fails_while_migrations_are_pending() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let address = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    let pool = create_database(&configuration.database).await;
+    let storage = Storage::new(PostgresMarkingRepository::new(pool));
+    #[allow(clippy::let_underscore_future)]
+    let _ = tokio::spawn(
+        run(listener, storage, &configuration.application).expect("Failed to bind address"),
+    );
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/health/ready", address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(503, response.status().as_u16());
+    let readiness: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(true, readiness["checks"]["database"]["ready"]);
+    assert_eq!(false, readiness["checks"]["migrations"]["ready"]);
+    assert_eq!(
+        "schema mismatch",
+        readiness["checks"]["migrations"]["detail"]
+    );
+}
@@ -0,0 +1,137 @@
+use metaman::configuration::{get_configuration, DatabaseKind, DatabaseSettings, Settings};
+use metaman::repository::{PostgresMarkingRepository, Storage};
+use metaman::startup::{get_storage, run};
+use metaman::telemetry::{get_subscriber, init_subscriber};
+use once_cell::sync::Lazy;
+use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::net::TcpListener;
+use uuid::Uuid;
+
+static TRACING: Lazy<()> = Lazy::new(|| {
+    let default_filter_level = "info".to_string();
+    let subscriber_name = "test".to_string();
+    if std::env::var("TEST_LOG").is_ok() {
+        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        init_subscriber(subscriber);
+    } else {
+        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        init_subscriber(subscriber);
+    }
+});
+
+pub struct TestApp {
+    pub address: String,
+    pub db_pool: PgPool,
+    pub api_client: reqwest::Client,
+}
+
+impl TestApp {
+    pub async fn post_markings(&self, body: &'static str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/markings", &self.address))
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_marking(&self, id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/markings/{}", &self.address, id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_webhooks(&self, body: serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/webhooks", &self.address))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_webhook_resource(&self, id: &str, resource: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/webhooks/{}/{}", &self.address, id, resource))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+}
+
+pub async fn spawn_app() -> TestApp {
+    Lazy::force(&TRACING);
+
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+    let address = format!("http://127.0.0.1:{}", port);
+
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    // Give up on failing webhooks within a fraction of a second.
+    configuration.application.webhooks.max_attempts = 3;
+    configuration.application.webhooks.retry_delay_ms = 10;
+    // Receivers are mock servers on this host.
+    configuration.application.webhooks.allow_private_targets = true;
+    configuration.application.outbox.enabled = true;
+    configuration.application.outbox.poll_interval_ms = 10;
+    configuration.application.open_operator_routes = true;
+
+    let connection_pool = configure_database(&configuration.database).await;
+    let storage = Storage::new(PostgresMarkingRepository::new(connection_pool.clone()));
+    let server =
+        run(listener, storage, &configuration.application).expect("Failed to bind address");
+
+    #[allow(clippy::let_underscore_future)]
+    let _ = tokio::spawn(server);
+
+    TestApp {
+        address,
+        db_pool: connection_pool,
+        api_client: reqwest::Client::new(),
+    }
+}
+
+/// Starts the API on in-memory storage with `configuration`, for tests of
+/// settings rather than of persistence. Returns its address.
+pub async fn spawn_app_with(mut configuration: Settings) -> String {
+    Lazy::force(&TRACING);
+
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let address = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+    configuration.database.kind = DatabaseKind::Memory;
+    let storage = get_storage(&configuration.database)
+        .await
+        .expect("Failed to open the marking storage.");
+    let server =
+        run(listener, storage, &configuration.application).expect("Failed to bind address");
+    #[allow(clippy::let_underscore_future)]
+    let _ = tokio::spawn(server);
+    address
+}
+
+pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
+    let connection_pool = create_database(config).await;
+    sqlx::migrate!("./migrations")
+        .run(&connection_pool)
+        .await
+        .expect("failed to migrate the database.");
+    connection_pool
+}
+
+pub async fn create_database(config: &DatabaseSettings) -> PgPool {
+    let mut connection = PgConnection::connect_with(&config.without_db())
+        .await
+        .expect("Failed to connect to Postgres.");
+    connection
+        .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
+        .await
+        .expect("Failed to create database.");
+
+    PgPool::connect_with(config.with_db())
+        .await
+        .expect("Failed to connect to Postgres.")
+}
@@ -0,0 +1,145 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use metaman::configuration::get_configuration;
+
+#[tokio::test]
+async fn retrying_with_the_same_idempotency_key_returns_the_original_response() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "name": "tlp_red",
+        "definition_type": "tlp",
+        "definition": "TLP Red"
+    });
+
+    let mut responses = Vec::new();
+    for _ in 0..2 {
+        let response = app
+            .api_client
+            .post(format!("{}/markings", &app.address))
+            .header("Idempotency-Key", "ingestion-worker-42")
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(201, response.status().as_u16());
+        let etag = response.headers()["ETag"].clone();
+        let marking: serde_json::Value = response.json().await.unwrap();
+        responses.push((etag, marking));
+    }
+
+    assert_eq!(responses[0], responses[1]);
+    let saved = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM markings")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count markings.");
+    assert_eq!(1, saved.count);
+}
+
+#[tokio::test]
+async fn reusing_an_idempotency_key_for_another_payload_returns_a_422() {
+    let app = spawn_app().await;
+
+    for (definition, status) in [("TLP Red", 201), ("TLP Amber", 422)] {
+        let response = app
+            .api_client
+            .post(format!("{}/markings", &app.address))
+            .header("Idempotency-Key", "ingestion-worker-42")
+            .json(&serde_json::json!({
+                "name": "tlp_red",
+                "definition_type": "tlp",
+                "definition": definition
+            }))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        assert_eq!(status, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn different_idempotency_keys_are_processed_independently() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "name": "tlp_red",
+        "definition_type": "tlp",
+        "definition": "TLP Red"
+    });
+
+    for (key, status) in [("first", 201), ("second", 409)] {
+        let response = app
+            .api_client
+            .post(format!("{}/markings", &app.address))
+            .header("Idempotency-Key", key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        assert_eq!(status, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn an_idempotency_key_abandoned_mid_request_can_be_retried() {
+    let app = spawn_app().await;
+    // The request claiming the key, sent from the same address, was dropped
+    // before saving a response.
+    sqlx::query!(
+        r#"
+        INSERT INTO idempotency
+            (scope, client, idempotency_key, request_fingerprint, created_at, locked_until)
+        VALUES ('POST /markings', 'ip:127.0.0.1', 'ingestion-worker-42', 'abandoned', now(),
+            now() - interval '1 second')
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to save the abandoned claim.");
+
+    let response = app
+        .api_client
+        .post(format!("{}/markings", &app.address))
+        .header("Idempotency-Key", "ingestion-worker-42")
+        .json(&serde_json::json!({
+            "name": "tlp_red",
+            "definition_type": "tlp",
+            "definition": "TLP Red"
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(201, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn clients_sending_the_same_idempotency_key_do_not_share_it() {
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.application.rate_limit.api_keys = vec!["partner".into(), "other-partner".into()];
+    let address = spawn_app_with(configuration).await;
+    let create = |api_key: &'static str, name: &'static str| {
+        reqwest::Client::new()
+            .post(format!("{}/markings", &address))
+            .header("X-Api-Key", api_key)
+            .header("Idempotency-Key", "ingestion-worker-42")
+            .json(&serde_json::json!({
+                "name": name,
+                "definition_type": "tlp",
+                "definition": "TLP Red"
+            }))
+            .send()
+    };
+
+    let first = create("partner", "tlp_red").await.unwrap();
+    let other = create("other-partner", "tlp_red_too").await.unwrap();
+    let retry = create("partner", "tlp_red").await.unwrap();
+
+    assert_eq!(201, first.status().as_u16());
+    assert_eq!(201, other.status().as_u16());
+    assert_eq!(201, retry.status().as_u16());
+    let first: serde_json::Value = first.json().await.unwrap();
+    let other: serde_json::Value = other.json().await.unwrap();
+    let retry: serde_json::Value = retry.json().await.unwrap();
+    assert_ne!(first["id"], other["id"]);
+    assert_eq!(first, retry);
+}
@@ -0,0 +1,19 @@
+mod admin;
+mod bulk;
+mod cors;
+mod events;
+mod health_check;
+mod helpers;
+mod idempotency;
+mod markings;
+mod metrics;
+mod openapi;
+mod outbox;
+mod rate_limit;
+mod request_id;
+mod storage_backends;
+mod taxii;
+mod taxii_sync;
+mod tls;
+mod tracing;
+mod webhooks;
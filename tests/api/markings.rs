@@ -0,0 +1,440 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn create_marking_returns_a_201_for_valid_form_data() {
+    let app = spawn_app().await;
+
+    let body = "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}";
+    let response = app.post_markings(body).await;
+
+    assert_eq!(201, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT name, definition_type, definition FROM markings",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved marking.");
+
+    assert_eq!(saved.name, "tlp_red");
+    assert_eq!(saved.definition_type, "tlp");
+    assert_eq!(saved.definition, "TLP Red");
+}
+
+#[tokio::test]
+async fn create_marking_returns_a_409_when_the_name_is_taken() {
+    let app = spawn_app().await;
+
+    let body = "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}";
+    app.post_markings(body).await;
+    let response = app.post_markings(body).await;
+
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn create_marking_returns_a_400_when_fields_are_present_but_invalid() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            "{\"name\": \"\", \"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}",
+            "empty name",
+        ),
+        (
+            "{\"name\": \"tlp_red\", \"definition_type\": \"\", \"definition\": \"TLP Red\"}",
+            "empty definition type",
+        ),
+        (
+            "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\", \"definition\": \"\"}",
+            "empty definition",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_markings(body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not return a 400 when the payload had an {}.",
+            description
+        )
+    }
+}
+
+#[tokio::test]
+async fn create_marking_returns_a_400_when_data_is_missing() {
+    let app = spawn_app().await;
+
+    let test_cases = vec![
+        (
+            "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\"}",
+            "missing the definition",
+        ),
+        (
+            "{\"name\": \"tlp_red\", \"definition\": \"TLP Red\"}",
+            "missing the definition type",
+        ),
+        (
+            "{\"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}",
+            "missing the name",
+        ),
+        (
+            "{\"name\": \"tlp_red\"}",
+            "missing the definition type and the definition",
+        ),
+        (
+            "{\"definition_type\": \"tlp\",}",
+            "missing the name and the definition",
+        ),
+        (
+            "{\"definition\": \"TLP Red\"}",
+            "missing the name and the definition type",
+        ),
+        ("{}", "missing the name, definition, and definition type"),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        let response = app.post_markings(invalid_body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn create_marking_returns_a_413_when_the_body_exceeds_the_payload_limit() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "name": "tlp_red",
+        "definition_type": "tlp",
+        "definition": "a".repeat(3 * 1024 * 1024),
+    });
+
+    let response = app
+        .api_client
+        .post(format!("{}/markings", &app.address))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(413, response.status().as_u16());
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert!(error["error"].as_str().unwrap().contains("2097152 bytes"));
+}
+
+#[tokio::test]
+async fn a_created_marking_can_be_fetched_updated_and_deleted() {
+    let app = spawn_app().await;
+
+    let body = "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}";
+    let created: serde_json::Value = app.post_markings(body).await.json().await.unwrap();
+    let id = created["id"].as_str().unwrap();
+
+    let response = app.get_marking(id).await;
+    assert_eq!(200, response.status().as_u16());
+    let etag = response.headers()["ETag"].clone();
+    let fetched: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("tlp_red", fetched["name"]);
+
+    let response = app
+        .api_client
+        .put(format!("{}/markings/{}", &app.address, id))
+        .header("If-Match", etag)
+        .json(&serde_json::json!({
+            "name": "tlp_amber",
+            "definition_type": "tlp",
+            "definition": "TLP Amber"
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    let etag = response.headers()["ETag"].clone();
+    let updated: serde_json::Value = app.get_marking(id).await.json().await.unwrap();
+    assert_eq!("tlp_amber", updated["name"]);
+    assert_eq!("TLP Amber", updated["definition"]);
+
+    let response = app
+        .api_client
+        .delete(format!("{}/markings/{}", &app.address, id))
+        .header("If-Match", etag)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(204, response.status().as_u16());
+    assert_eq!(404, app.get_marking(id).await.status().as_u16());
+}
+
+#[tokio::test]
+async fn writes_without_if_match_return_a_428() {
+    let app = spawn_app().await;
+
+    let body = "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}";
+    let created: serde_json::Value = app.post_markings(body).await.json().await.unwrap();
+    let url = format!(
+        "{}/markings/{}",
+        &app.address,
+        created["id"].as_str().unwrap()
+    );
+
+    let requests = vec![
+        app.api_client.put(&url).json(&created),
+        app.api_client
+            .patch(&url)
+            .json(&serde_json::json!({"definition": "TLP Red!"})),
+        app.api_client.delete(&url),
+        app.api_client.post(format!("{}/deprecate", url)),
+        app.api_client.post(format!("{}/revoke", url)),
+    ];
+    for request in requests {
+        let response = request.send().await.expect("Failed to execute request.");
+        assert_eq!(428, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn writes_with_a_stale_etag_return_a_412() {
+    let app = spawn_app().await;
+
+    let body = "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}";
+    let response = app.post_markings(body).await;
+    let stale_etag = response.headers()["ETag"].clone();
+    let created: serde_json::Value = response.json().await.unwrap();
+    let url = format!(
+        "{}/markings/{}",
+        &app.address,
+        created["id"].as_str().unwrap()
+    );
+
+    let response = app
+        .api_client
+        .patch(&url)
+        .header("If-Match", stale_etag.clone())
+        .json(&serde_json::json!({"definition": "TLP Red, edited by the first steward"}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    let patched: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("tlp_red", patched["name"]);
+    assert_eq!(2, patched["version"]);
+
+    let response = app
+        .api_client
+        .patch(&url)
+        .header("If-Match", stale_etag.clone())
+        .json(&serde_json::json!({"definition": "TLP Red, edited by the second steward"}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(412, response.status().as_u16());
+
+    let response = app
+        .api_client
+        .delete(&url)
+        .header("If-Match", stale_etag)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(412, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn get_marking_returns_a_304_when_the_etag_still_matches() {
+    let app = spawn_app().await;
+
+    let body = "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}";
+    let response = app.post_markings(body).await;
+    let etag = response.headers()["ETag"].clone();
+    let created: serde_json::Value = response.json().await.unwrap();
+
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/markings/{}",
+            &app.address,
+            created["id"].as_str().unwrap()
+        ))
+        .header("If-None-Match", etag.clone())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(304, response.status().as_u16());
+    assert_eq!(etag, response.headers()["ETag"]);
+}
+
+#[tokio::test]
+async fn list_markings_returns_every_marking() {
+    let app = spawn_app().await;
+
+    app.post_markings(
+        "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}",
+    )
+    .await;
+    app.post_markings(
+        "{\"name\": \"tlp_amber\", \"definition_type\": \"tlp\", \"definition\": \"TLP Amber\"}",
+    )
+    .await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/markings", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let markings: Vec<serde_json::Value> = response.json().await.unwrap();
+    let names: Vec<_> = markings
+        .iter()
+        .map(|m| m["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(vec!["tlp_amber", "tlp_red"], names);
+}
+
+#[tokio::test]
+async fn search_markings_returns_ranked_matches_with_highlighted_snippets() {
+    let app = spawn_app().await;
+
+    for body in [
+        "{\"name\": \"copyright_vendor_x\", \"definition_type\": \"statement\", \"definition\": \"Copyright 2022 Vendor X, all rights reserved.\"}",
+        "{\"name\": \"copyright_vendor_y\", \"definition_type\": \"statement\", \"definition\": \"Copyright 2022 Vendor Y, all rights reserved.\"}",
+        "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}",
+    ] {
+        app.post_markings(body).await;
+    }
+
+    let response = app
+        .api_client
+        .get(format!("{}/markings/search", &app.address))
+        .query(&[("q", "copyright vendor x")])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let results: Vec<serde_json::Value> = response.json().await.unwrap();
+    assert_eq!(1, results.len());
+    assert_eq!("copyright_vendor_x", results[0]["name"]);
+    assert!(results[0]["snippet"]
+        .as_str()
+        .unwrap()
+        .contains("<mark>Vendor</mark>"));
+}
+
+#[tokio::test]
+async fn search_markings_returns_a_400_when_the_query_is_empty() {
+    let app = spawn_app().await;
+
+    for query in ["", "   "] {
+        let response = app
+            .api_client
+            .get(format!("{}/markings/search", &app.address))
+            .query(&[("q", query)])
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        assert_eq!(400, response.status().as_u16());
+    }
+}
+
+const OBJECT_REF: &str = "indicator--8e2e2d2b-17d4-4cbf-938f-98ee46b3cd3f";
+
+#[tokio::test]
+async fn markings_are_deprecated_then_revoked_for_good() {
+    let app = spawn_app().await;
+
+    let body = "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}";
+    let created: serde_json::Value = app.post_markings(body).await.json().await.unwrap();
+    assert_eq!("active", created["status"]);
+    let url = format!(
+        "{}/markings/{}",
+        &app.address,
+        created["id"].as_str().unwrap()
+    );
+    let change = |action: &'static str| {
+        app.api_client
+            .post(format!("{}/{}", url, action))
+            .header("If-Match", "*")
+            .send()
+    };
+
+    let response = change("deprecate").await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let deprecated: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("deprecated", deprecated["status"]);
+    assert_eq!(2, deprecated["version"]);
+    assert_eq!(409, change("deprecate").await.unwrap().status().as_u16());
+
+    let response = change("revoke").await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let revoked: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("revoked", revoked["status"]);
+    assert_eq!(409, change("revoke").await.unwrap().status().as_u16());
+    assert_eq!(409, change("deprecate").await.unwrap().status().as_u16());
+}
+
+#[tokio::test]
+async fn markings_are_assigned_to_an_object_once() {
+    let app = spawn_app().await;
+
+    let body = "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}";
+    let created: serde_json::Value = app.post_markings(body).await.json().await.unwrap();
+    let url = format!(
+        "{}/markings/{}/assignments",
+        &app.address,
+        created["id"].as_str().unwrap()
+    );
+    let assign = || app.api_client.put(format!("{}/{}", url, OBJECT_REF)).send();
+
+    let response = assign().await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let first: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(OBJECT_REF, first["object_ref"]);
+    let again: serde_json::Value = assign().await.unwrap().json().await.unwrap();
+    assert_eq!(first, again);
+
+    let response = app.api_client.get(&url).send().await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let assignments: Vec<serde_json::Value> = response.json().await.unwrap();
+    assert_eq!(vec![first], assignments);
+}
+
+#[tokio::test]
+async fn invalid_assignments_are_rejected() {
+    let app = spawn_app().await;
+
+    let body = "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}";
+    let created: serde_json::Value = app.post_markings(body).await.json().await.unwrap();
+    let id = created["id"].as_str().unwrap();
+    let assign = |id: &str, object_ref: &str| {
+        app.api_client
+            .put(format!(
+                "{}/markings/{}/assignments/{}",
+                &app.address, id, object_ref
+            ))
+            .send()
+    };
+
+    let response = assign(id, "indicator--42").await.unwrap();
+    assert_eq!(400, response.status().as_u16());
+    let response = assign("7d7e9ac6-8e55-4fe4-a4f4-7a3b6f3e2a1d", OBJECT_REF)
+        .await
+        .unwrap();
+    assert_eq!(404, response.status().as_u16());
+
+    app.api_client
+        .post(format!("{}/markings/{}/revoke", &app.address, id))
+        .header("If-Match", "*")
+        .send()
+        .await
+        .unwrap();
+    let response = assign(id, OBJECT_REF).await.unwrap();
+    assert_eq!(409, response.status().as_u16());
+}
@@ -0,0 +1,53 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn metrics_are_exported_in_the_prometheus_text_format() {
+    let app = spawn_app().await;
+    app.post_markings(r#"{"name": "tlp_red", "definition_type": "tlp", "definition": "TLP Red"}"#)
+        .await;
+    app.post_markings(
+        r#"{"name": "Not Valid", "definition_type": "tlp", "definition": "TLP Red"}"#,
+    )
+    .await;
+    app.get_marking("7d7e9ac6-8e55-4fe4-a4f4-7a3b6f3e2a1d")
+        .await;
+    // Left open until the test ends.
+    let _stream = app
+        .api_client
+        .get(format!("{}/events", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    let response = app
+        .api_client
+        .get(format!("{}/metrics", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let body = response.text().await.unwrap();
+    for expected in [
+        r#"metaman_http_requests_total{method="POST",route="/markings",status="201"}"#,
+        r#"metaman_http_requests_total{method="GET",route="/markings/{id}",status="404"}"#,
+        r#"metaman_http_request_duration_seconds_bucket{method="POST",route="/markings","#,
+        r#"metaman_http_requests_total{method="GET",route="/events",status="200"}"#,
+        r#"metaman_validation_failures_total{field="name"}"#,
+        r#"metaman_markings{definition_type="tlp"} 1"#,
+        r#"metaman_db_connections{state="idle"}"#,
+    ] {
+        assert!(
+            body.contains(expected),
+            "{} is missing from:\n{}",
+            expected,
+            body
+        );
+    }
+    assert!(!body
+        .contains(r#"metaman_http_request_duration_seconds_bucket{method="GET",route="/events","#));
+}
@@ -0,0 +1,70 @@
+use crate::helpers::spawn_app_with;
+use metaman::configuration::get_configuration;
+
+#[tokio::test]
+async fn the_openapi_document_describes_every_route() {
+    let configuration = get_configuration().expect("Failed to read configuration");
+    let address = spawn_app_with(configuration).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/openapi.json", address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let document: serde_json::Value = response.json().await.unwrap();
+    assert!(document["openapi"].as_str().unwrap().starts_with("3."));
+    let routes = [
+        ("/markings", &["get", "post"][..]),
+        ("/markings/search", &["get"]),
+        ("/markings/bulk", &["post", "put"]),
+        ("/markings/{id}", &["get", "put", "patch", "delete"]),
+        ("/markings/{id}/deprecate", &["post"]),
+        ("/markings/{id}/revoke", &["post"]),
+        ("/markings/{id}/assignments", &["get"]),
+        ("/markings/{id}/assignments/{object_ref}", &["put"]),
+        ("/events", &["get"]),
+        ("/health_check", &["get"]),
+        ("/health/live", &["get"]),
+        ("/health/ready", &["get"]),
+        ("/metrics", &["get"]),
+        ("/admin/migrations", &["get"]),
+        ("/taxii2/", &["get"]),
+        ("/taxii2/api/collections/{collection}/objects/", &["get"]),
+        ("/taxii2/api/collections/{collection}/manifest/", &["get"]),
+        ("/webhooks", &["get", "post"]),
+        ("/webhooks/{id}", &["get", "delete"]),
+        ("/webhooks/{id}/deliveries", &["get"]),
+        ("/webhooks/{id}/dead_letters", &["get"]),
+    ];
+    for (path, methods) in routes {
+        for method in methods {
+            assert!(
+                document["paths"][path][method].is_object(),
+                "{} {} is not documented.",
+                method,
+                path
+            );
+        }
+    }
+    let marking = &document["components"]["schemas"]["JsonData"];
+    for field in ["name", "definition_type", "definition"] {
+        assert!(marking["properties"][field].is_object());
+    }
+}
+
+#[tokio::test]
+async fn swagger_ui_is_served_at_docs() {
+    let configuration = get_configuration().expect("Failed to read configuration");
+    let address = spawn_app_with(configuration).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/docs", address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("swagger-ui"));
+}
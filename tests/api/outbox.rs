@@ -0,0 +1,230 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use chrono::Utc;
+use metaman::configuration::{get_configuration, SinkSettings};
+use metaman::domain::{MarkingDefinition, MarkingDefinitionType, MarkingName, NewMarking};
+use metaman::repository::{PostgresMarkingRepository, Storage};
+use std::time::Duration;
+use uuid::Uuid;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const ACME: &str = r#"{"name": "acme", "definition_type": "statement", "definition": "Acme only"}"#;
+
+async fn outbox_event_types(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT event_type FROM outbox ORDER BY id")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to read the outbox.")
+        .into_iter()
+        .map(|row| row.event_type)
+        .collect()
+}
+
+#[tokio::test]
+async fn new_markings_are_saved_to_the_outbox() {
+    let app = spawn_app().await;
+
+    let response = app.post_markings(ACME).await;
+
+    let marking: serde_json::Value = response.json().await.unwrap();
+    let event = sqlx::query!("SELECT marking_id, payload FROM outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to read the outbox.");
+    assert_eq!(marking["id"], event.marking_id.to_string());
+    let payload: serde_json::Value = serde_json::from_str(&event.payload).unwrap();
+    assert_eq!("marking.created", payload["type"]);
+    assert_eq!(marking, payload["marking"]);
+}
+
+#[tokio::test]
+async fn rolled_back_writes_leave_no_event() {
+    let app = spawn_app().await;
+    app.post_markings(ACME).await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/markings/bulk", &app.address))
+        .json(&serde_json::json!([
+            { "name": "globex", "definition_type": "statement", "definition": "Globex" },
+            { "name": "acme", "definition_type": "statement", "definition": "Acme again" },
+        ]))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(422, response.status().as_u16());
+    assert_eq!(vec!["marking.created"], outbox_event_types(&app).await);
+}
+
+#[tokio::test]
+async fn sinks_record_the_last_event_they_published() {
+    let app = spawn_app().await;
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let response = app
+        .post_webhooks(serde_json::json!({
+            "url": server.uri(),
+            "secret": "a-secret-of-some-length"
+        }))
+        .await;
+    let webhook: serde_json::Value = response.json().await.unwrap();
+    let sink = format!("webhook:{}", webhook["id"].as_str().unwrap());
+
+    app.post_markings(ACME).await;
+
+    for _ in 0..100 {
+        let cursor = sqlx::query!("SELECT sequence FROM outbox_cursors WHERE sink = $1", sink)
+            .fetch_one(&app.db_pool)
+            .await
+            .expect("Failed to read the outbox cursors.");
+        let last = sqlx::query!(r#"SELECT MAX(sequence) AS sequence FROM outbox"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+        if last.sequence == Some(cursor.sequence) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("The sink of the webhook did not publish the event.");
+}
+
+#[tokio::test]
+async fn events_are_appended_to_file_sinks() {
+    let path = std::env::temp_dir().join(format!("metaman-events-{}.jsonl", Uuid::new_v4()));
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.application.outbox.enabled = true;
+    configuration.application.outbox.sinks = vec![SinkSettings::File {
+        path: path.to_string_lossy().into_owned(),
+    }];
+    configuration.application.outbox.poll_interval_ms = 10;
+    let address = spawn_app_with(configuration).await;
+
+    reqwest::Client::new()
+        .post(format!("{}/markings", address))
+        .header("Content-Type", "application/json")
+        .body(ACME)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    for _ in 0..100 {
+        let lines = std::fs::read_to_string(&path).unwrap_or_default();
+        if let Some(line) = lines.lines().next() {
+            let event: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!("marking.created", event["type"]);
+            assert_eq!("acme", event["marking"]["name"]);
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("No event was appended to {}.", path.display());
+}
+
+#[tokio::test]
+async fn events_committed_late_are_read_after_those_committed_before() {
+    let app = spawn_app().await;
+    let storage = Storage::new(PostgresMarkingRepository::new(app.db_pool.clone()));
+    let new_marking = |name: &str| NewMarking {
+        name: MarkingName::parse(name.into()).unwrap(),
+        definition_type: MarkingDefinitionType::parse("statement".into()).unwrap(),
+        definition: MarkingDefinition::parse("Acme only".into()).unwrap(),
+    };
+
+    // Saved first, committed last.
+    let mut slow = storage.markings.begin().await.unwrap();
+    let late = slow.insert_marking(&new_marking("late")).await.unwrap();
+    let early = storage
+        .markings
+        .insert_marking(&new_marking("early"))
+        .await
+        .unwrap();
+    let read = storage.outbox.list_outbox_events(0, 10).await.unwrap();
+    assert_eq!(
+        vec![early.id],
+        read.iter().map(|e| e.marking_id).collect::<Vec<_>>()
+    );
+
+    slow.commit().await.unwrap();
+    let after = storage
+        .outbox
+        .list_outbox_events(read[0].sequence, 10)
+        .await
+        .unwrap();
+    assert_eq!(
+        vec![late.id],
+        after.iter().map(|e| e.marking_id).collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn events_are_pruned_once_every_sink_published_them() {
+    let app = spawn_app().await;
+    let storage = Storage::new(PostgresMarkingRepository::new(app.db_pool.clone()));
+    for body in [
+        ACME,
+        r#"{"name": "globex", "definition_type": "statement", "definition": "Globex only"}"#,
+        r#"{"name": "initech", "definition_type": "statement", "definition": "Initech only"}"#,
+    ] {
+        app.post_markings(body).await;
+    }
+    let last = storage.outbox.last_outbox_sequence().await.unwrap();
+    storage
+        .outbox
+        .save_outbox_cursor("stdout", last - 1)
+        .await
+        .unwrap();
+    // Left behind by a deleted webhook.
+    storage
+        .outbox
+        .save_outbox_cursor(&format!("webhook:{}", Uuid::new_v4()), 0)
+        .await
+        .unwrap();
+
+    let within_retention = Utc::now() - chrono::Duration::hours(1);
+    let past_retention = Utc::now() + chrono::Duration::minutes(1);
+    assert_eq!(
+        0,
+        storage
+            .outbox
+            .prune_outbox_events(within_retention)
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        1,
+        storage
+            .outbox
+            .prune_outbox_events(past_retention)
+            .await
+            .unwrap()
+    );
+    storage
+        .outbox
+        .save_outbox_cursor("stdout", last)
+        .await
+        .unwrap();
+    assert_eq!(
+        1,
+        storage
+            .outbox
+            .prune_outbox_events(past_retention)
+            .await
+            .unwrap()
+    );
+
+    // The last event is kept, so sequences carry on after it.
+    assert_eq!(vec!["marking.created"], outbox_event_types(&app).await);
+    app.post_markings(
+        r#"{"name": "umbrella", "definition_type": "statement", "definition": "Umbrella only"}"#,
+    )
+    .await;
+    assert_eq!(
+        last + 1,
+        storage.outbox.last_outbox_sequence().await.unwrap()
+    );
+}
@@ -0,0 +1,81 @@
+use crate::helpers::spawn_app_with;
+use metaman::configuration::{get_configuration, RateLimit};
+
+async fn spawn_app_limiting_writes() -> String {
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.application.rate_limit.writes = Some(RateLimit {
+        burst: 2,
+        per_second: 0.01,
+    });
+    configuration.application.rate_limit.api_keys = vec!["partner".into(), "other-partner".into()];
+    spawn_app_with(configuration).await
+}
+
+async fn create_marking(address: &str, name: &str, api_key: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/markings", address))
+        .header("X-Api-Key", api_key)
+        .json(&serde_json::json!({
+            "name": name,
+            "definition_type": "statement",
+            "definition": "Copyright Acme"
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn clients_over_their_limit_get_a_429_with_retry_after() {
+    let address = spawn_app_limiting_writes().await;
+
+    for name in ["acme", "globex"] {
+        assert_eq!(
+            201,
+            create_marking(&address, name, "partner").await.status()
+        );
+    }
+    let response = create_marking(&address, "initech", "partner").await;
+
+    assert_eq!(429, response.status().as_u16());
+    let retry_after: u64 = response.headers()["retry-after"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after >= 1);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert!(error["error"].is_string());
+    assert!(error["request_id"].is_string());
+}
+
+#[tokio::test]
+async fn limits_apply_per_client_and_route_group() {
+    let address = spawn_app_limiting_writes().await;
+    for name in ["acme", "globex"] {
+        create_marking(&address, name, "partner").await;
+    }
+
+    let response = create_marking(&address, "initech", "other-partner").await;
+    assert_eq!(201, response.status().as_u16());
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/markings", address))
+        .header("X-Api-Key", "partner")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn unknown_api_keys_share_the_limit_of_their_address() {
+    let address = spawn_app_limiting_writes().await;
+    for (name, api_key) in [("acme", "forged-1"), ("globex", "forged-2")] {
+        create_marking(&address, name, api_key).await;
+    }
+
+    let response = create_marking(&address, "initech", "forged-3").await;
+
+    assert_eq!(429, response.status().as_u16());
+}
@@ -0,0 +1,69 @@
+use crate::helpers::spawn_app;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn an_incoming_request_id_is_echoed_back() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/markings", &app.address))
+        .header("X-Request-Id", "partner-submission-1234")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "partner-submission-1234",
+        response.headers()["X-Request-Id"]
+    );
+}
+
+#[tokio::test]
+async fn a_request_id_is_generated_when_missing_or_invalid() {
+    let app = spawn_app().await;
+
+    for header in [None, Some("")] {
+        let mut request = app.api_client.get(format!("{}/markings", &app.address));
+        if let Some(header) = header {
+            request = request.header("X-Request-Id", header);
+        }
+        let response = request.send().await.expect("Failed to execute request.");
+
+        let request_id = response.headers()["X-Request-Id"].to_str().unwrap();
+        assert!(Uuid::parse_str(request_id).is_ok());
+    }
+}
+
+#[tokio::test]
+async fn error_bodies_carry_the_request_id() {
+    let app = spawn_app().await;
+    let missing = format!("{}/markings/{}", &app.address, Uuid::new_v4());
+
+    let response = app
+        .api_client
+        .get(&missing)
+        .header("X-Request-Id", "lookup-42")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(404, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("lookup-42", body["request_id"]);
+    assert_eq!("Not Found", body["error"]);
+
+    let response = app
+        .api_client
+        .post(format!("{}/markings", &app.address))
+        .header("X-Request-Id", "submission-43")
+        .header("Content-Type", "application/json")
+        .body(r#"{"name": "tlp_red"}"#)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("submission-43", body["request_id"]);
+    assert!(body["error"].as_str().unwrap().contains("definition_type"));
+}
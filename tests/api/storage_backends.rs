@@ -0,0 +1,488 @@
+use chrono::Utc;
+use metaman::configuration::{get_configuration, DatabaseKind};
+use metaman::domain::{
+    webhook_sink_name, DeadLetter, MarkingDefinition, MarkingDefinitionType, MarkingEventType,
+    MarkingName, MarkingSource, NewMarking, NewWebhook, WebhookDelivery,
+};
+use metaman::repository::RepositoryError;
+use metaman::startup::{get_storage, run};
+use secrecy::{ExposeSecret, Secret};
+use std::net::TcpListener;
+use uuid::Uuid;
+
+async fn spawn_app_with(kind: DatabaseKind) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.database.kind = kind;
+    configuration.database.sqlite_path = std::env::temp_dir()
+        .join(format!("metaman-{}.db", Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned();
+
+    let storage = get_storage(&configuration.database)
+        .await
+        .expect("Failed to open the marking storage.");
+    let server =
+        run(listener, storage, &configuration.application).expect("Failed to bind address");
+    #[allow(clippy::let_underscore_future)]
+    let _ = tokio::spawn(server);
+
+    format!("http://127.0.0.1:{}", port)
+}
+
+async fn markings_can_be_managed_with(kind: DatabaseKind) {
+    let address = spawn_app_with(kind).await;
+    let client = reqwest::Client::new();
+    let body = serde_json::json!({
+        "name": "copyright_vendor_x",
+        "definition_type": "statement",
+        "definition": "Copyright 2022 Vendor X, all rights reserved."
+    });
+
+    let response = client
+        .post(format!("{}/markings", address))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(201, response.status().as_u16());
+    let created: serde_json::Value = response.json().await.unwrap();
+
+    let response = client
+        .get(format!(
+            "{}/markings/{}",
+            address,
+            created["id"].as_str().unwrap()
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+
+    let response = client
+        .post(format!("{}/markings", address))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(409, response.status().as_u16());
+
+    let response = client
+        .get(format!("{}/markings/search", address))
+        .query(&[("q", "vendor x")])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let results: Vec<serde_json::Value> = response.json().await.unwrap();
+    assert_eq!(1, results.len());
+    assert!(results[0]["snippet"]
+        .as_str()
+        .unwrap()
+        .contains("<mark>Vendor</mark>"));
+}
+
+#[tokio::test]
+async fn markings_can_be_managed_in_memory() {
+    markings_can_be_managed_with(DatabaseKind::Memory).await;
+}
+
+#[tokio::test]
+async fn markings_can_be_managed_in_sqlite() {
+    markings_can_be_managed_with(DatabaseKind::Sqlite).await;
+}
+
+async fn markings_can_be_mirrored_with(kind: DatabaseKind) {
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.database.kind = kind;
+    configuration.database.sqlite_path = std::env::temp_dir()
+        .join(format!("metaman-{}.db", Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned();
+    let storage = get_storage(&configuration.database)
+        .await
+        .expect("Failed to open the marking storage.");
+    storage.schema.migrate().await.unwrap();
+    let new_marking = |definition: &str| NewMarking {
+        name: MarkingName::parse("acme".into()).unwrap(),
+        definition_type: MarkingDefinitionType::parse("statement".into()).unwrap(),
+        definition: MarkingDefinition::parse(definition.into()).unwrap(),
+    };
+    let source = MarkingSource {
+        server: "https://taxii.example.com/api/".into(),
+        collection: "marking-definitions".into(),
+        fetched_at: Utc::now(),
+    };
+    let id = Uuid::new_v4();
+    let mirror = |definition: &'static str| {
+        let storage = storage.clone();
+        let source = source.clone();
+        async move {
+            storage
+                .markings
+                .mirror_marking(id, &new_marking(definition), &source)
+                .await
+                .unwrap()
+        }
+    };
+
+    let created = mirror("Copyright Acme").await;
+    assert_eq!(id, created.id);
+    assert_eq!(1, created.version);
+    assert_eq!(Some(source.server.clone()), created.source_server);
+    assert_eq!(1, mirror("Copyright Acme").await.version);
+    let updated = mirror("Copyright Acme Corporation").await;
+    assert_eq!(2, updated.version);
+    assert_eq!("Copyright Acme Corporation", updated.definition);
+
+    // Mirroring an unchanged copy is not an event.
+    let events = storage.outbox.list_outbox_events(0, 10).await.unwrap();
+    let types: Vec<_> = events.iter().map(|e| e.event_type.as_str()).collect();
+    assert_eq!(vec!["marking.created", "marking.updated"], types);
+
+    // Only copies from the same source are replaced.
+    let other_source = MarkingSource {
+        collection: "other-collection".into(),
+        ..source.clone()
+    };
+    let result = storage
+        .markings
+        .mirror_marking(id, &new_marking("Theirs"), &other_source)
+        .await;
+    assert!(matches!(result, Err(RepositoryError::ForeignMarking(_))));
+    let local = storage
+        .markings
+        .insert_marking(&NewMarking {
+            name: MarkingName::parse("ours".into()).unwrap(),
+            ..new_marking("Ours")
+        })
+        .await
+        .unwrap();
+    let result = storage
+        .markings
+        .mirror_marking(local.id, &new_marking("Theirs"), &source)
+        .await;
+    assert!(matches!(result, Err(RepositoryError::ForeignMarking(_))));
+    assert_eq!(
+        "Ours",
+        storage
+            .markings
+            .get_marking(local.id)
+            .await
+            .unwrap()
+            .definition
+    );
+
+    // Pulls resume where the previous one of the same collection stopped.
+    let cursor = |collection: &'static str| {
+        let storage = storage.clone();
+        let server = source.server.clone();
+        async move {
+            storage
+                .markings
+                .get_sync_cursor(&server, collection)
+                .await
+                .unwrap()
+        }
+    };
+    assert_eq!(None, cursor("marking-definitions").await);
+    for added_last in ["2022-03-01T10:00:00.000Z", "2022-03-02T10:00:00.000Z"] {
+        storage
+            .markings
+            .save_sync_cursor(&source.server, "marking-definitions", added_last)
+            .await
+            .unwrap();
+    }
+    assert_eq!(
+        Some("2022-03-02T10:00:00.000Z".to_owned()),
+        cursor("marking-definitions").await
+    );
+    assert_eq!(None, cursor("other-collection").await);
+}
+
+#[tokio::test]
+async fn markings_can_be_mirrored_in_memory() {
+    markings_can_be_mirrored_with(DatabaseKind::Memory).await;
+}
+
+#[tokio::test]
+async fn markings_can_be_mirrored_in_sqlite() {
+    markings_can_be_mirrored_with(DatabaseKind::Sqlite).await;
+}
+
+async fn webhooks_can_be_managed_with(kind: DatabaseKind) {
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.database.kind = kind;
+    configuration.database.sqlite_path = std::env::temp_dir()
+        .join(format!("metaman-{}.db", Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned();
+    let storage = get_storage(&configuration.database)
+        .await
+        .expect("Failed to open the marking storage.");
+    storage.schema.migrate().await.unwrap();
+
+    let webhook = storage
+        .webhooks
+        .insert_webhook(&NewWebhook {
+            url: "https://tip.example.com/hooks".into(),
+            events: vec![MarkingEventType::Updated, MarkingEventType::Deleted],
+            secret: Secret::new("0123456789abcdef".into()),
+        })
+        .await
+        .unwrap();
+    let fetched = storage.webhooks.get_webhook(webhook.id).await.unwrap();
+    assert_eq!(webhook.events, fetched.events);
+    assert_eq!("0123456789abcdef", fetched.secret.expose_secret());
+    assert_eq!(1, storage.webhooks.list_webhooks().await.unwrap().len());
+
+    let event_id = Uuid::new_v4();
+    for attempt in 1..=2 {
+        storage
+            .webhooks
+            .record_delivery(&WebhookDelivery {
+                id: Uuid::new_v4(),
+                webhook_id: webhook.id,
+                event_id,
+                event_type: "marking.updated".into(),
+                attempt,
+                status_code: Some(500),
+                error: Some("The receiver answered 500.".into()),
+                delivered: false,
+                attempted_at: Utc::now(),
+            })
+            .await
+            .unwrap();
+    }
+    storage
+        .webhooks
+        .insert_dead_letter(&DeadLetter {
+            id: Uuid::new_v4(),
+            webhook_id: webhook.id,
+            event_id,
+            event_type: "marking.updated".into(),
+            payload: "{\"type\":\"marking.updated\"}".into(),
+            attempts: 2,
+            last_error: Some("The receiver answered 500.".into()),
+            created_at: Utc::now(),
+        })
+        .await
+        .unwrap();
+
+    let deliveries = storage
+        .webhooks
+        .list_deliveries(webhook.id, 1)
+        .await
+        .unwrap();
+    assert_eq!(1, deliveries.len());
+    assert_eq!(2, deliveries[0].attempt);
+    let dead_letters = storage
+        .webhooks
+        .list_dead_letters(webhook.id)
+        .await
+        .unwrap();
+    assert_eq!(1, dead_letters.len());
+    assert_eq!(event_id, dead_letters[0].event_id);
+
+    storage.webhooks.delete_webhook(webhook.id).await.unwrap();
+    assert!(storage
+        .webhooks
+        .list_deliveries(webhook.id, 10)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(storage
+        .webhooks
+        .list_dead_letters(webhook.id)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(storage.webhooks.delete_webhook(webhook.id).await.is_err());
+}
+
+#[tokio::test]
+async fn webhooks_can_be_managed_in_memory() {
+    webhooks_can_be_managed_with(DatabaseKind::Memory).await;
+}
+
+#[tokio::test]
+async fn webhooks_can_be_managed_in_sqlite() {
+    webhooks_can_be_managed_with(DatabaseKind::Sqlite).await;
+}
+
+async fn marking_writes_are_saved_to_the_outbox_with(kind: DatabaseKind) {
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.database.kind = kind;
+    configuration.database.sqlite_path = std::env::temp_dir()
+        .join(format!("metaman-{}.db", Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned();
+    let storage = get_storage(&configuration.database)
+        .await
+        .expect("Failed to open the marking storage.");
+    storage.schema.migrate().await.unwrap();
+    let new_marking = |name: &str| NewMarking {
+        name: MarkingName::parse(name.into()).unwrap(),
+        definition_type: MarkingDefinitionType::parse("statement".into()).unwrap(),
+        definition: MarkingDefinition::parse("Acme only".into()).unwrap(),
+    };
+
+    let marking = storage
+        .markings
+        .insert_marking(&new_marking("acme"))
+        .await
+        .unwrap();
+    storage
+        .markings
+        .update_marking(marking.id, &new_marking("acme_corp"), None)
+        .await
+        .unwrap();
+    let mut transaction = storage.markings.begin().await.unwrap();
+    transaction
+        .insert_marking(&new_marking("rolled_back"))
+        .await
+        .unwrap();
+    transaction.rollback().await.unwrap();
+    storage
+        .markings
+        .delete_marking(marking.id, None)
+        .await
+        .unwrap();
+
+    let events = storage.outbox.list_outbox_events(0, 10).await.unwrap();
+    let types: Vec<_> = events.iter().map(|e| e.event_type.as_str()).collect();
+    assert_eq!(
+        vec!["marking.created", "marking.updated", "marking.deleted"],
+        types
+    );
+    assert!(events.windows(2).all(|w| w[0].sequence < w[1].sequence));
+    assert!(events.iter().all(|e| e.marking_id == marking.id));
+    let payload: serde_json::Value = serde_json::from_str(&events[2].payload).unwrap();
+    assert_eq!(events[2].event_id.to_string(), payload["id"]);
+    assert_eq!("acme_corp", payload["marking"]["name"]);
+    let after_first = storage
+        .outbox
+        .list_outbox_events(events[0].sequence, 10)
+        .await
+        .unwrap();
+    assert_eq!(2, after_first.len());
+
+    assert_eq!(0, storage.outbox.get_outbox_cursor("stdout").await.unwrap());
+    // A replica lagging behind saves an older cursor last.
+    for sequence in [events[0].sequence, events[2].sequence, events[1].sequence] {
+        storage
+            .outbox
+            .save_outbox_cursor("stdout", sequence)
+            .await
+            .unwrap();
+    }
+    assert_eq!(
+        events[2].sequence,
+        storage.outbox.get_outbox_cursor("stdout").await.unwrap()
+    );
+}
+
+#[tokio::test]
+async fn marking_writes_are_saved_to_the_outbox_in_memory() {
+    marking_writes_are_saved_to_the_outbox_with(DatabaseKind::Memory).await;
+}
+
+#[tokio::test]
+async fn marking_writes_are_saved_to_the_outbox_in_sqlite() {
+    marking_writes_are_saved_to_the_outbox_with(DatabaseKind::Sqlite).await;
+}
+
+async fn published_events_are_pruned_with(kind: DatabaseKind) {
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.database.kind = kind;
+    configuration.database.sqlite_path = std::env::temp_dir()
+        .join(format!("metaman-{}.db", Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned();
+    let storage = get_storage(&configuration.database)
+        .await
+        .expect("Failed to open the marking storage.");
+    storage.schema.migrate().await.unwrap();
+    let new_marking = |name: &str| NewMarking {
+        name: MarkingName::parse(name.into()).unwrap(),
+        definition_type: MarkingDefinitionType::parse("statement".into()).unwrap(),
+        definition: MarkingDefinition::parse("Acme only".into()).unwrap(),
+    };
+    let sequences = |events: Vec<metaman::domain::OutboxEvent>| {
+        events.iter().map(|e| e.sequence).collect::<Vec<_>>()
+    };
+    for name in ["acme", "globex", "initech"] {
+        storage
+            .markings
+            .insert_marking(&new_marking(name))
+            .await
+            .unwrap();
+    }
+    let events = storage.outbox.list_outbox_events(0, 10).await.unwrap();
+    let first = events[0].sequence;
+    storage
+        .outbox
+        .save_outbox_cursor("stdout", first + 1)
+        .await
+        .unwrap();
+    // Left behind by a deleted webhook.
+    storage
+        .outbox
+        .save_outbox_cursor(&webhook_sink_name(Uuid::new_v4()), 0)
+        .await
+        .unwrap();
+
+    let within_retention = Utc::now() - chrono::Duration::hours(1);
+    assert_eq!(
+        0,
+        storage
+            .outbox
+            .prune_outbox_events(within_retention)
+            .await
+            .unwrap()
+    );
+    let past_retention = Utc::now() + chrono::Duration::minutes(1);
+    assert_eq!(
+        1,
+        storage
+            .outbox
+            .prune_outbox_events(past_retention)
+            .await
+            .unwrap()
+    );
+    let events = storage.outbox.list_outbox_events(0, 10).await.unwrap();
+    assert_eq!(vec![first + 1, first + 2], sequences(events));
+
+    storage
+        .outbox
+        .save_outbox_cursor("stdout", first + 2)
+        .await
+        .unwrap();
+    storage
+        .outbox
+        .prune_outbox_events(past_retention)
+        .await
+        .unwrap();
+    let events = storage.outbox.list_outbox_events(0, 10).await.unwrap();
+    assert_eq!(vec![first + 2], sequences(events));
+    storage
+        .markings
+        .insert_marking(&new_marking("umbrella"))
+        .await
+        .unwrap();
+    assert_eq!(
+        first + 3,
+        storage.outbox.last_outbox_sequence().await.unwrap()
+    );
+}
+
+#[tokio::test]
+async fn published_events_are_pruned_in_memory() {
+    published_events_are_pruned_with(DatabaseKind::Memory).await;
+}
+
+#[tokio::test]
+async fn published_events_are_pruned_in_sqlite() {
+    published_events_are_pruned_with(DatabaseKind::Sqlite).await;
+}
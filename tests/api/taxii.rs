@@ -0,0 +1,299 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use metaman::configuration::get_configuration;
+use metaman::domain::{MarkingDefinition, MarkingDefinitionType, MarkingName, NewMarking};
+use metaman::repository::{PostgresMarkingRepository, Storage};
+
+const TAXII: &str = "application/taxii+json;version=2.1";
+const COLLECTION: &str = "/taxii2/api/collections/marking-definitions";
+
+async fn spawn_app_with_markings(names: &[&str]) -> String {
+    let configuration = get_configuration().expect("Failed to read configuration");
+    let address = spawn_app_with(configuration).await;
+    for name in names {
+        let response = reqwest::Client::new()
+            .post(format!("{}/markings", address))
+            .json(&serde_json::json!({
+                "name": name,
+                "definition_type": "statement",
+                "definition": format!("Copyright {}", name),
+            }))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(201, response.status().as_u16());
+    }
+    address
+}
+
+async fn get(address: &str, path: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}{}", address, path))
+        .header("Accept", TAXII)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn discovery_points_to_the_api_root_and_its_collection() {
+    let address = spawn_app_with_markings(&[]).await;
+
+    let response = get(&address, "/taxii2/").await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(TAXII, response.headers()["content-type"]);
+    let discovery: serde_json::Value = response.json().await.unwrap();
+    let api_root = discovery["api_roots"][0].as_str().unwrap();
+    assert_eq!(format!("{}/taxii2/api/", address), api_root);
+
+    let api_root: serde_json::Value = get(&address, "/taxii2/api/").await.json().await.unwrap();
+    assert_eq!(serde_json::json!([TAXII]), api_root["versions"]);
+
+    let collections: serde_json::Value = get(&address, "/taxii2/api/collections/")
+        .await
+        .json()
+        .await
+        .unwrap();
+    let collection = &collections["collections"][0];
+    assert_eq!("marking-definitions", collection["alias"]);
+    assert_eq!(true, collection["can_read"]);
+    assert_eq!(false, collection["can_write"]);
+
+    let id = collection["id"].as_str().unwrap();
+    let response = get(&address, &format!("/taxii2/api/collections/{}/", id)).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn markings_are_served_as_stix_marking_definitions() {
+    let address = spawn_app_with_markings(&["acme"]).await;
+
+    let response = get(&address, &format!("{}/objects/", COLLECTION)).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(TAXII, response.headers()["content-type"]);
+    assert!(response.headers().contains_key("x-taxii-date-added-first"));
+    assert!(response.headers().contains_key("x-taxii-date-added-last"));
+    let envelope: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(false, envelope["more"]);
+    let object = &envelope["objects"][0];
+    assert_eq!("marking-definition", object["type"]);
+    assert_eq!("2.1", object["spec_version"]);
+    assert_eq!("statement", object["definition_type"]);
+    assert_eq!("Copyright acme", object["definition"]["statement"]);
+
+    let id = object["id"].as_str().unwrap();
+    let response = get(&address, &format!("{}/objects/{}/", COLLECTION, id)).await;
+    assert_eq!(200, response.status().as_u16());
+    let versions: serde_json::Value = get(
+        &address,
+        &format!("{}/objects/{}/versions/", COLLECTION, id),
+    )
+    .await
+    .json()
+    .await
+    .unwrap();
+    assert_eq!(1, versions["versions"].as_array().unwrap().len());
+}
+
+#[tokio::test]
+async fn edited_markings_keep_their_id_and_deleted_ones_are_removed() {
+    let address = spawn_app_with_markings(&["acme"]).await;
+    let client = reqwest::Client::new();
+    let markings: serde_json::Value = client
+        .get(format!("{}/markings", address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = markings[0]["id"].as_str().unwrap().to_owned();
+    let object_path = format!("{}/objects/marking-definition--{}/", COLLECTION, id);
+    let etag = |response: &reqwest::Response| response.headers()["etag"].clone();
+    let created: serde_json::Value = get(&address, &object_path).await.json().await.unwrap();
+
+    let response = client
+        .get(format!("{}/markings/{}", address, id))
+        .send()
+        .await
+        .unwrap();
+    let response = client
+        .put(format!("{}/markings/{}", address, id))
+        .header("If-Match", etag(&response))
+        .json(&serde_json::json!({
+            "name": "acme",
+            "definition_type": "statement",
+            "definition": "Copyright Acme Corporation",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let edited: serde_json::Value = get(&address, &object_path).await.json().await.unwrap();
+    assert_eq!(created["objects"][0]["id"], edited["objects"][0]["id"]);
+    assert_eq!(
+        "Copyright Acme Corporation",
+        edited["objects"][0]["definition"]["statement"]
+    );
+
+    let response = client
+        .delete(format!("{}/markings/{}", address, id))
+        .header("If-Match", etag(&response))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(204, response.status().as_u16());
+    assert_eq!(404, get(&address, &object_path).await.status().as_u16());
+}
+
+#[tokio::test]
+async fn objects_are_paged_with_next() {
+    let address = spawn_app_with_markings(&["a", "b", "c"]).await;
+    let mut ids = Vec::new();
+    let mut path = format!("{}/objects/?limit=2", COLLECTION);
+
+    loop {
+        let envelope: serde_json::Value = get(&address, &path).await.json().await.unwrap();
+        for object in envelope["objects"].as_array().unwrap() {
+            ids.push(object["id"].as_str().unwrap().to_string());
+        }
+        match envelope["next"].as_str() {
+            Some(next) => path = format!("{}/objects/?limit=2&next={}", COLLECTION, next),
+            None => break,
+        }
+    }
+
+    ids.dedup();
+    assert_eq!(3, ids.len());
+}
+
+#[tokio::test]
+async fn objects_are_filtered_by_id_type_and_date_added() {
+    let address = spawn_app_with_markings(&["a", "b"]).await;
+    let manifest: serde_json::Value = get(&address, &format!("{}/manifest/", COLLECTION))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let records = manifest["objects"].as_array().unwrap();
+    assert_eq!(2, records.len());
+    let first = &records[0];
+    let last = &records[1];
+
+    let filtered = |query: String| {
+        let address = address.clone();
+        async move {
+            let envelope: serde_json::Value =
+                get(&address, &format!("{}/objects/?{}", COLLECTION, query))
+                    .await
+                    .json()
+                    .await
+                    .unwrap();
+            envelope["objects"]
+                .as_array()
+                .map_or(0, |objects| objects.len())
+        }
+    };
+    assert_eq!(
+        1,
+        filtered(format!("match[id]={}", first["id"].as_str().unwrap())).await
+    );
+    assert_eq!(2, filtered("match[type]=marking-definition".into()).await);
+    assert_eq!(0, filtered("match[type]=indicator".into()).await);
+    assert_eq!(
+        0,
+        filtered(format!(
+            "added_after={}",
+            last["date_added"].as_str().unwrap()
+        ))
+        .await
+    );
+}
+
+#[tokio::test]
+async fn markings_committed_late_are_added_after_those_served_before() {
+    let app = spawn_app().await;
+    let storage = Storage::new(PostgresMarkingRepository::new(app.db_pool.clone()));
+
+    // Written first, committed last.
+    let mut slow = storage.markings.begin().await.unwrap();
+    let late = slow
+        .insert_marking(&NewMarking {
+            name: MarkingName::parse("late".into()).unwrap(),
+            definition_type: MarkingDefinitionType::parse("statement".into()).unwrap(),
+            definition: MarkingDefinition::parse("Copyright late".into()).unwrap(),
+        })
+        .await
+        .unwrap();
+    app.post_markings(
+        r#"{"name": "early", "definition_type": "statement", "definition": "Copyright early"}"#,
+    )
+    .await;
+    let response = get(&app.address, &format!("{}/objects/", COLLECTION)).await;
+    let last = response.headers()["X-TAXII-Date-Added-Last"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let envelope: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(1, envelope["objects"].as_array().unwrap().len());
+    slow.commit().await.unwrap();
+
+    let envelope: serde_json::Value = get(
+        &app.address,
+        &format!("{}/objects/?added_after={}", COLLECTION, last),
+    )
+    .await
+    .json()
+    .await
+    .unwrap();
+    let objects = envelope["objects"].as_array().unwrap();
+    assert_eq!(1, objects.len());
+    assert_eq!(format!("marking-definition--{}", late.id), objects[0]["id"]);
+}
+
+#[tokio::test]
+async fn unknown_collections_and_objects_are_taxii_errors() {
+    let address = spawn_app_with_markings(&[]).await;
+
+    for path in [
+        "/taxii2/api/collections/unknown/".to_string(),
+        format!(
+            "{}/objects/marking-definition--{}/",
+            COLLECTION,
+            uuid::Uuid::new_v4()
+        ),
+    ] {
+        let response = get(&address, &path).await;
+        assert_eq!(404, response.status().as_u16(), "{}", path);
+        assert_eq!(TAXII, response.headers()["content-type"]);
+        let error: serde_json::Value = response.json().await.unwrap();
+        assert_eq!("404", error["http_status"]);
+    }
+}
+
+#[tokio::test]
+async fn invalid_filters_are_rejected() {
+    let address = spawn_app_with_markings(&[]).await;
+
+    let response = get(
+        &address,
+        &format!("{}/objects/?added_after=yesterday", COLLECTION),
+    )
+    .await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn clients_must_accept_taxii() {
+    let address = spawn_app_with_markings(&[]).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/taxii2/", address))
+        .header("Accept", "text/html")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(406, response.status().as_u16());
+}
@@ -0,0 +1,224 @@
+use crate::helpers::{spawn_app, TestApp};
+use metaman::configuration::TaxiiSourceSettings;
+use metaman::repository::{MarkingRepository, PostgresMarkingRepository};
+use metaman::taxii_sync::TaxiiSource;
+use secrecy::Secret;
+use uuid::Uuid;
+use wiremock::matchers::{basic_auth, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const COLLECTION: &str = "91a7b528-80eb-42ed-a74d-c6fbd5a26116";
+const OBJECTS: &str = "/api/collections/91a7b528-80eb-42ed-a74d-c6fbd5a26116/objects/";
+
+fn source(server: &MockServer) -> TaxiiSource {
+    TaxiiSource::new(TaxiiSourceSettings {
+        api_root: format!("{}/api/", server.uri()),
+        collection: COLLECTION.into(),
+        username: Some("metaman".into()),
+        password: Some(Secret::new("secret".into())),
+        interval: 60,
+    })
+    .unwrap()
+}
+
+fn statement(id: Uuid, name: &str, statement: &str) -> serde_json::Value {
+    serde_json::json!({
+        "type": "marking-definition",
+        "spec_version": "2.1",
+        "id": format!("marking-definition--{}", id),
+        "created": "2022-03-01T10:00:00.000Z",
+        "name": name,
+        "definition_type": "statement",
+        "definition": { "statement": statement }
+    })
+}
+
+fn envelope(objects: Vec<serde_json::Value>, next: Option<&str>) -> ResponseTemplate {
+    ResponseTemplate::new(200)
+        .insert_header("Content-Type", "application/taxii+json;version=2.1")
+        .insert_header("X-TAXII-Date-Added-Last", "2022-03-01T10:00:00.000Z")
+        .set_body_json(serde_json::json!({
+            "more": next.is_some(),
+            "next": next,
+            "objects": objects
+        }))
+}
+
+async fn get_marking(app: &TestApp, id: Uuid) -> serde_json::Value {
+    let response = app.get_marking(&id.to_string()).await;
+    assert_eq!(200, response.status().as_u16());
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn marking_definitions_are_mirrored_with_their_source() {
+    let app = spawn_app().await;
+    let server = MockServer::start().await;
+    let id = Uuid::new_v4();
+    Mock::given(method("GET"))
+        .and(path(OBJECTS))
+        .and(basic_auth("metaman", "secret"))
+        .and(query_param("match[type]", "marking-definition"))
+        .respond_with(envelope(
+            vec![
+                statement(id, "Copyright Acme", "Copyright Acme"),
+                serde_json::json!({ "type": "indicator", "id": "indicator--1" }),
+            ],
+            None,
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let repository = PostgresMarkingRepository::new(app.db_pool.clone());
+
+    let report = source(&server).sync(&repository).await.unwrap();
+
+    assert_eq!(1, report.mirrored);
+    assert_eq!(1, report.skipped);
+    assert_eq!(
+        Some("2022-03-01T10:00:00.000Z"),
+        report.added_last.as_deref()
+    );
+    let marking = get_marking(&app, id).await;
+    assert_eq!("copyright_acme", marking["name"]);
+    assert_eq!("Copyright Acme", marking["definition"]);
+    assert_eq!(format!("{}/api/", server.uri()), marking["source_server"]);
+    assert_eq!(COLLECTION, marking["source_collection"]);
+    assert!(marking["fetched_at"].is_string());
+}
+
+#[tokio::test]
+async fn pulling_again_resumes_from_the_saved_cursor_and_updates_changed_markings_only() {
+    let app = spawn_app().await;
+    let server = MockServer::start().await;
+    let (changed, unchanged) = (Uuid::new_v4(), Uuid::new_v4());
+    let repository = PostgresMarkingRepository::new(app.db_pool.clone());
+    let first = Mock::given(path(OBJECTS))
+        .respond_with(envelope(
+            vec![
+                statement(changed, "acme", "Copyright Acme"),
+                statement(unchanged, "globex", "Copyright Globex"),
+            ],
+            None,
+        ))
+        .expect(1)
+        .mount_as_scoped(&server)
+        .await;
+    source(&server).sync(&repository).await.unwrap();
+    drop(first);
+    let cursor = repository
+        .get_sync_cursor(&format!("{}/api/", server.uri()), COLLECTION)
+        .await
+        .unwrap();
+    assert_eq!(Some("2022-03-01T10:00:00.000Z"), cursor.as_deref());
+
+    Mock::given(path(OBJECTS))
+        .and(query_param("added_after", "2022-03-01T10:00:00.000Z"))
+        .respond_with(envelope(
+            vec![
+                statement(changed, "acme", "Copyright Acme Corporation"),
+                statement(unchanged, "globex", "Copyright Globex"),
+            ],
+            None,
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let report = source(&server).sync(&repository).await.unwrap();
+
+    assert_eq!(2, report.mirrored);
+    let marking = get_marking(&app, changed).await;
+    assert_eq!("Copyright Acme Corporation", marking["definition"]);
+    assert_eq!(2, marking["version"]);
+    assert_eq!(1, get_marking(&app, unchanged).await["version"]);
+}
+
+#[tokio::test]
+async fn every_page_of_a_collection_is_pulled() {
+    let app = spawn_app().await;
+    let server = MockServer::start().await;
+    let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+    Mock::given(path(OBJECTS))
+        .and(query_param("next", "page_2"))
+        .respond_with(envelope(vec![statement(second, "globex", "Globex")], None))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(path(OBJECTS))
+        .respond_with(envelope(
+            vec![statement(first, "acme", "Acme")],
+            Some("page_2"),
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let repository = PostgresMarkingRepository::new(app.db_pool.clone());
+
+    let report = source(&server).sync(&repository).await.unwrap();
+
+    assert_eq!(2, report.mirrored);
+    get_marking(&app, first).await;
+    get_marking(&app, second).await;
+}
+
+#[tokio::test]
+async fn a_marking_definition_with_a_taken_name_is_skipped() {
+    let app = spawn_app().await;
+    let server = MockServer::start().await;
+    app.post_markings(
+        "{\"name\": \"acme\", \"definition_type\": \"statement\", \"definition\": \"Ours\"}",
+    )
+    .await;
+    Mock::given(path(OBJECTS))
+        .respond_with(envelope(
+            vec![statement(Uuid::new_v4(), "acme", "Theirs")],
+            None,
+        ))
+        .mount(&server)
+        .await;
+    let repository = PostgresMarkingRepository::new(app.db_pool.clone());
+
+    let report = source(&server).sync(&repository).await.unwrap();
+
+    assert_eq!(0, report.mirrored);
+    assert_eq!(1, report.skipped);
+}
+
+#[tokio::test]
+async fn a_marking_definition_with_the_id_of_a_local_marking_is_skipped() {
+    let app = spawn_app().await;
+    let server = MockServer::start().await;
+    let response = app
+        .post_markings(
+            "{\"name\": \"acme\", \"definition_type\": \"statement\", \"definition\": \"Ours\"}",
+        )
+        .await;
+    let local: serde_json::Value = response.json().await.unwrap();
+    let id = Uuid::parse_str(local["id"].as_str().unwrap()).unwrap();
+    Mock::given(path(OBJECTS))
+        .respond_with(envelope(vec![statement(id, "acme", "Theirs")], None))
+        .mount(&server)
+        .await;
+    let repository = PostgresMarkingRepository::new(app.db_pool.clone());
+
+    let report = source(&server).sync(&repository).await.unwrap();
+
+    assert_eq!(0, report.mirrored);
+    assert_eq!(1, report.skipped);
+    let marking = get_marking(&app, id).await;
+    assert_eq!("Ours", marking["definition"]);
+    assert_eq!(serde_json::Value::Null, marking["source_server"]);
+}
+
+#[tokio::test]
+async fn a_failing_server_is_reported() {
+    let app = spawn_app().await;
+    let server = MockServer::start().await;
+    Mock::given(path(OBJECTS))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+    let repository = PostgresMarkingRepository::new(app.db_pool.clone());
+
+    assert!(source(&server).sync(&repository).await.is_err());
+}
@@ -0,0 +1,208 @@
+use metaman::configuration::{get_configuration, ClientAuthSettings, DatabaseKind, TlsSettings};
+use metaman::startup::{get_storage, run};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, SanType,
+};
+use std::net::{IpAddr, Ipv4Addr, TcpListener};
+use std::path::PathBuf;
+use std::time::Duration;
+use uuid::Uuid;
+
+fn certificate_authority() -> Certificate {
+    let mut params = CertificateParams::new(vec![]);
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.distinguished_name = distinguished_name(&format!("Authority {}", Uuid::new_v4()));
+    Certificate::from_params(params).unwrap()
+}
+
+fn distinguished_name(common_name: &str) -> DistinguishedName {
+    let mut name = DistinguishedName::new();
+    name.push(DnType::CommonName, common_name);
+    name
+}
+
+/// Returns the PEM certificate and private key of a leaf signed by `issuer`.
+fn issue(issuer: &Certificate, purpose: ExtendedKeyUsagePurpose) -> (String, String) {
+    let mut params = CertificateParams::new(vec!["localhost".into()]);
+    params
+        .subject_alt_names
+        .push(SanType::IpAddress(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+    params.extended_key_usages = vec![purpose];
+    params.distinguished_name = distinguished_name("localhost");
+    let certificate = Certificate::from_params(params).unwrap();
+    (
+        certificate.serialize_pem_with_signer(issuer).unwrap(),
+        certificate.serialize_private_key_pem(),
+    )
+}
+
+fn temporary_file(contents: &str) -> String {
+    let path: PathBuf = std::env::temp_dir().join(format!("metaman-{}.pem", Uuid::new_v4()));
+    std::fs::write(&path, contents).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+async fn spawn_https_app(tls: TlsSettings) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let address = format!(
+        "https://127.0.0.1:{}",
+        listener.local_addr().unwrap().port()
+    );
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.database.kind = DatabaseKind::Memory;
+    configuration.application.tls = Some(tls);
+    let storage = get_storage(&configuration.database)
+        .await
+        .expect("Failed to open the marking storage.");
+    let server =
+        run(listener, storage, &configuration.application).expect("Failed to bind address");
+    #[allow(clippy::let_underscore_future)]
+    let _ = tokio::spawn(server);
+    address
+}
+
+fn client_trusting(authority: &Certificate) -> reqwest::ClientBuilder {
+    let root =
+        reqwest::Certificate::from_pem(authority.serialize_pem().unwrap().as_bytes()).unwrap();
+    reqwest::Client::builder()
+        .add_root_certificate(root)
+        .tls_built_in_root_certs(false)
+}
+
+#[tokio::test]
+async fn the_api_is_served_over_https_when_tls_is_configured() {
+    let authority = certificate_authority();
+    let (certificate, private_key) = issue(&authority, ExtendedKeyUsagePurpose::ServerAuth);
+    let address = spawn_https_app(TlsSettings {
+        certificate: temporary_file(&certificate),
+        private_key: temporary_file(&private_key),
+        reload_interval: 60,
+        client_auth: None,
+    })
+    .await;
+
+    let response = client_trusting(&authority)
+        .build()
+        .unwrap()
+        .get(format!("{}/health_check", address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn clients_must_present_a_trusted_certificate_when_client_auth_is_configured() {
+    let authority = certificate_authority();
+    let (certificate, private_key) = issue(&authority, ExtendedKeyUsagePurpose::ServerAuth);
+    let client_authority = certificate_authority();
+    let address = spawn_https_app(TlsSettings {
+        certificate: temporary_file(&certificate),
+        private_key: temporary_file(&private_key),
+        reload_interval: 60,
+        client_auth: Some(ClientAuthSettings {
+            ca_certificate: temporary_file(&client_authority.serialize_pem().unwrap()),
+            identities: Default::default(),
+        }),
+    })
+    .await;
+    let health_check = |client: reqwest::Client| {
+        let address = address.clone();
+        async move { client.get(format!("{}/health_check", address)).send().await }
+    };
+
+    let anonymous = client_trusting(&authority).build().unwrap();
+    assert!(health_check(anonymous).await.is_err());
+
+    let (certificate, private_key) = issue(&authority, ExtendedKeyUsagePurpose::ClientAuth);
+    let identity =
+        reqwest::Identity::from_pkcs8_pem(certificate.as_bytes(), private_key.as_bytes()).unwrap();
+    let untrusted = client_trusting(&authority)
+        .identity(identity)
+        .build()
+        .unwrap();
+    assert!(health_check(untrusted).await.is_err());
+
+    let (certificate, private_key) = issue(&client_authority, ExtendedKeyUsagePurpose::ClientAuth);
+    let identity =
+        reqwest::Identity::from_pkcs8_pem(certificate.as_bytes(), private_key.as_bytes()).unwrap();
+    let trusted = client_trusting(&authority)
+        .identity(identity)
+        .build()
+        .unwrap();
+    let response = health_check(trusted)
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn operator_routes_are_served_to_clients_with_a_certificate() {
+    let authority = certificate_authority();
+    let (certificate, private_key) = issue(&authority, ExtendedKeyUsagePurpose::ServerAuth);
+    let client_authority = certificate_authority();
+    let address = spawn_https_app(TlsSettings {
+        certificate: temporary_file(&certificate),
+        private_key: temporary_file(&private_key),
+        reload_interval: 60,
+        client_auth: Some(ClientAuthSettings {
+            ca_certificate: temporary_file(&client_authority.serialize_pem().unwrap()),
+            identities: Default::default(),
+        }),
+    })
+    .await;
+    let (certificate, private_key) = issue(&client_authority, ExtendedKeyUsagePurpose::ClientAuth);
+    let identity =
+        reqwest::Identity::from_pkcs8_pem(certificate.as_bytes(), private_key.as_bytes()).unwrap();
+
+    let response = client_trusting(&authority)
+        .identity(identity)
+        .build()
+        .unwrap()
+        .get(format!("{}/admin/migrations", address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_renewed_certificate_is_served_without_a_restart() {
+    let old_authority = certificate_authority();
+    let (certificate, private_key) = issue(&old_authority, ExtendedKeyUsagePurpose::ServerAuth);
+    let certificate_path = temporary_file(&certificate);
+    let private_key_path = temporary_file(&private_key);
+    let address = spawn_https_app(TlsSettings {
+        certificate: certificate_path.clone(),
+        private_key: private_key_path.clone(),
+        reload_interval: 1,
+        client_auth: None,
+    })
+    .await;
+    let new_authority = certificate_authority();
+    let health_check = || async {
+        client_trusting(&new_authority)
+            .build()
+            .unwrap()
+            .get(format!("{}/health_check", address))
+            .send()
+            .await
+    };
+    assert!(health_check().await.is_err());
+
+    let (certificate, private_key) = issue(&new_authority, ExtendedKeyUsagePurpose::ServerAuth);
+    std::fs::write(&certificate_path, certificate).unwrap();
+    std::fs::write(&private_key_path, private_key).unwrap();
+
+    for _ in 0..50 {
+        if let Ok(response) = health_check().await {
+            assert_eq!(200, response.status().as_u16());
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The renewed certificate was not served.");
+}
@@ -0,0 +1,120 @@
+use crate::helpers::configure_database;
+use actix_web::{test, web, App, HttpResponse, HttpServer};
+use metaman::configuration::{get_configuration, OtlpSettings};
+use metaman::repository::{PostgresMarkingRepository, Storage};
+use metaman::request_id::RequestIdRootSpan;
+use metaman::routes::create_marking;
+use metaman::telemetry::{get_otlp_layer, get_subscriber};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use tracing_actix_web::TracingLogger;
+use tracing_subscriber::layer::SubscriberExt;
+use uuid::Uuid;
+
+type ExportedTraces = Arc<Mutex<Vec<Vec<u8>>>>;
+
+#[tokio::test]
+async fn request_spans_join_the_trace_of_the_caller() {
+    let exported = ExportedTraces::default();
+    let otlp = OtlpSettings {
+        endpoint: spawn_trace_collector(exported.clone()),
+    };
+    let subscriber = get_subscriber("test".into(), "info".into(), std::io::sink)
+        .with(get_otlp_layer("test".into(), &otlp).expect("Failed to set up trace export."));
+    // The subscriber only applies to this thread, so the request is served
+    // here rather than by the worker threads of a server.
+    let default_subscriber = tracing::subscriber::set_default(subscriber);
+
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    let storage = Storage::new(PostgresMarkingRepository::new(
+        configure_database(&configuration.database).await,
+    ));
+    let app = test::init_service(
+        App::new()
+            .wrap(TracingLogger::<RequestIdRootSpan>::new())
+            .app_data(web::Data::from(storage.markings))
+            .app_data(web::Data::from(storage.idempotency))
+            .route("/markings", web::post().to(create_marking)),
+    )
+    .await;
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    let request = test::TestRequest::post()
+        .uri("/markings")
+        .insert_header((
+            "traceparent",
+            format!("00-{}-00f067aa0ba902b7-01", trace_id),
+        ))
+        .set_json(serde_json::json!({
+            "name": "tlp_red",
+            "definition_type": "tlp",
+            "definition": "TLP Red"
+        }))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(201, response.status().as_u16());
+
+    // Shutting the tracer provider down exports the spans still batched.
+    drop(default_subscriber);
+    tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider)
+        .await
+        .unwrap();
+
+    // OTLP payloads are protobuf messages, in which ids are raw bytes and
+    // span names plain UTF-8.
+    let trace_id = (0..trace_id.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&trace_id[i..i + 2], 16).unwrap())
+        .collect::<Vec<_>>();
+    let exported: Vec<_> = exported
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|payload| contains(payload, &trace_id))
+        .cloned()
+        .collect();
+    for name in [
+        b"Adding a new marking".as_slice(),
+        b"Saving new marking in the database",
+    ] {
+        assert!(
+            exported.iter().any(|payload| contains(payload, name)),
+            "{} was not exported under the caller's trace.",
+            String::from_utf8_lossy(name)
+        );
+    }
+}
+
+/// Starts an OTLP/HTTP collector stub on its own thread, so that it keeps
+/// receiving while the test blocks on the export. Returns its endpoint.
+fn spawn_trace_collector(exported: ExportedTraces) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        actix_web::rt::System::new().block_on(async move {
+            HttpServer::new(move || {
+                let exported = exported.clone();
+                App::new().route(
+                    "/v1/traces",
+                    web::post().to(move |body: web::Bytes| {
+                        exported.lock().unwrap().push(body.to_vec());
+                        async { HttpResponse::Ok().finish() }
+                    }),
+                )
+            })
+            .workers(1)
+            .listen(listener)
+            .expect("Failed to bind address")
+            .run()
+            .await
+        })
+    });
+    endpoint
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
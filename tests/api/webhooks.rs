@@ -0,0 +1,429 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use metaman::configuration::get_configuration;
+use metaman::webhooks::sign;
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+const SECRET: &str = "a-secret-of-some-length";
+
+async fn register(app: &TestApp, server: &MockServer, events: &[&str]) -> String {
+    let response = app
+        .post_webhooks(serde_json::json!({
+            "url": format!("{}/hooks", server.uri()),
+            "events": events,
+            "secret": SECRET
+        }))
+        .await;
+    assert_eq!(201, response.status().as_u16());
+    let webhook: serde_json::Value = response.json().await.unwrap();
+    webhook["id"].as_str().unwrap().to_owned()
+}
+
+/// Waits for `server` to have received `count` requests.
+async fn received(server: &MockServer, count: usize) -> Vec<Request> {
+    for _ in 0..100 {
+        let requests = server.received_requests().await.unwrap();
+        if requests.len() >= count {
+            return requests;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("The webhook did not receive {} requests.", count);
+}
+
+fn header(request: &Request, name: &str) -> String {
+    request
+        .headers
+        .get(&name.into())
+        .unwrap()
+        .last()
+        .to_string()
+}
+
+/// Waits for webhook `id` to have `count` items in `resource`.
+async fn listed(app: &TestApp, id: &str, resource: &str, count: usize) -> Vec<serde_json::Value> {
+    for _ in 0..100 {
+        let items: Vec<serde_json::Value> = app
+            .get_webhook_resource(id, resource)
+            .await
+            .json()
+            .await
+            .unwrap();
+        if items.len() >= count {
+            return items;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("Webhook {} did not list {} {}.", id, count, resource);
+}
+
+#[tokio::test]
+async fn new_markings_are_posted_to_webhooks_with_a_signature() {
+    let app = spawn_app().await;
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/hooks"))
+        .respond_with(ResponseTemplate::new(204))
+        .mount(&server)
+        .await;
+    let id = register(&app, &server, &[]).await;
+
+    app.post_markings(
+        "{\"name\": \"acme\", \"definition_type\": \"statement\", \"definition\": \"Acme only\"}",
+    )
+    .await;
+
+    let request = &received(&server, 1).await[0];
+    assert_eq!(
+        sign(SECRET, &request.body),
+        header(request, "X-Metaman-Signature-256")
+    );
+    assert_eq!("marking.created", header(request, "X-Metaman-Event"));
+    let event: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!("marking.created", event["type"]);
+    assert_eq!("acme", event["marking"]["name"]);
+    assert_eq!(event["id"], header(request, "X-Metaman-Delivery"));
+
+    let deliveries = listed(&app, &id, "deliveries", 1).await;
+    assert_eq!(true, deliveries[0]["delivered"]);
+    assert_eq!(204, deliveries[0]["status_code"]);
+    assert_eq!(1, deliveries[0]["attempt"]);
+}
+
+#[tokio::test]
+async fn webhooks_only_receive_the_events_they_subscribe_to() {
+    let app = spawn_app().await;
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    register(&app, &server, &["marking.deleted"]).await;
+
+    let response = app
+        .post_markings(
+            "{\"name\": \"acme\", \"definition_type\": \"statement\", \"definition\": \"Acme only\"}",
+        )
+        .await;
+    let marking: serde_json::Value = response.json().await.unwrap();
+    let response = app
+        .api_client
+        .delete(format!(
+            "{}/markings/{}",
+            app.address,
+            marking["id"].as_str().unwrap()
+        ))
+        .header("If-Match", "\"1\"")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(204, response.status().as_u16());
+
+    let requests = received(&server, 1).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(1, server.received_requests().await.unwrap().len());
+    let event: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!("marking.deleted", event["type"]);
+    assert_eq!(marking["id"], event["marking"]["id"]);
+}
+
+#[tokio::test]
+async fn lifecycle_changes_are_delivered_to_webhooks() {
+    let app = spawn_app().await;
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    register(
+        &app,
+        &server,
+        &["marking.assigned", "marking.deprecated", "marking.revoked"],
+    )
+    .await;
+
+    let response = app
+        .post_markings(
+            "{\"name\": \"acme\", \"definition_type\": \"statement\", \"definition\": \"Acme only\"}",
+        )
+        .await;
+    let marking: serde_json::Value = response.json().await.unwrap();
+    let url = format!(
+        "{}/markings/{}",
+        app.address,
+        marking["id"].as_str().unwrap()
+    );
+    let object_ref = "indicator--8e2e2d2b-17d4-4cbf-938f-98ee46b3cd3f";
+    app.api_client
+        .put(format!("{}/assignments/{}", url, object_ref))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    for action in ["deprecate", "revoke"] {
+        let response = app
+            .api_client
+            .post(format!("{}/{}", url, action))
+            .header("If-Match", "*")
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    let requests = received(&server, 3).await;
+    let events: Vec<serde_json::Value> = requests
+        .iter()
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect();
+    assert_eq!("marking.assigned", events[0]["type"]);
+    assert_eq!(object_ref, events[0]["object_ref"]);
+    assert_eq!("marking.deprecated", events[1]["type"]);
+    assert_eq!("deprecated", events[1]["marking"]["status"]);
+    assert_eq!(None, events[1].get("object_ref"));
+    assert_eq!("marking.revoked", events[2]["type"]);
+    assert_eq!("revoked", events[2]["marking"]["status"]);
+}
+
+#[tokio::test]
+async fn new_webhooks_only_receive_the_events_that_follow() {
+    let app = spawn_app().await;
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    app.post_markings(
+        "{\"name\": \"acme\", \"definition_type\": \"statement\", \"definition\": \"Acme only\"}",
+    )
+    .await;
+    register(&app, &server, &[]).await;
+
+    app.post_markings(
+        "{\"name\": \"umbrella\", \"definition_type\": \"statement\", \"definition\": \"Umbrella only\"}",
+    )
+    .await;
+
+    let requests = received(&server, 1).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(1, server.received_requests().await.unwrap().len());
+    let event: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!("umbrella", event["marking"]["name"]);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried() {
+    let app = spawn_app().await;
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let id = register(&app, &server, &[]).await;
+
+    app.post_markings(
+        "{\"name\": \"acme\", \"definition_type\": \"statement\", \"definition\": \"Acme only\"}",
+    )
+    .await;
+
+    let requests = received(&server, 2).await;
+    assert_eq!(
+        header(&requests[0], "X-Metaman-Delivery"),
+        header(&requests[1], "X-Metaman-Delivery")
+    );
+    let deliveries = listed(&app, &id, "deliveries", 2).await;
+    assert_eq!(2, deliveries[0]["attempt"]);
+    assert_eq!(true, deliveries[0]["delivered"]);
+    assert_eq!(1, deliveries[1]["attempt"]);
+    assert_eq!(false, deliveries[1]["delivered"]);
+    assert_eq!(503, deliveries[1]["status_code"]);
+    let dead_letters: Vec<serde_json::Value> = app
+        .get_webhook_resource(&id, "dead_letters")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert!(dead_letters.is_empty());
+}
+
+#[tokio::test]
+async fn a_receiver_that_is_down_holds_up_no_other_webhook() {
+    let app = spawn_app().await;
+    let down = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(503).set_delay(Duration::from_secs(1)))
+        .mount(&down)
+        .await;
+    let up = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&up)
+        .await;
+    register(&app, &down, &[]).await;
+    register(&app, &up, &[]).await;
+
+    for body in [
+        r#"{"name": "acme", "definition_type": "statement", "definition": "Acme"}"#,
+        r#"{"name": "globex", "definition_type": "statement", "definition": "Globex"}"#,
+        r#"{"name": "initech", "definition_type": "statement", "definition": "Initech"}"#,
+    ] {
+        app.post_markings(body).await;
+    }
+
+    // Each event takes the receiver that is down 3 attempts of a second.
+    received(&up, 3).await;
+}
+
+#[tokio::test]
+async fn events_no_attempt_could_deliver_are_dead_lettered() {
+    let app = spawn_app().await;
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(3)
+        .mount(&server)
+        .await;
+    let id = register(&app, &server, &[]).await;
+
+    app.post_markings(
+        "{\"name\": \"acme\", \"definition_type\": \"statement\", \"definition\": \"Acme only\"}",
+    )
+    .await;
+
+    let dead_letters = listed(&app, &id, "dead_letters", 1).await;
+    assert_eq!(3, dead_letters[0]["attempts"]);
+    assert_eq!("marking.created", dead_letters[0]["event_type"]);
+    assert_eq!("acme", dead_letters[0]["payload"]["marking"]["name"]);
+    let deliveries = listed(&app, &id, "deliveries", 3).await;
+    assert!(deliveries
+        .iter()
+        .all(|delivery| delivery["delivered"] == false));
+}
+
+#[tokio::test]
+async fn webhooks_are_served_without_their_secret() {
+    let app = spawn_app().await;
+    let server = MockServer::start().await;
+    let id = register(&app, &server, &["marking.updated"]).await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/webhooks/{}", app.address, id))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let webhook: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(serde_json::json!(["marking.updated"]), webhook["events"]);
+    assert!(webhook.get("secret").is_none());
+}
+
+#[tokio::test]
+async fn deleted_webhooks_are_gone() {
+    let app = spawn_app().await;
+    let server = MockServer::start().await;
+    let id = register(&app, &server, &[]).await;
+
+    let delete = || {
+        app.api_client
+            .delete(format!("{}/webhooks/{}", app.address, id))
+            .send()
+    };
+    assert_eq!(204, delete().await.unwrap().status().as_u16());
+    assert_eq!(404, delete().await.unwrap().status().as_u16());
+    let response = app.get_webhook_resource(&id, "deliveries").await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn invalid_webhooks_are_rejected() {
+    let app = spawn_app().await;
+    let test_cases = [
+        (
+            serde_json::json!({ "url": "not a url", "secret": SECRET }),
+            "an invalid URL",
+        ),
+        (
+            serde_json::json!({ "url": "ftp://tip.example.com/", "secret": SECRET }),
+            "a URL other than HTTP",
+        ),
+        (
+            serde_json::json!({ "url": "https://tip.example.com/", "secret": "short" }),
+            "a short secret",
+        ),
+        (
+            serde_json::json!({
+                "url": "https://tip.example.com/",
+                "events": ["marking.renamed"],
+                "secret": SECRET
+            }),
+            "an unknown event",
+        ),
+        (
+            serde_json::json!({ "url": "https://tip.example.com/" }),
+            "no secret",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_webhooks(body).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn webhooks_targeting_private_addresses_are_rejected() {
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.application.open_operator_routes = true;
+    let address = spawn_app_with(configuration).await;
+
+    for url in [
+        "http://127.0.0.1:8080/hooks",
+        "http://10.0.0.1/hooks",
+        "http://169.254.169.254/latest/meta-data/",
+        "http://[::1]/hooks",
+        "http://[::ffff:127.0.0.1]/hooks",
+        "http://localhost/hooks",
+    ] {
+        let response = reqwest::Client::new()
+            .post(format!("{}/webhooks", address))
+            .json(&serde_json::json!({ "url": url, "secret": SECRET }))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(400, response.status().as_u16(), "{} was accepted.", url);
+    }
+}
+
+#[tokio::test]
+async fn webhooks_are_managed_by_operators_only() {
+    let configuration = get_configuration().expect("Failed to read configuration");
+    let address = spawn_app_with(configuration).await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{}/webhooks", address))
+        .json(&serde_json::json!({ "url": "https://tip.example.com/", "secret": SECRET }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(403, response.status().as_u16());
+
+    let response = client
+        .get(format!("{}/webhooks", address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(403, response.status().as_u16());
+}
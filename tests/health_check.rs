@@ -1,193 +0,0 @@
-use metaman::configuration::{get_configuration, DatabaseSettings};
-use metaman::startup::run;
-use metaman::telemetry::{get_subscriber, init_subscriber};
-use once_cell::sync::Lazy;
-use sqlx::{Connection, Executor, PgConnection, PgPool};
-use std::net::TcpListener;
-use uuid::Uuid;
-
-static TRACING: Lazy<()> = Lazy::new(|| {
-    let default_filter_level = "info".to_string();
-    let subscriber_name = "test".to_string();
-    if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
-        init_subscriber(subscriber);
-    } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
-        init_subscriber(subscriber);
-    }
-});
-
-pub struct TestApp {
-    pub address: String,
-    pub db_pool: PgPool,
-}
-
-async fn spawn_app() -> TestApp {
-    Lazy::force(&TRACING);
-
-    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
-    let port = listener.local_addr().unwrap().port();
-    let address = format!("http://127.0.0.1:{}", port);
-
-    let mut configuration = get_configuration().expect("Failed to read configuration");
-    configuration.database.database_name = Uuid::new_v4().to_string();
-
-    let connection_pool = configure_database(&configuration.database).await;
-    let server = run(listener, connection_pool.clone()).expect("Failed to bind address");
-
-    let _ = tokio::spawn(server);
-
-    TestApp {
-        address,
-        db_pool: connection_pool,
-    }
-}
-
-pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
-    let mut connection = PgConnection::connect_with(&config.without_db())
-        .await
-        .expect("Failed to connect to Postgres.");
-    connection
-        .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
-        .await
-        .expect("Failed to create database.");
-
-    let connection_pool = PgPool::connect_with(config.with_db())
-        .await
-        .expect("Failed to connect to Postgres.");
-    sqlx::migrate!("./migrations")
-        .run(&connection_pool)
-        .await
-        .expect("failed to migrate the database.");
-    connection_pool
-}
-
-#[tokio::test]
-async fn health_check_works() {
-    let app = spawn_app().await;
-    let client = reqwest::Client::new();
-
-    let response = client
-        .get(format!("{}/health_check", &app.address))
-        .send()
-        .await
-        .expect("Failed to execute request.");
-
-    assert!(response.status().is_success());
-    assert_eq!(Some(0), response.content_length());
-}
-
-#[tokio::test]
-async fn create_marking_returns_a_201_for_valid_form_data() {
-    let app = spawn_app().await;
-    let client = reqwest::Client::new();
-
-    let body = "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}";
-    let response = client
-        .post(&format!("{}/markings", &app.address))
-        .header("Content-Type", "application/json")
-        .body(body)
-        .send()
-        .await
-        .expect("Failed to execute request.");
-
-    assert_eq!(201, response.status().as_u16());
-
-    let saved = sqlx::query!("SELECT name, definition_type, definition FROM markings",)
-        .fetch_one(&app.db_pool)
-        .await
-        .expect("Failed to fetch saved marking.");
-
-    assert_eq!(saved.name, "tlp_red");
-    assert_eq!(saved.definition_type, "tlp");
-    assert_eq!(saved.definition, "TLP Red");
-}
-
-#[tokio::test]
-async fn create_marking_returns_a_400_when_fields_are_present_but_invalid() {
-    let app = spawn_app().await;
-    let client = reqwest::Client::new();
-    let test_cases = vec![
-        (
-            "{\"name\": \"\", \"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}",
-            "empty name",
-        ),
-        (
-            "{\"name\": \"tlp_red\", \"definition_type\": \"\", \"definition\": \"TLP Red\"}",
-            "empty definition type",
-        ),
-        (
-            "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\", \"definition\": \"\"}",
-            "empty definition",
-        ),
-    ];
-
-    for (body, description) in test_cases {
-        let response = client
-            .post(&format!("{}/markings", &app.address))
-            .header("Content-Type", "application/json")
-            .body(body)
-            .send()
-            .await
-            .expect("Failed to execute request.");
-
-        assert_eq!(
-            400,
-            response.status().as_u16(),
-            "The API did not return a 400 when the payload had an {}.",
-            description
-        )
-    }
-}
-
-#[tokio::test]
-async fn create_marking_returns_a_400_when_data_is_missing() {
-    let app = spawn_app().await;
-    let client = reqwest::Client::new();
-
-    let test_cases = vec![
-        (
-            "{\"name\": \"tlp_red\", \"definition_type\": \"tlp\"}",
-            "missing the definition",
-        ),
-        (
-            "{\"name\": \"tlp_red\", \"definition\": \"TLP Red\"}",
-            "missing the definition type",
-        ),
-        (
-            "{\"definition_type\": \"tlp\", \"definition\": \"TLP Red\"}",
-            "missing the name",
-        ),
-        (
-            "{\"name\": \"tlp_red\"}",
-            "missing the definition type and the definition",
-        ),
-        (
-            "{\"definition_type\": \"tlp\",}",
-            "missing the name and the definition",
-        ),
-        (
-            "{\"definition\": \"TLP Red\"}",
-            "missing the name and the definition type",
-        ),
-        ("{}", "missing the name, definition, and definition type"),
-    ];
-
-    for (invalid_body, error_message) in test_cases {
-        let response = client
-            .post(&format!("{}/markings", &app.address))
-            .header("Content-Type", "application/json")
-            .body(invalid_body)
-            .send()
-            .await
-            .expect("Failed to execute request.");
-
-        assert_eq!(
-            400,
-            response.status().as_u16(),
-            "The API did not fail with 400 Bad Request when the payload was {}",
-            error_message
-        );
-    }
-}